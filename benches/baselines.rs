@@ -0,0 +1,172 @@
+//! k-word updates with `cas_n` next to the usual ways of updating several
+//! words at once: a `parking_lot::Mutex` over the words and a seqlock. Run
+//! with `--features htm` to get `cas_n` trying a hardware transaction first
+//! where the CPU has RTM.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mw_cas::{cas_n, Atomic};
+use parking_lot::Mutex;
+use rand::{rngs::SmallRng, thread_rng, Rng, SeedableRng};
+use std::{
+    array,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
+
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+const THREADS: usize = 8;
+const PER_THREAD_UPDATES: usize = 20_000;
+
+// A group of k words every update adds 1 to.
+trait Group<const K: usize>: Sync {
+    fn new() -> Self;
+    fn increment(&self);
+    fn read(&self) -> [u64; K];
+}
+
+struct CasN<const K: usize>([Atomic<usize>; K]);
+
+impl<const K: usize> Group<K> for CasN<K> {
+    fn new() -> Self {
+        Self(array::from_fn(|_| Atomic::new(0)))
+    }
+
+    fn increment(&self) {
+        let addresses: [&Atomic<usize>; K] = array::from_fn(|i| &self.0[i]);
+        loop {
+            let expected: [usize; K] = array::from_fn(|i| self.0[i].load());
+            let new = expected.map(|value| value + 1);
+            if unsafe { cas_n(&addresses, &expected, &new) } {
+                return;
+            }
+        }
+    }
+
+    // separate loads can straddle an update, the identity `cas_n` confirms
+    // them
+    fn read(&self) -> [u64; K] {
+        let addresses: [&Atomic<usize>; K] = array::from_fn(|i| &self.0[i]);
+        loop {
+            let values: [usize; K] = array::from_fn(|i| self.0[i].load());
+            if unsafe { cas_n(&addresses, &values, &values) } {
+                return values.map(|value| value as u64);
+            }
+        }
+    }
+}
+
+struct Locked<const K: usize>(Mutex<[u64; K]>);
+
+impl<const K: usize> Group<K> for Locked<K> {
+    fn new() -> Self {
+        Self(Mutex::new([0; K]))
+    }
+
+    fn increment(&self) {
+        for value in self.0.lock().iter_mut() {
+            *value += 1;
+        }
+    }
+
+    fn read(&self) -> [u64; K] {
+        *self.0.lock()
+    }
+}
+
+// Writers take the sequence number odd with a CAS, readers retry while it's
+// odd or changed under them.
+struct SeqLock<const K: usize> {
+    seq: AtomicU64,
+    values: [AtomicU64; K],
+}
+
+impl<const K: usize> Group<K> for SeqLock<K> {
+    fn new() -> Self {
+        Self {
+            seq: AtomicU64::new(0),
+            values: array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    fn increment(&self) {
+        let seq = loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            if seq % 2 == 0
+                && self
+                    .seq
+                    .compare_exchange_weak(
+                        seq,
+                        seq + 1,
+                        Ordering::Acquire,
+                        Ordering::Relaxed,
+                    )
+                    .is_ok()
+            {
+                break seq;
+            }
+            std::hint::spin_loop();
+        };
+        for value in &self.values {
+            value.store(value.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+        }
+        self.seq.store(seq + 2, Ordering::Release);
+    }
+
+    fn read(&self) -> [u64; K] {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            let values = array::from_fn(|i| self.values[i].load(Ordering::Relaxed));
+            std::sync::atomic::fence(Ordering::Acquire);
+            if seq % 2 == 0 && self.seq.load(Ordering::Relaxed) == seq {
+                return values;
+            }
+        }
+    }
+}
+
+// Every thread increments random groups and reads one group after every
+// update, all words of a group have to be equal.
+fn run<G: Group<K>, const K: usize>(groups: &[G]) {
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                let mut rng = SmallRng::from_rng(thread_rng()).unwrap();
+                for _ in 0..PER_THREAD_UPDATES {
+                    groups[rng.gen_range(0, groups.len())].increment();
+                    let values = groups[rng.gen_range(0, groups.len())].read();
+                    assert!(values.iter().all(|&value| value == values[0]));
+                }
+            });
+        }
+    });
+}
+
+fn bench_k<const K: usize>(c: &mut Criterion) {
+    let mut group = c.benchmark_group(format!("baselines/k={}", K));
+    group.throughput(Throughput::Elements((THREADS * PER_THREAD_UPDATES) as u64));
+    group.sample_size(10);
+    // a single group has every thread fight over the same words
+    for &groups in &[1, 1024] {
+        let casn: Vec<CasN<K>> = (0..groups).map(|_| Group::new()).collect();
+        let locked: Vec<Locked<K>> = (0..groups).map(|_| Group::new()).collect();
+        let seqlock: Vec<SeqLock<K>> = (0..groups).map(|_| Group::new()).collect();
+        let name = if cfg!(feature = "htm") {
+            "cas_n+htm"
+        } else {
+            "cas_n"
+        };
+        group.bench_function(BenchmarkId::new(name, groups), |b| b.iter(|| run(&casn)));
+        group.bench_function(BenchmarkId::new("mutex", groups), |b| {
+            b.iter(|| run(&locked))
+        });
+        group.bench_function(BenchmarkId::new("seqlock", groups), |b| {
+            b.iter(|| run(&seqlock))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(baselines, bench_k::<2>, bench_k::<4>);
+criterion_main!(baselines);
@@ -1,7 +1,9 @@
 #![allow(unused_imports)]
 #![allow(dead_code)]
 
-use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use criterion::{
+    criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
 use crossbeam_epoch::{self, pin, unprotected, Owned, Shared};
 use mw_cas::{cas2, Atomic, CASN};
 use rand::{prelude::SliceRandom, rngs::SmallRng, thread_rng, Rng, SeedableRng};
@@ -13,6 +15,42 @@ use std::sync::{
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+// With `--features stats`, prints how many of the operations of a benchmark
+// succeeded and how often they helped others, throughput alone doesn't tell
+// useful work from retry storms.
+struct StatsReport {
+    #[cfg(feature = "stats")]
+    before: mw_cas::stats::Stats,
+}
+
+impl StatsReport {
+    fn start() -> Self {
+        Self {
+            #[cfg(feature = "stats")]
+            before: mw_cas::stats::snapshot(),
+        }
+    }
+
+    #[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+    fn print(self, name: &str) {
+        #[cfg(feature = "stats")]
+        {
+            let stats = mw_cas::stats::snapshot().since(self.before);
+            // skipped by a filter
+            if stats.ops == 0 {
+                return;
+            }
+            println!(
+                "{}: {:.1}% of {} operations succeeded, {:.3} helps per operation",
+                name,
+                100.0 * stats.success_ratio(),
+                stats.ops,
+                stats.helps_per_op()
+            );
+        }
+    }
+}
+
 fn casn_sum(
     atoms: Arc<Vec<Atomic<usize>>>,
     threads: usize,
@@ -78,6 +116,9 @@ fn cas2_sum_alloc(
                 for _ in 0..per_thread_attempts {
                     let first = atoms.choose(&mut rng).unwrap();
                     let second = atoms.choose(&mut rng).unwrap();
+                    if std::ptr::eq(first, second) {
+                        continue;
+                    }
 
                     let g = pin();
                     let first_current = Shared::from(first.load());
@@ -137,6 +178,7 @@ fn cas2_benchmark(c: &mut Criterion) {
     group.throughput(Throughput::Elements(threads * per_thread_attempts));
 
 
+    let report = StatsReport::start();
     group.bench_function("cas2_sum_alloc", |b| {
         b.iter_batched(
             || {
@@ -154,8 +196,10 @@ fn cas2_benchmark(c: &mut Criterion) {
             BatchSize::SmallInput,
         )
     });
+    report.print("cas2/cas2_sum_alloc");
 
     for n in 1..=4 {
+        let report = StatsReport::start();
         group.bench_function(format!("casn_sum: {}", n), |b| {
             b.iter_batched(
                 || Arc::new((0..24000).map(|_| Atomic::new(0)).collect::<Vec<_>>()),
@@ -165,11 +209,178 @@ fn cas2_benchmark(c: &mut Criterion) {
                 BatchSize::SmallInput,
             )
         });
+        report.print(&format!("cas2/casn_sum: {}", n));
+    }
+
+
+    group.finish();
+}
+
+// `cas_n` over k words out of a pool of cells, fewer cells mean more conflicts.
+// A single `cas_n` covers up to 4 words, so k stops there.
+fn cas_n_arity_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cas_n_arity");
+    let threads = 24;
+    let per_thread_attempts = 20_000;
+    group.throughput(Throughput::Elements(threads * per_thread_attempts));
+    group.sample_size(10);
+
+    for &k in &[2, 4] {
+        for &cells in &[16, 1024, 24000] {
+            let mut attempts = 0;
+            let mut succeeded = 0;
+            let report = StatsReport::start();
+            group.bench_with_input(
+                BenchmarkId::new(format!("k={}", k), cells),
+                &cells,
+                |b, &cells| {
+                    b.iter_batched(
+                        || {
+                            Arc::new(
+                                (0..cells).map(|_| Atomic::new(0)).collect::<Vec<_>>(),
+                            )
+                        },
+                        |atoms| {
+                            let atoms = casn_sum(
+                                atoms,
+                                threads as usize,
+                                k,
+                                per_thread_attempts as usize,
+                            );
+                            // every successful `cas_n` added 1 to k cells
+                            let sum: usize = atoms.iter().map(|a| a.load()).sum();
+                            attempts += threads * per_thread_attempts;
+                            succeeded += (sum / k) as u64;
+                            atoms
+                        },
+                        BatchSize::SmallInput,
+                    )
+                },
+            );
+            report.print(&format!("cas_n_arity/k={}/{}", k, cells));
+            // skipped by a filter
+            if attempts > 0 {
+                println!(
+                    "cas_n_arity/k={}/{}: {:.1}% of the attempts succeeded",
+                    k,
+                    cells,
+                    100.0 * succeeded as f64 / attempts as f64
+                );
+            }
+        }
     }
 
+    group.finish();
+}
+
+// Cumulative Zipf distribution over `n` cells with exponent `s`, cell 0 is the
+// hottest one.
+fn zipf_cdf(n: usize, s: f64) -> Vec<f64> {
+    let weights: Vec<f64> = (1..=n).map(|rank| 1.0 / (rank as f64).powf(s)).collect();
+    let total: f64 = weights.iter().sum();
+    weights
+        .iter()
+        .scan(0.0, |acc, w| {
+            *acc += w / total;
+            Some(*acc)
+        })
+        .collect()
+}
+
+// picks a cell by the distribution, uniformly without one
+fn pick(cdf: Option<&[f64]>, cells: usize, rng: &mut SmallRng) -> usize {
+    match cdf {
+        Some(cdf) => {
+            let x: f64 = rng.gen();
+            cdf.partition_point(|&p| p < x).min(cells - 1)
+        },
+        None => rng.gen_range(0, cells),
+    }
+}
+
+// Every thread loads cells and increments a pair of them with `cas2` after
+// every `loads_per_write` loads. Returns the number of successful `cas2`s.
+fn load_mostly(
+    atoms: &[Atomic<usize>],
+    cdf: Option<&[f64]>,
+    threads: usize,
+    loads_per_write: usize,
+    per_thread_attempts: usize,
+) -> u64 {
+    std::thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(move || {
+                    let mut rng = SmallRng::from_rng(thread_rng()).unwrap();
+                    let mut succeeded = 0;
+                    for attempt in 0..per_thread_attempts {
+                        let first = &atoms[pick(cdf, atoms.len(), &mut rng)];
+                        if attempt % (loads_per_write + 1) != loads_per_write {
+                            criterion::black_box(first.load());
+                            continue;
+                        }
+                        let second = &atoms[pick(cdf, atoms.len(), &mut rng)];
+                        if std::ptr::eq(first, second) {
+                            continue;
+                        }
+                        let (v0, v1) = (first.load(), second.load());
+                        if unsafe { cas2(first, second, v0, v1, v0 + 1, v1 + 1) } {
+                            succeeded += 1;
+                        }
+                    }
+                    succeeded
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    })
+}
+
+// Loads with occasional `cas2`s, over uniformly chosen cells and over cells
+// chosen by a Zipf distribution, where the loads keep running into the
+// descriptors of the writes to the hot cells and help them.
+fn contention_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contention");
+    let threads = 24;
+    let per_thread_attempts = 20_000;
+    let cells = 1024;
+    group.throughput(Throughput::Elements(threads * per_thread_attempts));
+    group.sample_size(10);
+
+    let zipf = zipf_cdf(cells, 1.0);
+    for &(name, cdf) in &[("uniform", None), ("zipf", Some(&zipf[..]))] {
+        for &loads_per_write in &[9, 99] {
+            let atoms: Vec<_> = (0..cells).map(|_| Atomic::new(0)).collect();
+            let report = StatsReport::start();
+            group.bench_with_input(
+                BenchmarkId::new(name, format!("{} loads per cas2", loads_per_write)),
+                &loads_per_write,
+                |b, &loads_per_write| {
+                    b.iter(|| {
+                        load_mostly(
+                            &atoms,
+                            cdf,
+                            threads as usize,
+                            loads_per_write,
+                            per_thread_attempts as usize,
+                        )
+                    })
+                },
+            );
+            report.print(&format!(
+                "contention/{}/{} loads per cas2",
+                name, loads_per_write
+            ));
+        }
+    }
 
     group.finish();
 }
 
-criterion_group!(benches, cas2_benchmark);
+criterion_group!(
+    benches,
+    cas2_benchmark,
+    cas_n_arity_benchmark,
+    contention_benchmark
+);
 criterion_main!(benches);
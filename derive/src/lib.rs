@@ -0,0 +1,116 @@
+//! `#[derive(MwAtomicFields)]` for structs of `mw_cas::Atomic` fields, see
+//! the re-export in `mw_cas`.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Fields, GenericArgument, PathArguments,
+    Type,
+};
+
+// the number of words one `cas_n` takes
+const MAX_FIELDS: usize = 4;
+
+/// Generates a `<Name>Snapshot` struct with the plain values of the fields
+/// and, on the struct, `snapshot` reading all fields at once and `cas_all`
+/// updating all of them with one `cas_n`.
+#[proc_macro_derive(MwAtomicFields)]
+pub fn derive_mw_atomic_fields(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: &DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(Error::new_spanned(input, "expected named fields")),
+        },
+        _ => return Err(Error::new_spanned(input, "expected a struct")),
+    };
+    if fields.is_empty() || fields.len() > MAX_FIELDS {
+        return Err(Error::new(
+            Span::call_site(),
+            format!(
+                "expected 1 to {} fields, one cas_n updates all of them",
+                MAX_FIELDS
+            ),
+        ));
+    }
+    if !input.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &input.generics,
+            "generics aren't supported",
+        ));
+    }
+    let names: Vec<_> = fields
+        .iter()
+        .map(|field| field.ident.as_ref().unwrap())
+        .collect();
+    let types = fields
+        .iter()
+        .map(|field| atomic_value_type(&field.ty))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let snapshot = format_ident!("{}Snapshot", name);
+    let doc = format!("The values of the fields of a `{}`.", name);
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #vis struct #snapshot {
+            #(pub #names: #types,)*
+        }
+
+        impl #name {
+            /// The values of all fields at one point in time, as far as
+            /// values tell: a field that changed and changed back between
+            /// two reads counts as unchanged.
+            pub fn snapshot(&self) -> #snapshot {
+                let mut snapshot = #snapshot {
+                    #(#names: self.#names.load(),)*
+                };
+                loop {
+                    // nothing moved between the two passes, so the fields all
+                    // held these values when the first one ended
+                    let again = #snapshot {
+                        #(#names: self.#names.load(),)*
+                    };
+                    if again == snapshot {
+                        return snapshot;
+                    }
+                    snapshot = again;
+                }
+            }
+
+            /// Sets every field to its value in `new` if all of them hold
+            /// their value in `expected`, with one `cas_n`.
+            #[allow(clippy::missing_safety_doc)]
+            pub unsafe fn cas_all(&self, expected: #snapshot, new: #snapshot) -> bool {
+                let mut cas_n = ::mw_cas::CASN::new();
+                #(cas_n.add_unchecked(&self.#names, expected.#names, new.#names);)*
+                cas_n.exec()
+            }
+        }
+    })
+}
+
+// `T` of a field of type `Atomic<T>`
+fn atomic_value_type(ty: &Type) -> Result<&Type, Error> {
+    if let Type::Path(path) = ty {
+        let last = path.path.segments.last().unwrap();
+        if last.ident == "Atomic" {
+            if let PathArguments::AngleBracketed(args) = &last.arguments {
+                if let Some(GenericArgument::Type(ty)) = args.args.first() {
+                    return Ok(ty);
+                }
+            }
+        }
+    }
+    Err(Error::new_spanned(ty, "expected an `Atomic<_>` field"))
+}
@@ -0,0 +1,161 @@
+//! Soak test: threads move amounts between random cells with `cas_n` for as
+//! long as asked to, in rounds. After every round the cells have to add up to
+//! the same total as at the start and the resident set of the process must
+//! not have grown past a limit since the first round.
+//!
+//! ```text
+//! cargo run --release --example stress -- --threads 16 --cells 64 --arity 4 \
+//!     --hours 8 --round-secs 60 --max-rss-growth-mb 64
+//! ```
+
+use mw_cas::{cas_n, Atomic};
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{
+    env, fs, process,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+const AMOUNT_PER_CELL: usize = 1_000;
+
+struct Config {
+    threads: usize,
+    cells: usize,
+    arity: usize,
+    duration: Duration,
+    round: Duration,
+    max_rss_growth_kb: u64,
+}
+
+impl Config {
+    fn from_args() -> Self {
+        let mut config = Config {
+            threads: thread::available_parallelism().map_or(4, |n| n.get()),
+            cells: 64,
+            arity: 4,
+            duration: Duration::from_secs(60),
+            round: Duration::from_secs(10),
+            max_rss_growth_kb: 64 * 1024,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(flag) = args.next() {
+            let value: f64 = match args.next().map(|value| value.parse()) {
+                Some(Ok(value)) => value,
+                _ => usage(&format!("{} needs a number", flag)),
+            };
+            match flag.as_str() {
+                "--threads" => config.threads = value as usize,
+                "--cells" => config.cells = value as usize,
+                "--arity" => config.arity = value as usize,
+                "--secs" => config.duration = Duration::from_secs_f64(value),
+                "--hours" => config.duration = Duration::from_secs_f64(value * 3600.0),
+                "--round-secs" => config.round = Duration::from_secs_f64(value),
+                "--max-rss-growth-mb" => config.max_rss_growth_kb = value as u64 * 1024,
+                _ => usage(&format!("unknown flag {}", flag)),
+            }
+        }
+        // a transfer needs two cells, a `cas_n` covers up to 4
+        if !(2..=4).contains(&config.arity) || config.arity > config.cells {
+            usage("--arity has to be between 2 and 4 and at most --cells");
+        }
+        config
+    }
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("{}", error);
+    eprintln!(
+        "usage: stress [--threads N] [--cells N] [--arity 2..=4] [--secs S | --hours H] \
+         [--round-secs S] [--max-rss-growth-mb MB]"
+    );
+    process::exit(2);
+}
+
+// resident set in kB, where the platform tells
+fn rss_kb() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+// moves a random amount from one of the chosen cells to another one
+fn transfer(cells: &[Atomic<usize>], arity: usize, rng: &mut SmallRng) -> bool {
+    let mut chosen: Vec<_> = cells.choose_multiple(rng, arity).collect();
+    // addresses in a `cas_n` have to be unique, the order doesn't matter
+    chosen.sort_by_key(|cell| *cell as *const Atomic<usize>);
+    let expected: Vec<_> = chosen.iter().map(|cell| cell.load()).collect();
+    let mut new = expected.clone();
+    let from = rng.gen_range(0, arity);
+    let to = (from + rng.gen_range(1, arity)) % arity;
+    let amount = rng.gen_range(0, new[from] + 1);
+    new[from] -= amount;
+    new[to] += amount;
+    unsafe { cas_n(&chosen, &expected, &new) }
+}
+
+fn main() {
+    let config = Config::from_args();
+    let total = config.cells * AMOUNT_PER_CELL;
+    let cells: Vec<_> = (0..config.cells)
+        .map(|_| Atomic::new(AMOUNT_PER_CELL))
+        .collect();
+    let (succeeded, failed) = (AtomicU64::new(0), AtomicU64::new(0));
+    let start = Instant::now();
+    let mut baseline_rss = None;
+    let mut round = 0;
+    while start.elapsed() < config.duration {
+        round += 1;
+        let stop = AtomicBool::new(false);
+        // new threads every round, so thread ids and descriptors get reused
+        thread::scope(|s| {
+            for t in 0..config.threads {
+                let (cells, stop, succeeded, failed) =
+                    (&cells, &stop, &succeeded, &failed);
+                let arity = config.arity;
+                s.spawn(move || {
+                    let mut rng = SmallRng::seed_from_u64(round * 1_000 + t as u64);
+                    while !stop.load(Ordering::Relaxed) {
+                        let counter = if transfer(cells, arity, &mut rng) {
+                            succeeded
+                        } else {
+                            failed
+                        };
+                        counter.fetch_add(1, Ordering::Relaxed);
+                    }
+                });
+            }
+            thread::sleep(
+                config
+                    .round
+                    .min(config.duration.saturating_sub(start.elapsed())),
+            );
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        let sum: usize = cells.iter().map(Atomic::load).sum();
+        let rss = rss_kb();
+        println!(
+            "round {} after {:?}: {} succeeded, {} failed, sum {}, rss {}",
+            round,
+            start.elapsed(),
+            succeeded.load(Ordering::Relaxed),
+            failed.load(Ordering::Relaxed),
+            sum,
+            rss.map_or("unknown".to_string(), |rss| format!("{} kB", rss)),
+        );
+        if sum != total {
+            eprintln!("the cells add up to {} instead of {}", sum, total);
+            process::exit(1);
+        }
+        // the first round allocates the descriptor tables and thread locals
+        match (baseline_rss, rss) {
+            (None, _) => baseline_rss = rss,
+            (Some(baseline), Some(rss)) if rss > baseline + config.max_rss_growth_kb => {
+                eprintln!("rss grew from {} kB to {} kB", baseline, rss);
+                process::exit(1);
+            },
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! Runs the operations the input encodes on a few threads over a small array
+//! of cells. Every `cas_n` moves an amount between the cells it covers, so
+//! the cells add up to the same total in every snapshot and at the end.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use mw_cas::{cas_n, register_current_thread, Atomic};
+use std::thread;
+
+// as many as one `cas_n` can cover
+const CELLS: usize = 4;
+const THREADS: usize = 3;
+const TOTAL: usize = 1000;
+
+fuzz_target!(|data: &[u8]| {
+    let cells: Vec<_> = (0..CELLS).map(|_| Atomic::new(TOTAL / CELLS)).collect();
+    thread::scope(|s| {
+        for ops in data.chunks(data.len() / THREADS + 1) {
+            let cells = &cells;
+            s.spawn(move || run(cells, ops));
+        }
+    });
+    assert_eq!(cells.iter().map(Atomic::load).sum::<usize>(), TOTAL);
+});
+
+fn run(cells: &[Atomic<usize>], ops: &[u8]) {
+    let mut ops = ops.iter().copied();
+    while let Some(op) = ops.next() {
+        let arg = op as usize / 4;
+        match op % 4 {
+            // gives the id back, the next operation registers the thread again,
+            // maybe with the descriptors another thread used in the meantime
+            0 => drop(register_current_thread()),
+            1 => assert!(cells[arg % CELLS].load() <= TOTAL),
+            2 => transfer(cells, arg, ops.next().unwrap_or(0) as usize),
+            _ => assert_eq!(snapshot(cells).iter().sum::<usize>(), TOTAL),
+        }
+    }
+}
+
+// moves up to `amount` from the first to the last of the cells set in `mask`
+fn transfer(cells: &[Atomic<usize>], mask: usize, amount: usize) {
+    let addresses: Vec<_> = (0..CELLS)
+        .filter(|i| mask & (1 << i) != 0)
+        .map(|i| &cells[i])
+        .collect();
+    if addresses.is_empty() {
+        return;
+    }
+    let expected: Vec<_> = addresses.iter().map(|cell| cell.load()).collect();
+    let mut new = expected.clone();
+    let moved = amount.min(new[0]);
+    new[0] -= moved;
+    *new.last_mut().unwrap() += moved;
+    // losing to another thread is fine, the total stays the same either way
+    let _ = unsafe { cas_n(&addresses, &expected, &new) };
+}
+
+// the values of all cells at one point in time
+fn snapshot(cells: &[Atomic<usize>]) -> Vec<usize> {
+    let addresses: Vec<_> = cells.iter().collect();
+    loop {
+        let values: Vec<_> = cells.iter().map(Atomic::load).collect();
+        if unsafe { cas_n(&addresses, &values, &values) } {
+            return values;
+        }
+    }
+}
@@ -0,0 +1,54 @@
+/* C interface of mw-cas, the shared library is built with
+   `cargo rustc --release --features ffi --crate-type cdylib`. */
+
+#ifndef MW_CAS_H
+#define MW_CAS_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define MWCAS_SUCCEEDED 1
+#define MWCAS_FAILED 0
+/* a value is larger than MWCAS_MAX_VALUE */
+#define MWCAS_INVALID_OPERAND (-1)
+/* the same word appears twice with different expected or new values */
+#define MWCAS_DUPLICATE_ADDRESS (-2)
+/* the calling thread couldn't be registered, all thread ids are taken */
+#define MWCAS_THREAD_LIMIT (-3)
+/* more words than MWCAS_MAX_ENTRIES */
+#define MWCAS_TOO_MANY_ENTRIES (-4)
+/* the operation ran out of its retry budget */
+#define MWCAS_TIMEOUT (-5)
+
+#define MWCAS_MAX_VALUE (SIZE_MAX >> 2)
+#define MWCAS_MAX_ENTRIES 4
+
+typedef struct mwcas_atomic mwcas_atomic;
+typedef struct mwcas_thread mwcas_thread;
+
+/* NULL if the value is larger than MWCAS_MAX_VALUE */
+mwcas_atomic *mwcas_alloc_atomic(size_t value);
+/* no operation may run on the word anymore */
+void mwcas_free_atomic(mwcas_atomic *atomic);
+
+/* MWCAS_SUCCEEDED with the value stored in *value, or MWCAS_THREAD_LIMIT */
+int mwcas_read(const mwcas_atomic *atomic, size_t *value);
+int mwcas_cas2(const mwcas_atomic *addr0, const mwcas_atomic *addr1, size_t exp0,
+               size_t exp1, size_t new0, size_t new1);
+int mwcas_casn(const mwcas_atomic *const *addresses, const size_t *expected,
+               const size_t *new_values, size_t len);
+
+/* keeps a foreign thread registered until mwcas_unregister_thread is called
+   on the same thread, NULL if all thread ids are taken */
+mwcas_thread *mwcas_register_thread(void);
+void mwcas_unregister_thread(mwcas_thread *thread);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -1,78 +1,293 @@
 use crate::{
-    mwcas::{CasNDescriptor, CASN_DESCRIPTOR},
-    rdcss::RDCSS_DESCRIPTOR,
+    backoff::{self, BackoffPolicy},
+    domain::DEFAULT_DOMAIN,
+    layout::DescriptorLayout,
+    mwcas::{cas2, CasNDescriptor},
+    rdcss::RDCSSDescriptor,
     sequence_number::SeqNumber,
     thread_local::ThreadId,
 };
-use std::{
-    cell::UnsafeCell,
-    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
-};
-
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::{marker::PhantomData, ptr};
+
+/// A word that takes part in multi-word CAS.
+///
+/// Layout: `repr(transparent)` over an `AtomicPtr`, so it has the size and
+/// alignment of a `usize` and can overlay memory laid out elsewhere, see
+/// `from_raw`. Pointers are stored as they are, integers shifted left by 2
+/// bits, the low 2 bits mark the descriptors of operations in progress.
 #[repr(transparent)]
 pub struct Atomic<T: Word> {
-    v: UnsafeCell<T>,
+    bits: AtomicBits,
+    _marker: PhantomData<T>,
 }
 
 impl<T: Word> Atomic<T> {
     pub fn new(t: T) -> Self {
         Self {
-            v: UnsafeCell::new(t),
+            bits: AtomicBits::new(t.into()),
+            _marker: PhantomData,
         }
     }
 
+    /// Loads the current value. An operation that is in the middle of
+    /// updating the word is waited for as the global `BackoffPolicy` allows and
+    /// helped to completion after that.
     pub fn load(&self) -> T {
+        self.load_with_backoff(backoff::global())
+    }
+
+    /// Same as `load`, but waits for an operation in progress according to
+    /// `policy`. A policy that never gives up makes readers wait for writers
+    /// instead of helping them.
+    pub fn load_with_backoff(&self, policy: &dyn BackoffPolicy) -> T {
+        DEFAULT_DOMAIN
+            .casn
+            .read_with_backoff(self.as_atomic_bits(), policy)
+            .into()
+    }
+
+    /// Loads the current value without helping an operation in progress, its
+    /// outcome is read from its descriptor instead.
+    pub fn peek(&self) -> T {
+        DEFAULT_DOMAIN.casn.peek(self.as_atomic_bits()).into()
+    }
+
+    /// Completes the operation that is updating the word right now, if there
+    /// is one. See `quiesce` before freeing memory that holds an `Atomic`.
+    pub fn settle(&self) {
+        DEFAULT_DOMAIN.casn.read(self.as_atomic_bits());
+    }
+
+    /// Returns `true` if an operation is updating the word right now. Only a
+    /// hint, the answer may be stale by the time it's returned.
+    #[inline]
+    pub fn is_contended(&self) -> bool {
+        self.bits.load(Ordering::Relaxed).is_descriptor()
+    }
+
+    pub(crate) fn as_atomic_bits(&self) -> &AtomicBits {
+        &self.bits
+    }
+
+    /// Views a `std` atomic as an `Atomic`, so multi-word CAS can run on a
+    /// field without changing its type. `Atomic` has the layout of an
+    /// `AtomicUsize`. Pointers stored through an integer view have no
+    /// provenance, for pointer words pass the address of an `AtomicPtr` to
+    /// `from_raw`.
+    ///
+    /// # Safety
+    ///
+    /// The word has to hold a value encoded the way `Atomic<T>` stores it:
+    /// pointers as they are, with the low 2 bits clear, integers shifted left
+    /// by 2 bits. As long as an operation may be running on the word, it must
+    /// not be written other than through `Atomic`s.
+    pub unsafe fn from_std(atomic: &AtomicUsize) -> &Self {
+        &*(atomic as *const AtomicUsize as *const Self)
+    }
+
+    /// The `std` atomic underneath. A load through it can see the
+    /// descriptor of an operation in progress instead of a value, a store
+    /// breaks operations running on the word.
+    pub fn as_std(&self) -> &AtomicUsize {
+        self.bits.as_raw()
+    }
+
+    /// An `Atomic` over a word the caller manages, like a field of a
+    /// `repr(C)` struct or a slot of an arena.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` has to be non-null, aligned to 8 bytes and valid for reads and
+    /// writes for `'a`, all accesses to it during `'a` have to be atomic.
+    /// Same as for `from_std`, it has to hold an encoded value and mustn't be
+    /// written other than through `Atomic`s while operations may run on it.
+    /// The memory can only be reused after the word was `settle`d, or after
+    /// `quiesce` if other threads may still help operations that ran on it.
+    #[cfg(not(loom))]
+    pub unsafe fn from_raw<'a>(ptr: *mut usize) -> &'a Self {
+        Self::from_std(AtomicUsize::from_ptr(ptr))
+    }
+
+    /// The address of the word, holding the encoded value or a descriptor.
+    #[cfg(not(loom))]
+    pub fn as_ptr(&self) -> *mut usize {
+        self.bits.as_raw().as_ptr()
+    }
+}
+
+/// A word paired with a version, for algorithms written against LL/SC.
+///
+/// Every successful `store_conditional` bumps the version together with the
+/// value in one `cas2`, and a link only succeeds while the version is the one
+/// it read. A write in between fails the link even if it stored the linked
+/// value again, unlike comparing values, which misses A-B-A sequences. The
+/// version wraps after `Atomic::<usize>::MAX` stores.
+///
+/// A plain `Atomic` can't be linked: its word has no bits to spare for a
+/// version, and single word writes don't go through a descriptor, so the
+/// sequence numbers of descriptors don't witness every write either.
+pub struct Versioned<T: Word> {
+    value: Atomic<T>,
+    version: Atomic<usize>,
+}
+
+impl<T: Word> Versioned<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value: Atomic::new(value),
+            version: Atomic::new(0),
+        }
+    }
+
+    pub fn load(&self) -> T {
+        self.value.load()
+    }
+
+    /// Loads the current value and links it for a later `store_conditional`.
+    pub fn load_linked(&self) -> Linked<'_, T> {
         loop {
-            let curr = RDCSS_DESCRIPTOR.read(self.as_atomic_bits());
-            if curr.mark() == CasNDescriptor::MARK {
-                CASN_DESCRIPTOR.help(curr, true);
-            } else {
-                return curr.into();
+            // values only change together with the version, a value read
+            // between two reads of the same version belongs to it
+            let version = self.version.load();
+            let value = self.value.load();
+            if self.version.load() == version {
+                return Linked {
+                    versioned: self,
+                    value,
+                    version,
+                };
             }
         }
     }
+}
 
-    pub(crate) fn as_atomic_bits(&self) -> &AtomicBits {
-        unsafe {
-            let v = self.v.get() as *const T as *const AtomicBits;
-            &*v
-        }
+/// A value read by `Versioned::load_linked`.
+pub struct Linked<'a, T: Word> {
+    versioned: &'a Versioned<T>,
+    value: T,
+    version: usize,
+}
+
+impl<'a, T: Word> Linked<'a, T> {
+    pub fn value(&self) -> T {
+        self.value
     }
+
+    /// Stores `new` if no store succeeded since the value was linked.
+    #[must_use]
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn store_conditional(self, new: T) -> bool {
+        let next = self.version.wrapping_add(1) & Atomic::<usize>::MAX;
+        cas2(
+            &self.versioned.value,
+            &self.versioned.version,
+            self.value,
+            self.version,
+            new,
+            next,
+        )
+    }
+}
+
+/// `ptr` in the sentinel state: the mark no descriptor uses set in its low
+/// bits. Operations treat such a pointer as an ordinary value, structures
+/// can use it for a state of their own, like the link of a removed node.
+/// `ptr` has to be aligned to 4 bytes and not be in the state already.
+///
+/// The result doesn't `fit`, nothing tells it from a pointer misaligned by 3,
+/// so `try_cas_n` rejects it. The operations that don't check their values
+/// store it.
+pub fn with_sentinel<T>(ptr: *const T) -> *const T {
+    debug_assert_eq!(ptr.addr() & Bits::RESERVED_MASK, 0);
+    ptr.map_addr(|addr| addr | Bits::SENTINEL_MARK)
+}
+
+/// The pointer `with_sentinel` was given, `ptr` itself if it isn't in the
+/// sentinel state.
+pub fn without_sentinel<T>(ptr: *const T) -> *const T {
+    ptr.map_addr(|addr| addr & !Bits::SENTINEL_MARK)
+}
+
+pub fn is_sentinel<T>(ptr: *const T) -> bool {
+    ptr.addr() & Bits::RESERVED_MASK == Bits::SENTINEL_MARK
+}
+
+impl Atomic<usize> {
+    /// Largest value an `Atomic<usize>` can hold: the low
+    /// `Bits::NUM_RESERVED_BITS` bits of every word are taken by descriptor marks.
+    pub const MAX: usize = usize::MAX >> Bits::NUM_RESERVED_BITS;
 }
 
-pub trait Word: sealed::Word + Into<Bits> + From<Bits> + Copy + 'static {}
-impl<T: 'static> Word for *mut T {}
+pub trait Word: sealed::Word + Into<Bits> + From<Bits> + Copy + 'static {
+    /// Returns `false` if `self` can't be stored without touching the reserved mark bits.
+    /// Pointers from `with_sentinel` don't fit either, see there.
+    fn fits(self) -> bool;
+}
+
+impl<T: 'static> Word for *mut T {
+    fn fits(self) -> bool {
+        self.addr() & Bits::RESERVED_MASK == 0
+    }
+}
 
 impl<T: 'static> From<*mut T> for Bits {
     fn from(ptr: *mut T) -> Self {
-        Bits::from_usize(ptr as _)
+        debug_assert!(
+            ptr.fits() || is_sentinel(ptr),
+            "pointer {:p} is not aligned",
+            ptr
+        );
+        Bits(ptr.cast())
     }
 }
 
 impl<T> From<Bits> for *mut T {
     fn from(bits: Bits) -> Self {
-        bits.into_usize() as _
+        bits.0.cast()
     }
 }
 
-impl<T: 'static> Word for *const T {}
+impl<T: 'static> Word for *const T {
+    fn fits(self) -> bool {
+        self.addr() & Bits::RESERVED_MASK == 0
+    }
+}
 
 impl<T: 'static> From<*const T> for Bits {
     fn from(ptr: *const T) -> Self {
-        Bits::from_usize(ptr as _)
+        debug_assert!(
+            ptr.fits() || is_sentinel(ptr),
+            "pointer {:p} is not aligned",
+            ptr
+        );
+        Bits(ptr.cast_mut().cast())
     }
 }
 
 impl<T: 'static> From<Bits> for *const T {
     fn from(bits: Bits) -> Self {
-        bits.into_usize() as _
+        bits.0.cast_const().cast()
     }
 }
 
-impl Word for usize {}
+impl Word for usize {
+    fn fits(self) -> bool {
+        self <= Atomic::<usize>::MAX
+    }
+}
 
 impl From<usize> for Bits {
     fn from(int: usize) -> Self {
+        assert!(
+            int.fits(),
+            "value {} does not fit into Atomic<usize>, max is {}",
+            int,
+            Atomic::<usize>::MAX
+        );
         Bits::from_usize(int << Bits::NUM_RESERVED_BITS)
     }
 }
@@ -83,6 +298,12 @@ impl From<Bits> for usize {
     }
 }
 
+const _: () = assert!(
+    RDCSSDescriptor::MARK != Bits::SENTINEL_MARK
+        && CasNDescriptor::MARK != Bits::SENTINEL_MARK
+        && Bits::SENTINEL_MARK <= Bits::RESERVED_MASK
+);
+
 unsafe impl<T: Word> Sync for Atomic<T> {}
 unsafe impl<T: Word> Send for Atomic<T> {}
 
@@ -95,81 +316,177 @@ mod sealed {
     impl Word for usize {}
 }
 
+// The content of a word. Pointers keep their provenance, integers and
+// descriptor pointers are addresses without one, so a word never turns an
+// integer back into a pointer.
 #[derive(Clone, Copy, Eq, PartialEq, Debug)]
-pub struct Bits(usize);
+pub struct Bits(*mut u8);
+
+// a plain value, whatever it points to is owned elsewhere
+unsafe impl Send for Bits {}
+unsafe impl Sync for Bits {}
 
 impl Bits {
-    pub const NUM_RESERVED_BITS: usize = 2;
+    pub const NUM_RESERVED_BITS: usize = DescriptorLayout::MARK_BITS;
+    pub const RESERVED_MASK: usize = DescriptorLayout::MARK_MASK;
+    // The mark no descriptor uses, free for structures to put pointers into a
+    // state of their own, like deleted. Operations treat such a word as an
+    // ordinary value.
+    pub const SENTINEL_MARK: usize = 3;
 
     pub fn new_descriptor_ptr(tid: ThreadId, seq: SeqNumber) -> Self {
-        let tid =
-            (tid.as_u16() as usize) << (SeqNumber::LENGTH + Self::NUM_RESERVED_BITS);
-        Self(tid | (seq.as_usize() << Self::NUM_RESERVED_BITS))
+        Self::from_usize(DescriptorLayout::pack(
+            tid.as_u16() as usize,
+            seq.as_usize(),
+        ))
     }
 
     pub fn tid(self) -> ThreadId {
-        ThreadId::from_u16(
-            (self.0 >> (SeqNumber::LENGTH + Self::NUM_RESERVED_BITS)) as u16,
-        )
+        ThreadId::from_u16(DescriptorLayout::tid(self.0.addr()) as u16)
     }
 
     pub fn seq(self) -> SeqNumber {
-        let mask = (1usize << (SeqNumber::LENGTH + Self::NUM_RESERVED_BITS)) - 1;
-        let seq = (self.0 & mask) >> Self::NUM_RESERVED_BITS;
-        SeqNumber::from_usize(seq)
+        SeqNumber::from_usize(DescriptorLayout::seq(self.0.addr()))
     }
 
     pub fn with_mark(self, mark: usize) -> Self {
-        let bits = mark & (Self::NUM_RESERVED_BITS + 1);
-        let marked = self.0 | bits;
-        Self(marked)
+        debug_assert_eq!(mark & !Self::RESERVED_MASK, 0);
+        Self(self.0.map_addr(|addr| addr | mark))
+    }
+
+    pub fn eq_masked(self, other: Bits, mask: Bits) -> bool {
+        (self.0.addr() & mask.0.addr()) == (other.0.addr() & mask.0.addr())
     }
 
     pub fn mark(self) -> usize {
-        self.0 & (Self::NUM_RESERVED_BITS + 1)
+        DescriptorLayout::mark(self.0.addr())
+    }
+
+    // whether the word holds a descriptor rather than a value
+    pub fn is_descriptor(self) -> bool {
+        self.mark() != 0 && self.mark() != Self::SENTINEL_MARK
+    }
+
+    pub fn is_sentinel(self) -> bool {
+        self.mark() == Self::SENTINEL_MARK
+    }
+
+    pub fn with_sentinel(self) -> Self {
+        debug_assert_eq!(self.mark(), 0);
+        self.with_mark(Self::SENTINEL_MARK)
+    }
+
+    pub fn without_sentinel(self) -> Self {
+        Self(self.0.map_addr(|addr| addr & !Self::SENTINEL_MARK))
     }
 
     pub fn into_usize(self) -> usize {
-        self.0
+        self.0.addr()
     }
 
+    // an integer or descriptor pointer, no memory can be reached through it
     pub fn from_usize(raw: usize) -> Self {
-        Self(raw)
+        Self(ptr::without_provenance_mut(raw))
+    }
+
+    pub fn from_ptr<T>(ptr: *mut T) -> Self {
+        Self(ptr.cast())
+    }
+
+    pub fn into_ptr<T>(self) -> *mut T {
+        self.0.cast()
     }
 }
 
 #[repr(transparent)]
-pub struct AtomicBits(AtomicUsize);
+pub struct AtomicBits(AtomicRepr);
+
+// Loom can't view an `AtomicPtr` as an `AtomicUsize`, so its words are
+// integers and pointers go through exposed provenance. Only the model checker
+// sees them.
+#[cfg(not(loom))]
+type AtomicRepr = AtomicPtr<u8>;
+#[cfg(loom)]
+type AtomicRepr = AtomicUsize;
+
+impl Default for AtomicBits {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
 
 impl AtomicBits {
+    pub fn new(bits: Bits) -> Self {
+        Self(AtomicRepr::new(Self::to_repr(bits)))
+    }
+
     pub fn empty() -> Self {
-        Self(AtomicUsize::new(0))
+        Self::new(Bits::from_usize(0))
     }
 
     pub fn load(&self, ord: Ordering) -> Bits {
-        Bits::from_usize(self.0.load(ord))
+        Self::from_repr(self.0.load(ord))
     }
 
+    #[cfg_attr(
+        all(feature = "heap-descriptors", not(feature = "wait-free")),
+        allow(dead_code)
+    )]
     pub fn store(&self, word: Bits, ord: Ordering) {
-        self.0.store(word.into_usize(), ord);
+        self.0.store(Self::to_repr(word), ord);
+    }
+
+    // The word as an integer, for words that never hold pointers, like the
+    // RDCSS control words. `AtomicPtr` has the layout of an `AtomicUsize`, the
+    // pointer to its value is valid for the lifetime of `self` and only
+    // accessed atomically.
+    #[cfg(not(loom))]
+    pub fn as_raw(&self) -> &AtomicUsize {
+        unsafe { AtomicUsize::from_ptr(self.0.as_ptr().cast::<usize>()) }
+    }
+
+    #[cfg(loom)]
+    pub fn as_raw(&self) -> &AtomicUsize {
+        &self.0
     }
 
     pub fn compare_exchange(&self, expected: Bits, new: Bits) -> Result<Bits, Bits> {
-        let exchanged = self.0.compare_exchange(
-            expected.into_usize(),
-            new.into_usize(),
-            Ordering::SeqCst,
-            Ordering::SeqCst,
-        );
-        match exchanged {
-            Ok(new) => Ok(Bits::from_usize(new)),
-            Err(err) => Err(Bits::from_usize(err)),
-        }
+        self.0
+            .compare_exchange(
+                Self::to_repr(expected),
+                Self::to_repr(new),
+                Ordering::SeqCst,
+                Ordering::SeqCst,
+            )
+            .map(Self::from_repr)
+            .map_err(Self::from_repr)
+    }
+
+    #[cfg(not(loom))]
+    fn to_repr(bits: Bits) -> *mut u8 {
+        bits.0
+    }
+
+    #[cfg(not(loom))]
+    fn from_repr(repr: *mut u8) -> Bits {
+        Bits(repr)
+    }
+
+    #[cfg(loom)]
+    fn to_repr(bits: Bits) -> usize {
+        bits.0.expose_provenance()
+    }
+
+    #[cfg(loom)]
+    fn from_repr(repr: usize) -> Bits {
+        Bits(ptr::with_exposed_provenance_mut(repr))
     }
 }
 
+#[cfg(not(feature = "heap-descriptors"))]
 pub struct AtomicAddress<T>(AtomicPtr<T>);
 
+#[cfg(not(feature = "heap-descriptors"))]
 impl<T> AtomicAddress<T> {
     pub fn empty() -> Self {
         Self(AtomicPtr::default())
@@ -186,9 +503,61 @@ impl<T> AtomicAddress<T> {
     }
 }
 
+// Proofs over every tid, sequence number, pointer and integer, run with
+// `cargo kani`.
+#[cfg(kani)]
+mod proofs {
+    use super::*;
+    use crate::thread_local::MAX_THREAD_IDS;
+
+    fn any_descriptor() -> Bits {
+        let tid: u16 = kani::any();
+        let seq: usize = kani::any();
+        kani::assume((tid as usize) < MAX_THREAD_IDS);
+        kani::assume(seq < 1 << SeqNumber::LENGTH);
+        let mark = if kani::any() {
+            RDCSSDescriptor::MARK
+        } else {
+            CasNDescriptor::MARK
+        };
+        Bits::new_descriptor_ptr(ThreadId::from_u16(tid), SeqNumber::from_usize(seq))
+            .with_mark(mark)
+    }
+
+    #[kani::proof]
+    fn descriptor_is_no_pointer() {
+        let descriptor = any_descriptor();
+        let addr: usize = kani::any();
+        let ptr: *mut u8 = ptr::without_provenance_mut(addr);
+        kani::assume(ptr.fits());
+        assert_ne!(descriptor.into_usize(), Bits::from(ptr).into_usize());
+    }
+
+    #[kani::proof]
+    fn descriptor_is_no_integer() {
+        let descriptor = any_descriptor();
+        let value: usize = kani::any();
+        kani::assume(value.fits());
+        assert_ne!(descriptor.into_usize(), Bits::from(value).into_usize());
+    }
+
+    #[kani::proof]
+    fn mark_is_injective() {
+        let bits = Bits::from_usize(kani::any::<usize>() & !Bits::RESERVED_MASK);
+        let (first, second): (usize, usize) = (kani::any(), kani::any());
+        kani::assume(first <= Bits::RESERVED_MASK && second <= Bits::RESERVED_MASK);
+        let (marked_first, marked_second) =
+            (bits.with_mark(first), bits.with_mark(second));
+        assert_eq!(marked_first.mark(), first);
+        assert_eq!(marked_first == marked_second, first == second);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::thread_local::MAX_THREAD_IDS;
+    use proptest::prelude::*;
 
     #[test]
     fn test_descriptor_ptr() {
@@ -205,4 +574,163 @@ mod tests {
         assert_eq!(marked_descriptor.tid(), tid);
         assert_eq!(marked_descriptor.seq(), seq_number);
     }
+
+    #[test]
+    fn test_usize_round_trip() {
+        for &v in &[0, 1, 2, 3, 12345, Atomic::<usize>::MAX] {
+            let atom = Atomic::new(v);
+            assert_eq!(atom.as_atomic_bits().load(Ordering::SeqCst).mark(), 0);
+            assert_eq!(atom.load(), v);
+        }
+    }
+
+    #[test]
+    fn test_load_linked() {
+        let word = Versioned::new(1usize);
+        let linked = word.load_linked();
+        assert_eq!(linked.value(), 1);
+        assert!(unsafe { linked.store_conditional(2) });
+        assert_eq!(word.load(), 2);
+
+        let first = word.load_linked();
+        let second = word.load_linked();
+        assert!(unsafe { second.store_conditional(3) });
+        assert!(!unsafe { first.store_conditional(4) });
+        assert_eq!(word.load(), 3);
+
+        // A-B-A: the value is back, but the link is stale
+        let stale = word.load_linked();
+        assert!(unsafe { word.load_linked().store_conditional(5) });
+        assert!(unsafe { word.load_linked().store_conditional(3) });
+        assert_eq!(stale.value(), word.load());
+        assert!(!unsafe { stale.store_conditional(6) });
+        assert_eq!(word.load(), 3);
+    }
+
+    #[test]
+    fn test_is_contended() {
+        let atom = Atomic::new(1usize);
+        assert!(!atom.is_contended());
+
+        let descriptor =
+            Bits::new_descriptor_ptr(ThreadId::from_u16(0), SeqNumber::from_usize(1));
+        atom.as_atomic_bits()
+            .store(descriptor.with_mark(2), Ordering::SeqCst);
+        assert!(atom.is_contended());
+        atom.as_atomic_bits().store(1.into(), Ordering::SeqCst);
+        assert!(!atom.is_contended());
+    }
+
+    #[test]
+    fn test_sentinel_is_a_value() {
+        let (first, second) = (Box::new(1u64), Box::new(2u64));
+        let first: *const u64 = &*first;
+        let deleted = with_sentinel(first);
+        assert!(!deleted.fits() && is_sentinel(deleted) && !is_sentinel(first));
+        assert_eq!(without_sentinel(deleted), first);
+        let atom = Atomic::new(first);
+        let other = Atomic::new(&*second as *const u64);
+        assert!(unsafe { crate::cas2(&atom, &other, first, &*second, deleted, first) });
+        assert!(!atom.is_contended());
+        assert_eq!(atom.load(), deleted);
+        let bits = atom.as_atomic_bits().load(Ordering::SeqCst);
+        assert!(bits.is_sentinel() && !bits.is_descriptor());
+        assert_eq!(<*const u64>::from(bits.without_sentinel()), first);
+        assert!(unsafe { crate::cas_n(&[&atom], &[deleted], &[first]) });
+        assert_eq!(atom.load(), first);
+    }
+
+    #[test]
+    fn test_from_std() {
+        let std = AtomicUsize::new(5 << 2);
+        let atom = unsafe { Atomic::<usize>::from_std(&std) };
+        assert_eq!(atom.load(), 5);
+        assert!(unsafe { crate::cas_n(&[atom], &[5], &[6]) });
+        assert_eq!(std.load(Ordering::SeqCst), 6 << 2);
+        assert!(std::ptr::eq(atom.as_std(), &std));
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_from_raw() {
+        #[repr(C)]
+        struct Slot {
+            tag: u64,
+            words: [usize; 2],
+        }
+
+        let mut slot = Slot {
+            tag: 1,
+            words: [0, 4 << 2],
+        };
+        let words = slot.words.as_mut_ptr();
+        let (a, b) = unsafe {
+            (
+                Atomic::<usize>::from_raw(words),
+                Atomic::<usize>::from_raw(words.add(1)),
+            )
+        };
+        assert_eq!(a.as_ptr(), words);
+        assert!(unsafe { crate::cas2(a, b, 0, 4, 1, 5) });
+        assert_eq!((slot.tag, slot.words), (1, [1 << 2, 5 << 2]));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_usize_overflow() {
+        let _ = Atomic::new(Atomic::<usize>::MAX + 1);
+    }
+
+    // the bounds themselves and their neighbours come up more often than a
+    // uniform pick would make them
+    fn up_to(max: usize) -> impl Strategy<Value = usize> {
+        prop_oneof![Just(0), Just(1), Just(max - 1), Just(max), 0..=max]
+    }
+
+    proptest! {
+        #[test]
+        fn test_descriptor_ptr_round_trip(
+            tid in up_to(MAX_THREAD_IDS - 1),
+            seq in up_to((1 << SeqNumber::LENGTH) - 1),
+            mark in 0..=Bits::RESERVED_MASK,
+        ) {
+            let tid = ThreadId::from_u16(tid as u16);
+            let seq = SeqNumber::from_usize(seq);
+            let descriptor = Bits::new_descriptor_ptr(tid, seq);
+            prop_assert_eq!(descriptor.mark(), 0);
+            let marked = descriptor.with_mark(mark);
+            prop_assert_eq!(marked.mark(), mark);
+            prop_assert_eq!(marked.tid(), tid);
+            prop_assert_eq!(marked.seq(), seq);
+            // the mark only touches the reserved bits
+            let unmarked = marked.into_usize() & !Bits::RESERVED_MASK;
+            prop_assert_eq!(unmarked, descriptor.into_usize());
+        }
+
+        #[test]
+        fn test_value_round_trip(
+            value in up_to(Atomic::<usize>::MAX),
+            mark in 0..=Bits::RESERVED_MASK,
+        ) {
+            let bits = Bits::from(value);
+            prop_assert_eq!(bits.mark(), 0);
+            prop_assert_eq!(usize::from(bits), value);
+            // a mark never changes the value the rest of the word decodes to
+            prop_assert_eq!(usize::from(bits.with_mark(mark)), value);
+        }
+
+        #[test]
+        fn test_pointer_round_trip(
+            addr in up_to(usize::MAX >> Bits::NUM_RESERVED_BITS),
+            mark in 0..=Bits::RESERVED_MASK,
+        ) {
+            let addr = addr << Bits::NUM_RESERVED_BITS;
+            let ptr: *mut u64 = ptr::without_provenance_mut(addr);
+            prop_assert!(ptr.fits());
+            let bits = Bits::from(ptr);
+            prop_assert_eq!(bits.mark(), 0);
+            prop_assert_eq!(<*mut u64>::from(bits), ptr);
+            prop_assert_eq!(bits.with_mark(mark).mark(), mark);
+        }
+    }
 }
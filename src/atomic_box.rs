@@ -0,0 +1,117 @@
+use crate::atomic::{Atomic, Bits};
+use std::{marker::PhantomData, mem};
+
+/// An `Atomic` pointer word that owns the boxed value it points to and frees
+/// it on drop.
+///
+/// The word takes part in multi-word CAS through `as_atomic`. Pointers stored
+/// into it have to come from `Box::into_raw`, and the caller that swapped a
+/// pointer out owns it from then on: it can free it right away if no other
+/// thread can still be reading it, or retire it with `epoch::cas2_retire`.
+pub struct AtomicBox<T: 'static> {
+    atomic: Atomic<*const T>,
+    _marker: PhantomData<*const T>,
+}
+
+impl<T: 'static> AtomicBox<T> {
+    const ALIGNED: () = assert!(
+        mem::align_of::<T>() > Bits::RESERVED_MASK,
+        "pointers to T don't leave the reserved bits free"
+    );
+
+    pub fn new(value: T) -> Self {
+        let () = Self::ALIGNED;
+        Self {
+            atomic: Atomic::new(Box::into_raw(Box::new(value))),
+            _marker: PhantomData,
+        }
+    }
+
+    /// The word itself, to be used with `cas2`, `cas_n` and friends.
+    #[inline]
+    pub fn as_atomic(&self) -> &Atomic<*const T> {
+        &self.atomic
+    }
+
+    #[inline]
+    pub fn load(&self) -> *const T {
+        self.atomic.load()
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        // safety: `&mut self` rules out concurrent operations on the word
+        unsafe { &mut *(self.atomic.load() as *mut T) }
+    }
+
+    pub fn into_inner(self) -> Box<T> {
+        let ptr = self.atomic.load();
+        mem::forget(self);
+        unsafe { Box::from_raw(ptr as *mut T) }
+    }
+}
+
+impl<T: 'static> Drop for AtomicBox<T> {
+    fn drop(&mut self) {
+        let ptr = self.atomic.load();
+        if !ptr.is_null() {
+            unsafe { drop(Box::from_raw(ptr as *mut T)) };
+        }
+    }
+}
+
+impl<T: Default + 'static> Default for AtomicBox<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+unsafe impl<T: Send + 'static> Send for AtomicBox<T> {}
+// other threads can read the value and take ownership of it by swapping it out
+unsafe impl<T: Send + Sync + 'static> Sync for AtomicBox<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cas2;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Tracked(usize);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            DROPPED.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_atomic_box() {
+        let first = AtomicBox::new(Tracked(0));
+        let second = AtomicBox::new(Tracked(0));
+
+        let (exp0, exp1) = (first.load(), second.load());
+        let new0: *const _ = Box::into_raw(Box::new(Tracked(1)));
+        let new1: *const _ = Box::into_raw(Box::new(Tracked(1)));
+        assert!(unsafe {
+            cas2(
+                first.as_atomic(),
+                second.as_atomic(),
+                exp0,
+                exp1,
+                new0,
+                new1,
+            )
+        });
+        unsafe {
+            drop(Box::from_raw(exp0 as *mut Tracked));
+            drop(Box::from_raw(exp1 as *mut Tracked));
+        }
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 2);
+
+        assert_eq!(first.into_inner().0, 1);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 3);
+        drop(second);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 4);
+    }
+}
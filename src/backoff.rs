@@ -0,0 +1,115 @@
+// loom has to see every spin to schedule the thread being waited for
+#[cfg(loom)]
+use loom::{hint::spin_loop, thread::yield_now};
+use std::{
+    cell::Cell,
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+    thread,
+    time::Duration,
+};
+#[cfg(not(loom))]
+use std::{hint::spin_loop, thread::yield_now};
+
+/// Decides how long an operation waits for a conflicting one to finish before
+/// helping it.
+pub trait BackoffPolicy: Send + Sync {
+    /// Called on the `step`-th conflict of an operation, starting from 0.
+    /// Waits and returns `true` to retry, or returns `false` to help the
+    /// conflicting operation right away.
+    fn wait(&self, step: u32) -> bool;
+}
+
+/// Spins `2^step` times for the first `spin_limit` steps, then gives up the
+/// time slice for `yield_limit` more steps before helping.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExponentialBackoff {
+    pub spin_limit: u32,
+    pub yield_limit: u32,
+    /// Sleep for this long instead of yielding.
+    pub park: Option<Duration>,
+    /// Spin a random number of times between `2^(step - 1)` and `2^step`, so
+    /// threads that collided once are unlikely to collide on the retry again.
+    pub jitter: bool,
+}
+
+impl ExponentialBackoff {
+    pub const fn new() -> Self {
+        Self {
+            spin_limit: 6,
+            yield_limit: 0,
+            park: None,
+            jitter: false,
+        }
+    }
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BackoffPolicy for ExponentialBackoff {
+    fn wait(&self, step: u32) -> bool {
+        if step < self.spin_limit {
+            let mut spins = 1u32 << step.min(16);
+            if self.jitter {
+                spins = spins / 2 + random() % (spins / 2 + 1);
+            }
+            for _ in 0..spins {
+                spin_loop();
+            }
+            true
+        } else if step - self.spin_limit < self.yield_limit {
+            match self.park {
+                Some(duration) => thread::sleep(duration),
+                None => yield_now(),
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+thread_local! {
+    static RNG_STATE: Cell<u32> = const { Cell::new(0) };
+}
+
+// xorshift, seeded from the address of the thread local, only has to be
+// different between threads
+fn random() -> u32 {
+    RNG_STATE.with(|state| {
+        let mut x = state.get();
+        if x == 0 {
+            x = (state as *const Cell<u32> as usize >> 3) as u32 | 1;
+        }
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        state.set(x);
+        x
+    })
+}
+
+static DEFAULT: ExponentialBackoff = ExponentialBackoff::new();
+
+static GLOBAL: AtomicPtr<&'static dyn BackoffPolicy> = AtomicPtr::new(ptr::null_mut());
+
+/// Replaces the policy used by operations that don't set their own, see
+/// `CASN::set_backoff`. Meant to be called once on startup, every call leaks
+/// a pointer sized allocation.
+pub fn set_backoff_policy(policy: &'static dyn BackoffPolicy) {
+    GLOBAL.store(Box::into_raw(Box::new(policy)), Ordering::Release);
+}
+
+pub(crate) fn global() -> &'static dyn BackoffPolicy {
+    let policy = GLOBAL.load(Ordering::Acquire);
+    if policy.is_null() {
+        &DEFAULT
+    } else {
+        // never freed, see `set_backoff_policy`
+        unsafe { *policy }
+    }
+}
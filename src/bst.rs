@@ -0,0 +1,369 @@
+//! A lock-free ordered map, enabled by the `structures` feature.
+
+use crate::{cas_n, is_sentinel, with_sentinel, without_sentinel, Atomic};
+use crossbeam_epoch::{pin, Guard};
+use std::{marker::PhantomData, ptr};
+
+// Internal nodes route, leaves hold the entries. Keys of internal nodes are
+// copies of leaf keys, a key goes right if it's at least the node's key.
+struct Node<K: 'static, V: 'static> {
+    key: Key<K>,
+    left: Atomic<*const Node<K, V>>,
+    right: Atomic<*const Node<K, V>>,
+    // `None` in internal nodes and the sentinel leaves
+    value: Option<V>,
+    is_leaf: bool,
+}
+
+// The root and its two leaves have infinite keys, so every finite leaf has a
+// parent and a grandparent.
+enum Key<K> {
+    Finite(K),
+    Infinite,
+}
+
+impl<K: Ord> Key<K> {
+    fn is_right_of(&self, key: &K) -> bool {
+        match self {
+            Key::Finite(node_key) => key >= node_key,
+            Key::Infinite => false,
+        }
+    }
+}
+
+impl<K: 'static, V: 'static> Node<K, V> {
+    fn leaf(key: Key<K>, value: Option<V>) -> *mut Self {
+        Self::alloc(key, ptr::null(), ptr::null(), value, true)
+    }
+
+    fn internal(key: Key<K>, left: *const Self, right: *const Self) -> *mut Self {
+        Self::alloc(key, left, right, None, false)
+    }
+
+    fn alloc(
+        key: Key<K>,
+        left: *const Self,
+        right: *const Self,
+        value: Option<V>,
+        is_leaf: bool,
+    ) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            key,
+            left: Atomic::new(left),
+            right: Atomic::new(right),
+            value,
+            is_leaf,
+        }))
+    }
+
+    fn child(&self, right: bool) -> &Atomic<*const Self> {
+        if right {
+            &self.right
+        } else {
+            &self.left
+        }
+    }
+}
+
+// both child pointers of a removed internal node are in the sentinel state
+fn is_deleted<T>(child: *const T) -> bool {
+    is_sentinel(child)
+}
+
+fn unmarked<T>(child: *const T) -> *const T {
+    without_sentinel(child)
+}
+
+fn marked<T>(child: *const T) -> *const T {
+    with_sentinel(child)
+}
+
+// where a search for a key ended
+struct Position<K: 'static, V: 'static> {
+    grandparent: *const Node<K, V>,
+    // side of the parent below the grandparent
+    parent_right: bool,
+    parent: *const Node<K, V>,
+    // side of the leaf below the parent
+    leaf_right: bool,
+    leaf: *const Node<K, V>,
+    // the child pointer of the parent as it was read, marked if the parent
+    // was removed in the meantime
+    leaf_raw: *const Node<K, V>,
+}
+
+/// An unbalanced external binary search tree.
+///
+/// Entries live in the leaves. An insert replaces a leaf with a new internal
+/// node over the old and the new leaf, a remove replaces the parent of a leaf
+/// with the leaf's sibling and marks both child pointers of the parent in the
+/// same `cas_n`, so an insert below a removed parent can't succeed.
+pub struct BstMap<K: 'static, V: 'static> {
+    root: *const Node<K, V>,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> BstMap<K, V>
+where
+    K: Ord + Clone + Send + 'static,
+    V: Send + 'static,
+{
+    pub fn new() -> Self {
+        let left = Node::leaf(Key::Infinite, None);
+        let right = Node::leaf(Key::Infinite, None);
+        Self {
+            root: Node::internal(Key::Infinite, left, right),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Inserts the entry if the map doesn't have `key` yet, returns whether it
+    /// did.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let _guard = pin();
+        let new_leaf = Node::leaf(Key::Finite(key), Some(value));
+        loop {
+            let key = match unsafe { &(*new_leaf).key } {
+                Key::Finite(key) => key,
+                Key::Infinite => unreachable!(),
+            };
+            let position = unsafe { self.search(key) };
+            let leaf = unsafe { &*position.leaf };
+            let (internal_key, left, right) = match &leaf.key {
+                Key::Finite(leaf_key) if leaf_key == key => {
+                    drop(unsafe { Box::from_raw(new_leaf) });
+                    return false;
+                },
+                Key::Finite(leaf_key) if leaf_key < key => (
+                    Key::Finite(key.clone()),
+                    position.leaf,
+                    new_leaf as *const _,
+                ),
+                Key::Finite(leaf_key) => (
+                    Key::Finite(leaf_key.clone()),
+                    new_leaf as *const _,
+                    position.leaf,
+                ),
+                Key::Infinite => (Key::Infinite, new_leaf as *const _, position.leaf),
+            };
+            let internal = Node::internal(internal_key, left, right);
+            let parent = unsafe { &*position.parent };
+            if !is_deleted(position.leaf_raw)
+                && unsafe {
+                    cas_n(
+                        &[parent.child(position.leaf_right)],
+                        &[position.leaf],
+                        &[internal],
+                    )
+                }
+            {
+                return true;
+            }
+            // only the internal node is discarded, the new leaf is tried again
+            drop(unsafe { Box::from_raw(internal) });
+        }
+    }
+
+    /// Removes the entry of `key`, returns whether there was one. The value
+    /// is dropped once no guard can see it anymore.
+    pub fn remove(&self, key: &K) -> bool {
+        let guard = pin();
+        loop {
+            let position = unsafe { self.search(key) };
+            match unsafe { &(*position.leaf).key } {
+                Key::Finite(leaf_key) if leaf_key == key => {},
+                _ => return false,
+            }
+            if is_deleted(position.leaf_raw) {
+                continue;
+            }
+            let grandparent = unsafe { &*position.grandparent };
+            let parent = unsafe { &*position.parent };
+            let sibling_word = parent.child(!position.leaf_right);
+            let sibling = sibling_word.load();
+            if is_deleted(sibling) {
+                continue;
+            }
+            if unsafe {
+                cas_n(
+                    &[
+                        grandparent.child(position.parent_right),
+                        parent.child(position.leaf_right),
+                        sibling_word,
+                    ],
+                    &[position.parent, position.leaf, sibling],
+                    &[sibling, marked(position.leaf), marked(sibling)],
+                )
+            } {
+                unsafe {
+                    retire(&guard, position.parent);
+                    retire(&guard, position.leaf);
+                }
+                return true;
+            }
+        }
+    }
+
+    pub fn get<'g>(&'g self, key: &K, _guard: &'g Guard) -> Option<&'g V> {
+        let position = unsafe { self.search(key) };
+        let leaf = unsafe { &*position.leaf };
+        match &leaf.key {
+            Key::Finite(leaf_key) if leaf_key == key => leaf.value.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key, &pin()).is_some()
+    }
+
+    // safety: the caller is pinned
+    unsafe fn search(&self, key: &K) -> Position<K, V> {
+        let mut grandparent = ptr::null();
+        let mut parent_right = false;
+        let mut parent = self.root;
+        let mut leaf_right = (*parent).key.is_right_of(key);
+        let mut leaf_raw = (*parent).child(leaf_right).load();
+        let mut leaf = unmarked(leaf_raw);
+        while !(*leaf).is_leaf {
+            grandparent = parent;
+            parent_right = leaf_right;
+            parent = leaf;
+            leaf_right = (*parent).key.is_right_of(key);
+            leaf_raw = (*parent).child(leaf_right).load();
+            leaf = unmarked(leaf_raw);
+        }
+        Position {
+            grandparent,
+            parent_right,
+            parent,
+            leaf_right,
+            leaf,
+            leaf_raw,
+        }
+    }
+}
+
+// safety: `node` was unlinked by the caller, nobody else retires it
+unsafe fn retire<K: Send + 'static, V: Send + 'static>(
+    guard: &Guard,
+    node: *const Node<K, V>,
+) {
+    let node = node as *mut Node<K, V>;
+    guard.defer_unchecked(move || drop(Box::from_raw(node)));
+}
+
+impl<K, V> Default for BstMap<K, V>
+where
+    K: Ord + Clone + Send + 'static,
+    V: Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: 'static, V: 'static> Drop for BstMap<K, V> {
+    fn drop(&mut self) {
+        let mut nodes = vec![self.root];
+        while let Some(node) = nodes.pop() {
+            let node = unsafe { Box::from_raw(node as *mut Node<K, V>) };
+            if !node.is_leaf {
+                nodes.push(unmarked(node.left.load()));
+                nodes.push(unmarked(node.right.load()));
+            }
+        }
+    }
+}
+
+unsafe impl<K: Send + 'static, V: Send + 'static> Send for BstMap<K, V> {}
+// every search compares its key against the routing keys of internal nodes
+// other threads inserted, and `get` lends `&V` of a leaf to every thread that
+// reaches it
+unsafe impl<K: Send + Sync + 'static, V: Send + Sync + 'static> Sync for BstMap<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicIsize, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn test_bst_map() {
+        let map = BstMap::new();
+        assert!(map.insert(2, "two"));
+        assert!(map.insert(1, "one"));
+        assert!(map.insert(3, "three"));
+        assert!(!map.insert(2, "deux"));
+        assert_eq!(map.get(&2, &pin()), Some(&"two"));
+        assert!(map.remove(&2));
+        assert!(!map.remove(&2));
+        assert!(!map.contains_key(&2));
+        assert!(map.contains_key(&1) && map.contains_key(&3));
+        assert!(map.insert(2, "deux"));
+        assert_eq!(map.get(&2, &pin()), Some(&"deux"));
+    }
+
+    // Collects the keys of the leaves below `node` in order, checking that
+    // each one lies in the range `low..high` the routing keys above it send
+    // it to. `usize::MAX` stands in for the infinite keys.
+    fn collect_leaves(
+        node: *const Node<usize, usize>,
+        low: usize,
+        high: usize,
+        keys: &mut Vec<usize>,
+    ) {
+        let node = unsafe { &*node };
+        let split = match node.key {
+            Key::Finite(key) => key,
+            Key::Infinite => usize::MAX,
+        };
+        if node.is_leaf {
+            if split != usize::MAX {
+                assert!((low..high).contains(&split));
+                keys.push(split);
+            }
+            return;
+        }
+        assert!(low <= split && split <= high);
+        let (left, right) = (node.left.load(), node.right.load());
+        // every removed parent has been replaced by its leaf's sibling
+        assert!(!is_deleted(left) && !is_deleted(right));
+        collect_leaves(left, low, split, keys);
+        collect_leaves(right, split, high, keys);
+    }
+
+    #[test]
+    fn test_routing_ranges() {
+        let map = BstMap::new();
+        // per key, how many more inserts than removes succeeded
+        let net: Vec<_> = (0..64).map(|_| AtomicIsize::new(0)).collect();
+        thread::scope(|s| {
+            for t in 0..4 {
+                let (map, net) = (&map, &net);
+                s.spawn(move || {
+                    // every thread goes over all keys, so inserts and removes
+                    // of the same key and of its neighbours race
+                    for i in 0..2000 {
+                        let key = (i * 7 + t * 3) % 64;
+                        if (i + t) % 3 == 0 {
+                            if map.remove(&key) {
+                                net[key].fetch_sub(1, Ordering::Relaxed);
+                            }
+                        } else if map.insert(key, i) {
+                            net[key].fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        let net: Vec<_> = net.iter().map(|n| n.load(Ordering::Relaxed)).collect();
+        assert!(net.iter().all(|&n| n == 0 || n == 1));
+        let mut keys = Vec::new();
+        collect_leaves(map.root, 0, usize::MAX, &mut keys);
+        let expected: Vec<_> = (0..64).filter(|&key| net[key] == 1).collect();
+        assert_eq!(keys, expected);
+    }
+}
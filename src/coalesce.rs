@@ -0,0 +1,114 @@
+// Clearing a finished descriptor out of two words at once, enabled by the
+// experimental `coalesce` feature.
+//
+// Both words of a 16 byte aligned pair are replaced with one CMPXCHG16B while
+// every other access to them is 8 bytes wide. Mixed-size atomic accesses to
+// the same memory are undefined in the Rust memory model, this only holds up
+// because x86_64 orders them like any other locked instruction.
+
+use crate::atomic::{AtomicBits, Bits};
+use once_cell::sync::Lazy;
+#[cfg(test)]
+use std::cell::Cell;
+use std::{arch::asm, mem};
+
+static CX16_AVAILABLE: Lazy<bool> = Lazy::new(|| is_x86_feature_detected!("cmpxchg16b"));
+
+#[cfg(test)]
+thread_local! {
+    // pairs the thread replaced, for tests to tell that the pair path ran
+    static SWAPPED_PAIRS: Cell<usize> = const { Cell::new(0) };
+}
+
+#[inline]
+pub fn available() -> bool {
+    *CX16_AVAILABLE
+}
+
+// whether the two words are the halves of one 16 byte aligned pair
+#[inline]
+pub fn is_pair(first: &AtomicBits, second: &AtomicBits) -> bool {
+    let first = first as *const AtomicBits as usize;
+    let second = second as *const AtomicBits as usize;
+    first % 16 == 0 && second == first + mem::size_of::<AtomicBits>()
+}
+
+// Replaces both words of the pair starting at `first` if they hold `expected`,
+// with a single CMPXCHG16B. Returns whether it did.
+//
+// safety: CMPXCHG16B has to be available and `first` has to start a pair, see
+// `is_pair`
+#[inline]
+pub unsafe fn cas_pair(first: &AtomicBits, expected: [Bits; 2], new: [Bits; 2]) -> bool {
+    let swapped: u8;
+    // rbx is reserved by LLVM, the low half of `new` goes through another
+    // register and is swapped in around the instruction
+    asm!(
+        "xchg {new_lo}, rbx",
+        "lock cmpxchg16b xmmword ptr [{dst}]",
+        "sete {swapped}",
+        "mov rbx, {new_lo}",
+        dst = in(reg) first as *const AtomicBits,
+        new_lo = inout(reg) new[0].into_ptr::<u8>().expose_provenance() => _,
+        swapped = out(reg_byte) swapped,
+        inout("rax") expected[0].into_usize() => _,
+        inout("rdx") expected[1].into_usize() => _,
+        in("rcx") new[1].into_ptr::<u8>().expose_provenance(),
+        options(nostack),
+    );
+    #[cfg(test)]
+    if swapped != 0 {
+        SWAPPED_PAIRS.with(|pairs| pairs.set(pairs.get() + 1));
+    }
+    swapped != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backoff, domain::DEFAULT_DOMAIN, mwcas::Entry, Atomic};
+
+    #[repr(C, align(16))]
+    struct Pair(Atomic<usize>, Atomic<usize>);
+
+    #[test]
+    fn test_cas_pair() {
+        if !available() {
+            return;
+        }
+        let pair = Pair(Atomic::new(1), Atomic::new(2));
+        let (first, second) = (pair.0.as_atomic_bits(), pair.1.as_atomic_bits());
+        assert!(is_pair(first, second));
+        assert!(!is_pair(second, first));
+        let swapped =
+            unsafe { cas_pair(first, [1.into(), 3.into()], [5.into(), 6.into()]) };
+        assert!(!swapped);
+        assert_eq!((pair.0.load(), pair.1.load()), (1, 2));
+        let swapped =
+            unsafe { cas_pair(first, [1.into(), 2.into()], [5.into(), 6.into()]) };
+        assert!(swapped);
+        assert_eq!((pair.0.load(), pair.1.load()), (5, 6));
+
+        // the descriptor is cleared out of both words at once, run without
+        // `cas2` so that a transaction can't take its place
+        let swapped = || SWAPPED_PAIRS.with(Cell::get);
+        let run = |expected: [usize; 2], new: [usize; 2]| {
+            let entries = [
+                Entry::new(&pair.0, expected[0], new[0]),
+                Entry::new(&pair.1, expected[1], new[1]),
+            ];
+            #[cfg(feature = "per-cpu")]
+            let _lease = crate::per_cpu::Lease::acquire();
+            let descriptor_ptr = DEFAULT_DOMAIN.casn.make_descriptor(None, &entries);
+            DEFAULT_DOMAIN
+                .casn
+                .help(None, descriptor_ptr, false, backoff::global())
+        };
+        let before = swapped();
+        assert!(run([5, 6], [7, 8]));
+        assert_eq!(swapped(), before + 1);
+        // the second word doesn't match, the first one is cleared alone
+        assert!(!run([7, 7], [0, 0]));
+        assert_eq!((pair.0.load(), pair.1.load()), (7, 8));
+    }
+}
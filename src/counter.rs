@@ -0,0 +1,95 @@
+use crate::{
+    cas_n_array,
+    thread_local::{Slot, ThreadId},
+    Atomic,
+};
+use std::array;
+
+// the stripes are summed with a double collect, not a `cas_n`, so their
+// number isn't bound by `MAX_ENTRIES`
+const STRIPES: usize = 4;
+
+/// A counter spread over stripes so that threads adding to it rarely touch
+/// the same word.
+///
+/// A plain striped counter can only sum its stripes one after the other, and
+/// the total it returns may never have been the value of the counter.
+/// `snapshot` reads the stripes twice without writing them and retries until
+/// both passes agree. Stripes only grow, so a stripe that reads the same
+/// twice didn't change in between, and the total is exact at the moment the
+/// first pass ended.
+///
+/// There are 4 stripes and a thread adds to the one at its id modulo 4, so
+/// with more than 4 threads adding at once some of them share a stripe and
+/// contend on it.
+pub struct MwCounter {
+    stripes: [Slot<Atomic<usize>>; STRIPES],
+}
+
+impl MwCounter {
+    pub fn new() -> Self {
+        Self {
+            stripes: array::from_fn(|_| Slot::new(Atomic::new(0))),
+        }
+    }
+
+    pub fn add(&self, n: usize) {
+        let stripe = &self.stripes[ThreadId::current().as_u16() as usize % STRIPES];
+        loop {
+            let value = stripe.load();
+            let sum = value + n;
+            assert!(sum <= Atomic::<usize>::MAX, "counter overflow");
+            if unsafe { cas_n_array([&**stripe], [value], [sum]) } {
+                return;
+            }
+        }
+    }
+
+    pub fn increment(&self) {
+        self.add(1)
+    }
+
+    /// The total of all the stripes at one point in time.
+    pub fn snapshot(&self) -> usize {
+        let mut values: [usize; STRIPES] = array::from_fn(|i| self.stripes[i].load());
+        loop {
+            let again = array::from_fn(|i| self.stripes[i].load());
+            if again == values {
+                return values.iter().sum();
+            }
+            values = again;
+        }
+    }
+}
+
+impl Default for MwCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_snapshot() {
+        let counter = MwCounter::new();
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    let mut last = 0;
+                    for i in 1..=1000 {
+                        counter.add(2);
+                        // includes the thread's own adds and never goes back
+                        let total = counter.snapshot();
+                        assert!(total >= 2 * i && total > last);
+                        last = total;
+                    }
+                });
+            }
+        });
+        assert_eq!(counter.snapshot(), 8000);
+    }
+}
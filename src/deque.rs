@@ -0,0 +1,120 @@
+//! A lock-free double ended queue, enabled by the `structures` feature.
+
+use crate::list::{self, List, Node};
+use crossbeam_epoch::pin;
+
+/// A queue with pushes and pops at both ends, a `List` whose values are
+/// moved out by the pops instead of being shared.
+///
+/// A pop unlinks the first or last node with a single `cas_n`, so it never
+/// races a concurrent push or pop at the other end on a half updated link,
+/// the case that makes CAS based deques so intricate.
+pub struct Deque<T: 'static> {
+    list: List<T>,
+}
+
+impl<T: Send + 'static> Deque<T> {
+    pub fn new() -> Self {
+        Self { list: List::new() }
+    }
+
+    pub fn push_front(&self, value: T) {
+        self.list.push_front(value, &pin());
+    }
+
+    pub fn push_back(&self, value: T) {
+        self.list.push_back(value, &pin());
+    }
+
+    pub fn pop_front(&self) -> Option<T> {
+        self.pop(List::first)
+    }
+
+    pub fn pop_back(&self) -> Option<T> {
+        self.pop(List::last)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    fn pop(&self, end: fn(&List<T>) -> Option<*const Node<T>>) -> Option<T> {
+        let guard = pin();
+        loop {
+            let node = end(&self.list)?;
+            // a node removed in the meantime has moved away from the end
+            if unsafe { self.list.unlink(node) } {
+                unsafe {
+                    let value = Node::take(node);
+                    list::retire(&guard, node, false);
+                    return Some(value);
+                }
+            }
+        }
+    }
+}
+
+impl<T: Send + 'static> Default for Deque<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// a pop unlinks its node with a `cas_n` before taking the value out of it, so
+// every value leaves the deque on exactly one thread and no `&T` is handed out
+unsafe impl<T: Send + 'static> Sync for Deque<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_deque() {
+        let deque = Deque::new();
+        assert_eq!(deque.pop_front(), None);
+        deque.push_back(2);
+        deque.push_front(1);
+        deque.push_back(3);
+        assert_eq!(deque.pop_back(), Some(3));
+        assert_eq!(deque.pop_front(), Some(1));
+        assert_eq!(deque.pop_front(), Some(2));
+        assert!(deque.is_empty());
+
+        let strings = Deque::new();
+        strings.push_back(String::from("freed with the deque"));
+    }
+
+    #[test]
+    fn test_concurrent_push_pop() {
+        let deque = Deque::new();
+        let popped: Vec<usize> = thread::scope(|s| {
+            let workers: Vec<_> = (0..4)
+                .map(|t| {
+                    let deque = &deque;
+                    s.spawn(move || {
+                        let mut sum = 0;
+                        for i in 0..1000 {
+                            let value = t * 1000 + i;
+                            if i % 2 == 0 {
+                                deque.push_front(value);
+                            } else {
+                                deque.push_back(value);
+                            }
+                            let popped = if t % 2 == 0 {
+                                deque.pop_front()
+                            } else {
+                                deque.pop_back()
+                            };
+                            sum += popped.unwrap();
+                        }
+                        sum
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap()).collect()
+        });
+        assert!(deque.is_empty());
+        assert_eq!(popped.iter().sum::<usize>(), (0..4000).sum());
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    atomic::{Atomic, Word},
+    backoff,
+    mwcas::{cas2_entries, CasNDescriptor, Entry, CASN},
+};
+#[cfg(not(loom))]
+use once_cell::sync::Lazy;
+
+// the domain behind the free functions and the methods of `Atomic`
+#[cfg(not(loom))]
+pub(crate) static DEFAULT_DOMAIN: Lazy<MwCasDomain> =
+    Lazy::new(|| MwCasDomain::global(crate::thread_local::MAX_THREADS));
+
+// loom atomics live for one execution of a model, so the model gets a fresh
+// domain every time
+#[cfg(loom)]
+loom::lazy_static! {
+    pub(crate) static ref DEFAULT_DOMAIN: MwCasDomain =
+        MwCasDomain::global(loom::MAX_THREADS);
+}
+
+/// A set of descriptor tables of its own, isolated from the global one that
+/// `cas2`, `cas_n` and `Atomic::load` use. The tables are freed when the
+/// domain is dropped.
+///
+/// A descriptor pointer found in a word is looked up in the tables of the
+/// domain the word is accessed through, so every word has to be used with a
+/// single domain only, its loads included: `load` and `peek` of the domain
+/// take the place of `Atomic::load` and `Atomic::peek`.
+///
+/// A domain hands out ids of its own to the threads that use it, independent
+/// of their global `ThreadId`. A thread takes the lowest free one on its first
+/// operation in the domain and holds it until it exits. Every table has a
+/// slot per id, the first 1024 are allocated with the domain and more are
+/// added in blocks of 1024 once more threads use it at a time,
+/// `with_max_threads` sizes them for fewer threads.
+pub struct MwCasDomain {
+    pub(crate) casn: CasNDescriptor,
+}
+
+impl MwCasDomain {
+    pub fn new() -> Self {
+        Self::with_max_threads(crate::thread_local::MAX_THREADS)
+    }
+
+    /// A domain with slots for `max_threads` threads at a time, up to 1024.
+    ///
+    /// The slots belong to the ids of the domain, see `MwCasDomain`, so any
+    /// number of threads can use it over time as long as no more than
+    /// `max_threads` of them are alive at once. Past that `CASN::try_exec` in
+    /// the domain fails with `CasNError::ThreadLimit` and the other operations
+    /// panic, only a domain for 1024 threads grows instead.
+    ///
+    /// With `per-cpu` the slots are leased per operation instead of indexed
+    /// by thread id, `max_threads` is ignored and the domain is the same as
+    /// `new` makes.
+    pub fn with_max_threads(max_threads: usize) -> Self {
+        Self {
+            casn: CasNDescriptor::with_own_ids(Self::num_slots(max_threads)),
+        }
+    }
+
+    // the tables of the default domain are indexed by the global thread ids
+    fn global(max_threads: usize) -> Self {
+        Self {
+            casn: CasNDescriptor::with_max_threads(Self::num_slots(max_threads)),
+        }
+    }
+
+    // slots are indexed by lease instead of by thread id with `per-cpu`
+    fn num_slots(max_threads: usize) -> usize {
+        #[cfg(feature = "per-cpu")]
+        let max_threads = {
+            let _ = max_threads;
+            crate::per_cpu::num_slots()
+        };
+        max_threads
+    }
+
+    /// `cas2` in this domain.
+    ///
+    /// # Safety
+    ///
+    /// As for `cas2`, the words have to stay alive as long as other threads
+    /// may still help the operation, see `quiesce`. In addition they must not
+    /// be used outside of this domain while it may have a descriptor in them:
+    /// no `Atomic::load`, `Atomic::peek`, `Atomic::settle`, `help_at` or
+    /// operation of another domain, those look the descriptor up in other
+    /// tables. Use `load` and `peek` of the domain instead.
+    #[allow(clippy::too_many_arguments)]
+    pub unsafe fn cas2<T0, T1>(
+        &self,
+        addr0: &Atomic<T0>,
+        addr1: &Atomic<T1>,
+        exp0: T0,
+        exp1: T1,
+        new0: T0,
+        new1: T1,
+    ) -> bool
+    where
+        T0: Word,
+        T1: Word,
+    {
+        cas2_entries(
+            &self.casn,
+            Entry::new(addr0, exp0, new0),
+            Entry::new(addr1, exp1, new1),
+            false,
+        )
+    }
+
+    /// `cas_n` in this domain, see `CASN::set_domain` for the other flavours.
+    ///
+    /// # Safety
+    ///
+    /// Same as for `cas2` of the domain.
+    pub unsafe fn cas_n<T>(
+        &self,
+        addresses: &[&Atomic<T>],
+        expected: &[T],
+        new: &[T],
+    ) -> bool
+    where
+        T: Word,
+    {
+        let mut cas_n = CASN::from_slices(addresses, expected, new);
+        cas_n.set_domain(self);
+        cas_n.exec()
+    }
+
+    /// `Atomic::load` for a word of this domain.
+    pub fn load<T: Word>(&self, atomic: &Atomic<T>) -> T {
+        self.casn
+            .read_with_backoff(atomic.as_atomic_bits(), backoff::global())
+            .into()
+    }
+
+    /// `Atomic::peek` for a word of this domain.
+    pub fn peek<T: Word>(&self, atomic: &Atomic<T>) -> T {
+        self.casn.peek(atomic.as_atomic_bits()).into()
+    }
+
+    /// `quiesce` for the descriptors of this domain.
+    #[cfg(not(feature = "per-cpu"))]
+    pub fn quiesce(&self) {
+        self.casn.invalidate();
+        self.casn.rdcss().invalidate(self.casn.owner(None));
+    }
+
+    /// `quiesce` for the descriptors of this domain.
+    ///
+    /// Past operations of the calling thread may have used any slot, so all
+    /// of them are invalidated, waiting for the operations running in them,
+    /// and the fallback slot of its thread id.
+    #[cfg(feature = "per-cpu")]
+    pub fn quiesce(&self) {
+        let fallback = crate::ThreadId::registered().map(crate::per_cpu::fallback_slot);
+        for slot in (0..crate::per_cpu::num_slots()).chain(fallback) {
+            let _lease = crate::per_cpu::Lease::acquire_slot(slot);
+            self.casn.invalidate();
+            self.casn.rdcss().invalidate(None);
+        }
+    }
+}
+
+impl Default for MwCasDomain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_domains() {
+        let first = MwCasDomain::new();
+        let second = MwCasDomain::new();
+        let words: Vec<_> = (0..4).map(|_| Atomic::new(0usize)).collect();
+        let (ours, theirs) = words.split_at(2);
+
+        thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        loop {
+                            let (v0, v1) = (first.load(&ours[0]), first.load(&ours[1]));
+                            if unsafe {
+                                first.cas2(&ours[0], &ours[1], v0, v1, v0 + 1, v1 + 1)
+                            } {
+                                break;
+                            }
+                        }
+                        loop {
+                            let exp = [second.load(&theirs[0]), second.load(&theirs[1])];
+                            let new = [exp[0] + 1, exp[1] + 1];
+                            let addresses = [&theirs[0], &theirs[1]];
+                            if unsafe { second.cas_n(&addresses, &exp, &new) } {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert!(ours.iter().all(|w| first.load(w) == 2000));
+        assert!(theirs.iter().all(|w| second.load(w) == 2000));
+        assert_eq!(first.peek(&ours[0]), 2000);
+
+        let mut cas_n = CASN::new();
+        cas_n.set_domain(&second);
+        cas_n.add_unchecked(&theirs[0], 2000, 0);
+        cas_n.add_unchecked(&theirs[1], 2000, 0);
+        assert!(unsafe { cas_n.exec() });
+        assert_eq!((second.load(&theirs[0]), second.load(&theirs[1])), (0, 0));
+    }
+
+    #[test]
+    fn test_with_max_threads() {
+        let domain = MwCasDomain::with_max_threads(2);
+        let (first, second) = (Atomic::new(0usize), Atomic::new(0usize));
+        // every thread takes an id the ones before gave back, however large
+        // its global id is. Joining waits for the thread local destructors
+        // that give the id back.
+        for i in 0..8 {
+            thread::scope(|s| {
+                s.spawn(|| {
+                    assert!(unsafe { domain.cas2(&first, &second, i, i, i + 1, i + 1) });
+                })
+                .join()
+                .unwrap();
+            });
+        }
+        assert_eq!((domain.load(&first), domain.load(&second)), (8, 8));
+    }
+
+    #[test]
+    #[cfg(not(any(
+        feature = "per-cpu",
+        all(feature = "heap-descriptors", not(feature = "wait-free"))
+    )))]
+    fn test_thread_limit() {
+        let domain = MwCasDomain::with_max_threads(1);
+        let word = Atomic::new(0usize);
+        assert!(unsafe { domain.cas_n(&[&word], &[0], &[1]) });
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut cas_n = CASN::new();
+                cas_n.set_domain(&domain);
+                cas_n.add_unchecked(&word, 1, 2);
+                assert_eq!(
+                    unsafe { cas_n.try_exec() },
+                    Err(crate::CasNError::ThreadLimit)
+                );
+            });
+        });
+        assert_eq!(domain.load(&word), 1);
+    }
+}
@@ -0,0 +1,190 @@
+//! Helpers for words that hold pointers managed by `crossbeam_epoch`, enabled
+//! by the `epoch` feature.
+
+use crate::{cas2, cas_n, Atomic};
+use crossbeam_epoch::{Guard, Owned, Shared};
+
+/// Loads a pointer word as a `Shared` that can't outlive `guard`, helping
+/// whatever operation is updating the word first.
+pub fn load<'g, T: 'static>(
+    atomic: &Atomic<*const T>,
+    _guard: &'g Guard,
+) -> Shared<'g, T> {
+    Shared::from(atomic.load())
+}
+
+/// `cas2` over two pointer words that hands the replaced pointers to `guard`
+/// for destruction once it succeeded, and returns the new pointers back to
+/// the caller if it failed.
+///
+/// # Safety
+///
+/// `new0` and `new1` must not be reachable by any other thread, `exp0` and
+/// `exp1` must not be retired by anybody else.
+pub unsafe fn cas2_retire<'g, T0, T1>(
+    addr0: &Atomic<*const T0>,
+    addr1: &Atomic<*const T1>,
+    exp0: Shared<'g, T0>,
+    exp1: Shared<'g, T1>,
+    new0: Shared<'g, T0>,
+    new1: Shared<'g, T1>,
+    guard: &'g Guard,
+) -> Result<(), (Owned<T0>, Owned<T1>)>
+where
+    T0: 'static,
+    T1: 'static,
+{
+    if cas2(
+        addr0,
+        addr1,
+        exp0.as_raw(),
+        exp1.as_raw(),
+        new0.as_raw(),
+        new1.as_raw(),
+    ) {
+        guard.defer_destroy(exp0);
+        guard.defer_destroy(exp1);
+        Ok(())
+    } else {
+        Err((new0.into_owned(), new1.into_owned()))
+    }
+}
+
+/// `cas2` that takes ownership of the new pointers: they're returned as
+/// `Shared` once published, or handed back if the operation failed.
+#[allow(clippy::missing_safety_doc, clippy::type_complexity)]
+pub unsafe fn cas2_owned<'g, T0, T1>(
+    addr0: &Atomic<*const T0>,
+    addr1: &Atomic<*const T1>,
+    exp0: Shared<'g, T0>,
+    exp1: Shared<'g, T1>,
+    new0: Owned<T0>,
+    new1: Owned<T1>,
+    guard: &'g Guard,
+) -> Result<(Shared<'g, T0>, Shared<'g, T1>), (Owned<T0>, Owned<T1>)>
+where
+    T0: 'static,
+    T1: 'static,
+{
+    let new0 = new0.into_shared(guard);
+    let new1 = new1.into_shared(guard);
+    if cas2(
+        addr0,
+        addr1,
+        exp0.as_raw(),
+        exp1.as_raw(),
+        new0.as_raw(),
+        new1.as_raw(),
+    ) {
+        Ok((new0, new1))
+    } else {
+        Err((new0.into_owned(), new1.into_owned()))
+    }
+}
+
+/// `cas_n` version of `cas2_owned`.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn cas_n_owned<'g, T: 'static>(
+    addresses: &[&Atomic<*const T>],
+    expected: &[Shared<'g, T>],
+    new: Vec<Owned<T>>,
+    guard: &'g Guard,
+) -> Result<Vec<Shared<'g, T>>, Vec<Owned<T>>> {
+    let expected: Vec<_> = expected.iter().map(Shared::as_raw).collect();
+    let new: Vec<_> = new
+        .into_iter()
+        .map(|owned| owned.into_shared(guard))
+        .collect();
+    let new_raw: Vec<_> = new.iter().map(Shared::as_raw).collect();
+    if cas_n(addresses, &expected, &new_raw) {
+        Ok(new)
+    } else {
+        Err(new.into_iter().map(|shared| shared.into_owned()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_epoch::pin;
+
+    #[test]
+    fn test_cas2_retire() {
+        let first = Atomic::<*const u64>::new(Box::into_raw(Box::new(0)));
+        let second = Atomic::<*const u64>::new(Box::into_raw(Box::new(0)));
+
+        let guard = pin();
+        let exp0 = load(&first, &guard);
+        let exp1 = load(&second, &guard);
+        let new0 = Owned::new(1).into_shared(&guard);
+        let new1 = Owned::new(1).into_shared(&guard);
+        assert!(
+            unsafe { cas2_retire(&first, &second, exp0, exp1, new0, new1, &guard) }
+                .is_ok()
+        );
+
+        let new0 = Owned::new(2).into_shared(&guard);
+        let new1 = Owned::new(2).into_shared(&guard);
+        let (new0, new1) =
+            unsafe { cas2_retire(&first, &second, exp0, exp1, new0, new1, &guard) }
+                .unwrap_err();
+        assert_eq!((*new0, *new1), (2, 2));
+        drop(guard);
+
+        unsafe {
+            assert_eq!(*first.load(), 1);
+            assert_eq!(*second.load(), 1);
+            drop(Box::from_raw(first.load() as *mut u64));
+            drop(Box::from_raw(second.load() as *mut u64));
+        }
+    }
+
+    #[test]
+    fn test_cas_n_owned() {
+        let words: Vec<_> = (0..3)
+            .map(|_| Atomic::<*const u64>::new(Box::into_raw(Box::new(0))))
+            .collect();
+        let addresses: Vec<_> = words.iter().collect();
+
+        let guard = pin();
+        let expected: Vec<_> = words.iter().map(|w| load(w, &guard)).collect();
+        let new = (0..3).map(|_| Owned::new(1)).collect();
+        let published =
+            unsafe { cas_n_owned(&addresses, &expected, new, &guard) }.unwrap();
+        for (word, shared) in words.iter().zip(&published) {
+            assert_eq!(word.load(), shared.as_raw());
+        }
+
+        let new = (0..3).map(|_| Owned::new(2)).collect();
+        let returned =
+            unsafe { cas_n_owned(&addresses, &expected, new, &guard) }.unwrap_err();
+        assert!(returned.iter().all(|owned| **owned == 2));
+
+        let (new0, new1) = (Owned::new(3), Owned::new(3));
+        let (new0, new1) = unsafe {
+            cas2_owned(
+                &words[0],
+                &words[1],
+                published[0],
+                published[1],
+                new0,
+                new1,
+                &guard,
+            )
+        }
+        .unwrap();
+        assert_eq!((*unsafe { new0.deref() }, *unsafe { new1.deref() }), (3, 3));
+        unsafe {
+            guard.defer_destroy(published[0]);
+            guard.defer_destroy(published[1]);
+        }
+        for shared in expected {
+            unsafe { guard.defer_destroy(shared) };
+        }
+        drop(guard);
+
+        for word in words {
+            unsafe { drop(Box::from_raw(word.load() as *mut u64)) };
+        }
+    }
+}
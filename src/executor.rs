@@ -0,0 +1,66 @@
+//! Thread registration for async executors, enabled by the `async` feature.
+//!
+//! Tasks move between worker threads, which is fine as descriptors belong to
+//! threads and a task never yields in the middle of an operation. Workers are
+//! registered for as long as they live through `on_worker_start` and
+//! `on_worker_stop`, e.g.
+//! `Builder::new_multi_thread().on_thread_start(on_worker_start).on_thread_stop(on_worker_stop)`.
+//! Threads of blocking pools come and go, closures run on them should use
+//! `with_registered` so their ids are given back as soon as they are done.
+
+use crate::thread_local::{register_current_thread, ThreadId, ThreadToken};
+use std::cell::RefCell;
+
+thread_local! {
+    static WORKER: RefCell<Option<ThreadToken>> = const { RefCell::new(None) };
+}
+
+/// Registers the calling worker thread until `on_worker_stop`.
+pub fn on_worker_start() {
+    let token = register_current_thread();
+    WORKER.with(|worker| *worker.borrow_mut() = Some(token));
+}
+
+/// Unregisters a worker thread registered with `on_worker_start`.
+pub fn on_worker_stop() {
+    let _ = WORKER.try_with(|worker| worker.borrow_mut().take());
+}
+
+/// Runs `f` with the calling thread registered. A thread that wasn't
+/// registered before is unregistered again once `f` returns or panics.
+pub fn with_registered<F: FnOnce() -> R, R>(f: F) -> R {
+    let _token = match ThreadId::registered() {
+        Some(_) => None,
+        None => Some(register_current_thread()),
+    };
+    f()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cas2, Atomic};
+    use std::thread;
+
+    #[test]
+    fn test_with_registered() {
+        let (first, second) = (Atomic::new(0usize), Atomic::new(0usize));
+        thread::scope(|s| {
+            s.spawn(|| {
+                with_registered(|| {
+                    assert!(unsafe { cas2(&first, &second, 0, 0, 1, 1) });
+                    assert!(ThreadId::registered().is_some());
+                });
+                assert!(ThreadId::registered().is_none());
+            });
+            s.spawn(|| {
+                on_worker_start();
+                with_registered(|| {});
+                assert!(ThreadId::registered().is_some());
+                on_worker_stop();
+                assert!(ThreadId::registered().is_none());
+            });
+        });
+        assert_eq!((first.load(), second.load()), (1, 1));
+    }
+}
@@ -0,0 +1,194 @@
+//! C interface over `Atomic<usize>` words, enabled by the `ffi` feature. The
+//! declarations for C are in `include/mw_cas.h`, the shared library is built
+//! with `cargo rustc --release --features ffi --crate-type cdylib`.
+//!
+//! Operations return `MWCAS_SUCCEEDED` or `MWCAS_FAILED`, or a negative
+//! error code instead of panicking across the boundary.
+
+use crate::{
+    mwcas::MAX_ENTRIES, register_current_thread, try_cas_n, Atomic, CasNError, ThreadId,
+    ThreadToken,
+};
+use std::{os::raw::c_int, ptr, slice};
+
+pub const MWCAS_SUCCEEDED: c_int = 1;
+pub const MWCAS_FAILED: c_int = 0;
+/// A value is larger than `MWCAS_MAX_VALUE`.
+pub const MWCAS_INVALID_OPERAND: c_int = -1;
+/// The same word appears twice with different expected or new values.
+pub const MWCAS_DUPLICATE_ADDRESS: c_int = -2;
+/// The calling thread couldn't be registered, all thread ids are taken.
+pub const MWCAS_THREAD_LIMIT: c_int = -3;
+/// More words than `MWCAS_MAX_ENTRIES`.
+pub const MWCAS_TOO_MANY_ENTRIES: c_int = -4;
+/// The operation ran out of its retry budget.
+pub const MWCAS_TIMEOUT: c_int = -5;
+
+pub const MWCAS_MAX_VALUE: usize = Atomic::<usize>::MAX;
+pub const MWCAS_MAX_ENTRIES: usize = MAX_ENTRIES;
+
+fn error_code(err: CasNError) -> c_int {
+    match err {
+        CasNError::InvalidOperand => MWCAS_INVALID_OPERAND,
+        CasNError::DuplicateAddress => MWCAS_DUPLICATE_ADDRESS,
+        CasNError::ThreadLimit => MWCAS_THREAD_LIMIT,
+        CasNError::Timeout => MWCAS_TIMEOUT,
+    }
+}
+
+fn status(result: Result<bool, CasNError>) -> c_int {
+    match result {
+        Ok(true) => MWCAS_SUCCEEDED,
+        Ok(false) => MWCAS_FAILED,
+        Err(err) => error_code(err),
+    }
+}
+
+/// Allocates a word holding `value`, null if the value is too large.
+#[no_mangle]
+pub extern "C" fn mwcas_alloc_atomic(value: usize) -> *mut Atomic<usize> {
+    if value > MWCAS_MAX_VALUE {
+        return ptr::null_mut();
+    }
+    Box::into_raw(Box::new(Atomic::new(value)))
+}
+
+/// Frees a word from `mwcas_alloc_atomic`.
+///
+/// # Safety
+///
+/// No thread may access the word anymore, operations that ran on it must
+/// have been completed, see `quiesce`.
+#[no_mangle]
+pub unsafe extern "C" fn mwcas_free_atomic(atomic: *mut Atomic<usize>) {
+    if !atomic.is_null() {
+        drop(Box::from_raw(atomic));
+    }
+}
+
+/// Stores the value of the word in `value`. Reading may have to help the
+/// operation installed in the word, which takes a thread id.
+///
+/// # Safety
+///
+/// `atomic` has to come from `mwcas_alloc_atomic` and not be freed yet,
+/// `value` has to be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn mwcas_read(
+    atomic: *const Atomic<usize>,
+    value: *mut usize,
+) -> c_int {
+    match ThreadId::try_register() {
+        Ok(_) => {
+            *value = (*atomic).load();
+            MWCAS_SUCCEEDED
+        },
+        Err(err) => error_code(err),
+    }
+}
+
+/// # Safety
+///
+/// Same as `mwcas_read` for both words.
+#[no_mangle]
+pub unsafe extern "C" fn mwcas_cas2(
+    addr0: *const Atomic<usize>,
+    addr1: *const Atomic<usize>,
+    exp0: usize,
+    exp1: usize,
+    new0: usize,
+    new1: usize,
+) -> c_int {
+    status(try_cas_n(&[&*addr0, &*addr1], &[exp0, exp1], &[new0, new1]))
+}
+
+/// Sets the `len` words in `addresses` to the values in `new` if all of them
+/// hold the values in `expected`.
+///
+/// # Safety
+///
+/// The arrays have `len` elements, the words are as for `mwcas_read`.
+#[no_mangle]
+pub unsafe extern "C" fn mwcas_casn(
+    addresses: *const *const Atomic<usize>,
+    expected: *const usize,
+    new: *const usize,
+    len: usize,
+) -> c_int {
+    if len > MAX_ENTRIES {
+        return MWCAS_TOO_MANY_ENTRIES;
+    }
+    if len == 0 {
+        return MWCAS_SUCCEEDED;
+    }
+    let addresses: Vec<&Atomic<usize>> = slice::from_raw_parts(addresses, len)
+        .iter()
+        .map(|addr| &**addr)
+        .collect();
+    let expected = slice::from_raw_parts(expected, len);
+    let new = slice::from_raw_parts(new, len);
+    status(try_cas_n(&addresses, expected, new))
+}
+
+/// Registers the calling thread until `mwcas_unregister_thread`, for foreign
+/// threads whose exit Rust doesn't see. Returns null if all thread ids are
+/// taken.
+#[no_mangle]
+pub extern "C" fn mwcas_register_thread() -> *mut ThreadToken {
+    match ThreadId::try_register() {
+        Ok(_) => Box::into_raw(Box::new(register_current_thread())),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `token` has to come from `mwcas_register_thread` on the calling thread.
+#[no_mangle]
+pub unsafe extern "C" fn mwcas_unregister_thread(token: *mut ThreadToken) {
+    if !token.is_null() {
+        drop(Box::from_raw(token));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        unsafe {
+            let token = mwcas_register_thread();
+            assert!(!token.is_null());
+            let a = mwcas_alloc_atomic(1);
+            let b = mwcas_alloc_atomic(2);
+            assert!(mwcas_alloc_atomic(MWCAS_MAX_VALUE + 1).is_null());
+
+            assert_eq!(mwcas_cas2(a, b, 1, 2, 3, 4), MWCAS_SUCCEEDED);
+            assert_eq!(mwcas_cas2(a, b, 1, 2, 3, 4), MWCAS_FAILED);
+            assert_eq!(mwcas_cas2(a, a, 3, 3, 5, 6), MWCAS_DUPLICATE_ADDRESS);
+            let addresses = [a as *const _, b as *const _];
+            assert_eq!(
+                mwcas_casn(addresses.as_ptr(), [3, 4].as_ptr(), [5, 6].as_ptr(), 2),
+                MWCAS_SUCCEEDED
+            );
+            let (mut a_value, mut b_value) = (0, 0);
+            assert_eq!(mwcas_read(a, &mut a_value), MWCAS_SUCCEEDED);
+            assert_eq!(mwcas_read(b, &mut b_value), MWCAS_SUCCEEDED);
+            assert_eq!((a_value, b_value), (5, 6));
+            assert_eq!(
+                mwcas_casn(
+                    addresses.as_ptr(),
+                    [5, 6].as_ptr(),
+                    [usize::MAX, 0].as_ptr(),
+                    2
+                ),
+                MWCAS_INVALID_OPERAND
+            );
+
+            mwcas_free_atomic(a);
+            mwcas_free_atomic(b);
+            mwcas_unregister_thread(token);
+        }
+    }
+}
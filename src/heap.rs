@@ -0,0 +1,38 @@
+// Heap allocated descriptors, enabled by the `heap-descriptors` feature.
+//
+// Every operation allocates its descriptors and retires them to
+// crossbeam-epoch once it's done, so a descriptor pointer is its address
+// instead of a thread id and a sequence number, and threads never have to be
+// registered. Every entry point that may come across a descriptor pins the
+// epoch before loading any word.
+
+use crate::atomic::Bits;
+use crossbeam_epoch::Guard;
+
+pub(crate) fn into_ptr<T>(descriptor: Box<T>, mark: usize) -> Bits {
+    let ptr = Box::into_raw(descriptor);
+    debug_assert_eq!(ptr.addr() & Bits::RESERVED_MASK, 0);
+    Bits::from_ptr(ptr).with_mark(mark)
+}
+
+// safety: `ptr` comes from `into_ptr::<T>` and the calling thread was pinned
+// before it loaded `ptr` from a word
+pub(crate) unsafe fn deref<'g, T>(ptr: Bits) -> &'g T {
+    &*ptr
+        .into_ptr::<T>()
+        .map_addr(|addr| addr & !Bits::RESERVED_MASK)
+}
+
+// Frees the descriptor after a grace period, every thread that may have
+// loaded `ptr` from a word was pinned before it did and unpins after it's done
+// with it. RDCSS descriptors point to the status of a CASN descriptor, but
+// the thread installing one stays pinned until it's removed, so the epoch
+// can't move far enough to free the status under a thread that loaded it.
+//
+// safety: `ptr` comes from `into_ptr::<T>`, isn't installed in any word and
+// won't be installed again
+pub(crate) unsafe fn retire<T: 'static>(guard: &Guard, ptr: Bits) {
+    guard.defer_unchecked(move || {
+        drop(Box::from_raw(deref::<T>(ptr) as *const T as *mut T))
+    });
+}
@@ -0,0 +1,32 @@
+use once_cell::sync::Lazy;
+use std::arch::asm;
+
+// number of times a transaction is retried before falling back to descriptors
+pub const MAX_ATTEMPTS: usize = 3;
+
+const XBEGIN_STARTED: u32 = !0;
+
+static RTM_AVAILABLE: Lazy<bool> = Lazy::new(|| is_x86_feature_detected!("rtm"));
+
+#[inline]
+pub fn available() -> bool {
+    *RTM_AVAILABLE
+}
+
+// Starts a transaction. Returns `true` inside of the transaction, if it aborts
+// execution continues from here again with all memory effects rolled back and
+// `false` is returned.
+//
+// safety: RTM has to be available
+#[inline]
+pub unsafe fn begin() -> bool {
+    let mut status = XBEGIN_STARTED;
+    asm!("xbegin 2f", "2:", inout("eax") status, options(nostack));
+    status == XBEGIN_STARTED
+}
+
+// safety: has to be called inside of a transaction started with `begin`
+#[inline]
+pub unsafe fn end() {
+    asm!("xend", options(nostack));
+}
@@ -0,0 +1,82 @@
+//! Bit layout of descriptor pointers. From the top of the word down a
+//! descriptor pointer holds the id of the thread owning the descriptor, the
+//! sequence number the descriptor had when the pointer was made and the mark
+//! telling which kind of descriptor it is. Plain values keep the mark bits
+//! clear, integers by being shifted past them.
+//!
+//! ```text
+//!  63        50 49                                       2 1    0
+//! +------------+------------------------------------------+------+
+//! |    tid     |                   seq                    | mark |
+//! +------------+------------------------------------------+------+
+//! ```
+//!
+//! Widening the thread ids or adding mark bits only changes
+//! `DescriptorLayout`, the fields have to take up the whole word. The
+//! `wide-thread-ids` feature moves two bits from the sequence number to the
+//! thread id, for 65536 threads with 46 bit sequence numbers.
+
+/// A descriptor pointer with `T` bits of thread id, `S` bits of sequence
+/// number and `M` mark bits.
+pub(crate) struct Layout<const T: usize, const S: usize, const M: usize>;
+
+impl<const T: usize, const S: usize, const M: usize> Layout<T, S, M> {
+    pub(crate) const MARK_BITS: usize = M;
+    pub(crate) const MARK_MASK: usize = (1 << M) - 1;
+    pub(crate) const SEQ_BITS: usize = S;
+    pub(crate) const SEQ_MASK: usize = (1 << S) - 1;
+    pub(crate) const TID_SHIFT: usize = S + M;
+    pub(crate) const MAX_TIDS: usize = 1 << T;
+
+    const FILLS_WORD: () = assert!(
+        T + S + M == usize::BITS as usize,
+        "the fields of a descriptor pointer have to fill the word"
+    );
+
+    pub(crate) fn pack(tid: usize, seq: usize) -> usize {
+        debug_assert!(tid < Self::MAX_TIDS && seq <= Self::SEQ_MASK);
+        tid << Self::TID_SHIFT | seq << M
+    }
+
+    pub(crate) fn tid(word: usize) -> usize {
+        word >> Self::TID_SHIFT
+    }
+
+    pub(crate) fn seq(word: usize) -> usize {
+        (word >> M) & Self::SEQ_MASK
+    }
+
+    pub(crate) fn mark(word: usize) -> usize {
+        word & Self::MARK_MASK
+    }
+}
+
+/// The layout of every descriptor pointer in the crate.
+#[cfg(not(feature = "wide-thread-ids"))]
+pub(crate) type DescriptorLayout = Layout<14, 48, 2>;
+#[cfg(feature = "wide-thread-ids")]
+pub(crate) type DescriptorLayout = Layout<16, 46, 2>;
+
+const _: () = DescriptorLayout::FILLS_WORD;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields_round_trip() {
+        type Wide = Layout<16, 45, 3>;
+        let () = Wide::FILLS_WORD;
+        let word = Wide::pack(Wide::MAX_TIDS - 1, Wide::SEQ_MASK) | 5;
+        assert_eq!(word, usize::MAX - 2);
+        assert_eq!(Wide::tid(word), Wide::MAX_TIDS - 1);
+        assert_eq!(Wide::seq(word), Wide::SEQ_MASK);
+        assert_eq!(Wide::mark(word), 5);
+
+        let last_tid = DescriptorLayout::MAX_TIDS - 1;
+        let word = DescriptorLayout::pack(last_tid, DescriptorLayout::SEQ_MASK) | 2;
+        assert_eq!(DescriptorLayout::tid(word), last_tid);
+        assert_eq!(DescriptorLayout::seq(word), DescriptorLayout::SEQ_MASK);
+        assert_eq!(DescriptorLayout::mark(word), 2);
+    }
+}
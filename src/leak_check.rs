@@ -0,0 +1,90 @@
+//! Checks that no descriptor is left behind in a set of words, enabled by the
+//! `test-internals` feature. Every operation removes its descriptors from the
+//! words before it returns, so once all threads are done with the words any
+//! descriptor still found in one is a bug, of this crate or of code that
+//! writes the words behind its back.
+
+use crate::{
+    atomic::{Atomic, AtomicBits, Word},
+    mwcas::CasNDescriptor,
+    rdcss::RDCSSDescriptor,
+    watchdog::describe,
+};
+use std::{fmt::Write, sync::atomic::Ordering};
+
+/// The words to check, each with a name for the report.
+#[derive(Default)]
+pub struct LeakCheck<'a> {
+    words: Vec<(String, &'a AtomicBits)>,
+}
+
+impl<'a> LeakCheck<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register<T: Word>(&mut self, name: impl Into<String>, atomic: &'a Atomic<T>) {
+        self.words.push((name.into(), atomic.as_atomic_bits()));
+    }
+
+    /// Panics naming every word that holds a descriptor, with the thread id
+    /// and sequence number of the operation it belongs to. Only meaningful
+    /// while no operation on the words is running.
+    pub fn assert_no_descriptors(&self) {
+        let mut report = String::new();
+        for (name, word) in &self.words {
+            // not `load`, that would help the descriptor out of the word
+            let bits = word.load(Ordering::SeqCst);
+            let kind = match bits.mark() {
+                RDCSSDescriptor::MARK => "RDCSS",
+                CasNDescriptor::MARK => "cas_n",
+                _ => continue,
+            };
+            let _ = writeln!(report, "  {} holds {} {}", name, kind, describe(bits));
+        }
+        assert!(report.is_empty(), "descriptors left in words:\n{}", report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cas2,
+        test_hooks::{Breakpoint, Point},
+        thread_local::ThreadId,
+    };
+    use std::{panic, thread};
+
+    #[test]
+    fn test_leak_check() {
+        let words = [Atomic::new(0usize), Atomic::new(0usize)];
+        let mut check = LeakCheck::new();
+        check.register("first", &words[0]);
+        check.register("second", &words[1]);
+        check.assert_no_descriptors();
+
+        // the owner stops with its descriptor in the first word
+        let installed = Breakpoint::new(Point::Installed(0));
+        thread::scope(|s| {
+            let owner = s.spawn(|| {
+                let _armed = installed.arm();
+                let tid = ThreadId::current().as_u16();
+                assert!(unsafe { cas2(&words[0], &words[1], 0, 0, 1, 1) });
+                tid
+            });
+            installed.wait_paused();
+            let report = panic::catch_unwind(|| check.assert_no_descriptors());
+            installed.resume();
+            let _tid = owner.join().unwrap();
+            let report = report.unwrap_err();
+            let report = report.downcast_ref::<String>().unwrap();
+            assert!(report.contains("first holds cas_n descriptor(mark: 2"));
+            // heap descriptors have their address in place of the tid
+            #[cfg(not(feature = "heap-descriptors"))]
+            assert!(report.contains(&format!("tid: {},", _tid)));
+            assert!(!report.contains("second"));
+        });
+        check.assert_no_descriptors();
+    }
+}
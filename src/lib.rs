@@ -1,9 +1,73 @@
 #![cfg(target_pointer_width = "64")]
 
 mod atomic;
+mod atomic_box;
+mod backoff;
+#[cfg(feature = "structures")]
+pub mod bst;
+#[cfg(all(feature = "coalesce", target_arch = "x86_64", not(loom)))]
+mod coalesce;
+mod counter;
+#[cfg(feature = "structures")]
+pub mod deque;
+mod domain;
+#[cfg(feature = "epoch")]
+pub mod epoch;
+#[cfg(feature = "async")]
+pub mod executor;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "heap-descriptors")]
+mod heap;
+#[cfg(all(feature = "htm", target_arch = "x86_64"))]
+mod htm;
+mod layout;
+#[cfg(feature = "test-internals")]
+pub mod leak_check;
+#[cfg(feature = "epoch")]
+pub mod list;
+#[cfg(feature = "structures")]
+pub mod lru;
 mod mwcas;
+#[cfg(feature = "per-cpu")]
+mod per_cpu;
 pub(crate) mod rdcss;
+mod read_guard;
+pub mod reclaim;
+#[cfg(feature = "structures")]
+pub mod ring_buffer;
 mod sequence_number;
-mod thread_local;
+#[cfg(feature = "structures")]
+pub mod skip_list;
+#[cfg(feature = "structures")]
+pub mod slot_map;
+#[cfg(feature = "stats")]
+pub mod stats;
+mod stm;
+#[cfg(feature = "test-internals")]
+pub mod test_hooks;
+pub mod thread_local;
+mod watchdog;
+#[cfg(feature = "work-stealing")]
+pub mod work_stealing;
 
-pub use mwcas::{cas2, cas_n, Atomic, CASN};
+pub use atomic_box::AtomicBox;
+pub use backoff::{set_backoff_policy, BackoffPolicy, ExponentialBackoff};
+pub use counter::MwCounter;
+pub use domain::MwCasDomain;
+#[cfg(feature = "derive")]
+pub use mw_cas_derive::MwAtomicFields;
+pub use mwcas::{
+    add_n, cas2, cas2_weak, cas_n, cas_n_array, cas_n_bounded, cas_n_entries,
+    cas_n_presorted, cas_n_weak, cas_n_with, compare_exchange_n, exec_batch, help_all,
+    help_at, is_sentinel, quiesce, read_word, swap_n, try_cas_n, with_sentinel,
+    without_sentinel, Atomic, CasEntry, CasNError, CasNFailure, Linked, MwCasOp, Options,
+    Versioned, CASN,
+};
+pub use rdcss::rdcss;
+pub use read_guard::ReadGuard;
+pub use stm::{tx, Tx};
+pub use thread_local::{
+    register_current_thread, set_register_timeout, ThreadId, ThreadToken,
+};
+pub use watchdog::set_livelock_limit;
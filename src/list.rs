@@ -0,0 +1,401 @@
+//! A lock-free doubly linked list on top of `cas2` and `cas_n`, enabled by
+//! the `epoch` feature.
+//!
+//! Every update changes all the links it touches in one operation: an insert
+//! swings the `next` of its predecessor and the `prev` of its successor to the
+//! new node, a remove unlinks the node from both of its neighbours and marks
+//! its own `next` as deleted. So for every node in the list `prev.next` and
+//! `next.prev` point back to it at all times, and an insert after a removed
+//! node can't succeed, it expects an unmarked `next`.
+//!
+//! Nodes are handed out as `NodeRef`s tied to a `crossbeam_epoch::Guard`,
+//! removed nodes are freed once no guard that may still see them is alive.
+
+use crate::{
+    atomic::Bits, cas2, cas_n, is_sentinel, with_sentinel, without_sentinel, Atomic,
+};
+use crossbeam_epoch::Guard;
+use std::{marker::PhantomData, mem::MaybeUninit, ptr};
+
+pub(crate) struct Node<T: 'static> {
+    prev: Atomic<*const Node<T>>,
+    next: Atomic<*const Node<T>>,
+    // uninitialized in the sentinels
+    value: MaybeUninit<T>,
+}
+
+impl<T: 'static> Node<T> {
+    fn alloc(value: MaybeUninit<T>) -> *mut Self {
+        let node = Box::into_raw(Box::new(Self {
+            prev: Atomic::new(ptr::null()),
+            next: Atomic::new(ptr::null()),
+            value,
+        }));
+        debug_assert_eq!(node.addr() & Bits::RESERVED_MASK, 0);
+        node
+    }
+
+    // Moves the value out of a node the caller unlinked, nobody can read it
+    // anymore if the list doesn't hand out references to its values.
+    #[cfg(feature = "structures")]
+    pub(crate) unsafe fn take(node: *const Self) -> T {
+        (*node).value.assume_init_read()
+    }
+
+    // safety: the node is unpublished, nobody else can access its words
+    unsafe fn link(node: *mut Self, prev: *const Self, next: *const Self) {
+        (*node).prev = Atomic::new(prev);
+        (*node).next = Atomic::new(next);
+    }
+}
+
+// Frees a node unlinked by the caller once no guard can see it anymore, with
+// its value unless the caller took it out.
+//
+// safety: `node` was unlinked by the caller, nobody else retires it
+pub(crate) unsafe fn retire<T: Send + 'static>(
+    guard: &Guard,
+    node: *const Node<T>,
+    drop_value: bool,
+) {
+    let node = node as *mut Node<T>;
+    guard.defer_unchecked(move || {
+        let mut node = Box::from_raw(node);
+        if drop_value {
+            node.value.assume_init_drop();
+        }
+    });
+}
+
+// the `next` of a removed node is in the sentinel state
+fn is_deleted<T>(next: *const Node<T>) -> bool {
+    is_sentinel(next)
+}
+
+fn unmarked<T>(next: *const Node<T>) -> *const Node<T> {
+    without_sentinel(next)
+}
+
+pub struct List<T: 'static> {
+    head: *const Node<T>,
+    tail: *const Node<T>,
+}
+
+impl<T: Send + 'static> List<T> {
+    pub fn new() -> Self {
+        let head = Node::alloc(MaybeUninit::uninit());
+        let tail = Node::alloc(MaybeUninit::uninit());
+        unsafe {
+            Node::link(head, ptr::null(), tail);
+            Node::link(tail, head, ptr::null());
+        }
+        Self { head, tail }
+    }
+
+    pub fn push_front<'g>(&'g self, value: T, guard: &'g Guard) -> NodeRef<'g, T> {
+        let node = Node::alloc(MaybeUninit::new(value));
+        // the head is never removed
+        assert!(unsafe { self.link_after(self.head, node) });
+        self.node_ref(node, guard)
+    }
+
+    pub fn push_back<'g>(&'g self, value: T, guard: &'g Guard) -> NodeRef<'g, T> {
+        let node = Node::alloc(MaybeUninit::new(value));
+        let tail = unsafe { &*self.tail };
+        loop {
+            let pred = tail.prev.load();
+            unsafe { Node::link(node, pred, self.tail) };
+            // a predecessor that is being removed has a marked `next`
+            if unsafe { cas2(&(*pred).next, &tail.prev, self.tail, pred, node, node) } {
+                return self.node_ref(node, guard);
+            }
+        }
+    }
+
+    /// Inserts `value` right after `at`, hands it back if `at` was removed.
+    pub fn insert_after<'g>(
+        &'g self,
+        at: NodeRef<'g, T>,
+        value: T,
+        guard: &'g Guard,
+    ) -> Result<NodeRef<'g, T>, T> {
+        self.assert_owns(at);
+        let node = Node::alloc(MaybeUninit::new(value));
+        if unsafe { self.link_after(at.node, node) } {
+            Ok(self.node_ref(node, guard))
+        } else {
+            let node = unsafe { Box::from_raw(node) };
+            Err(unsafe { node.value.assume_init_read() })
+        }
+    }
+
+    /// Removes `at` from the list, its value is dropped once no guard can
+    /// see it anymore. Returns `false` if it was removed already.
+    pub fn remove(&self, at: NodeRef<'_, T>, guard: &Guard) -> bool {
+        self.assert_owns(at);
+        if !unsafe { self.unlink(at.node) } {
+            return false;
+        }
+        unsafe { retire(guard, at.node, true) };
+        true
+    }
+
+    /// The nodes in the list from front to back. Nodes inserted or removed
+    /// while iterating may or may not be seen.
+    pub fn iter<'g>(&'g self, _guard: &'g Guard) -> Iter<'g, T> {
+        Iter {
+            list: self,
+            next: unsafe { &*self.head }.next.load(),
+            _guard: PhantomData,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        unsafe { &*self.head }.next.load() == self.tail
+    }
+
+    // safety: `node` is unpublished and owned by the caller, `pred` is a node
+    // of this list that the caller's guard keeps alive
+    pub(crate) unsafe fn link_after(
+        &self,
+        pred: *const Node<T>,
+        node: *mut Node<T>,
+    ) -> bool {
+        loop {
+            let succ = (*pred).next.load();
+            if is_deleted(succ) {
+                return false;
+            }
+            Node::link(node, pred, succ);
+            // a removal of `succ` changes `pred.next` too
+            if cas2(&(*pred).next, &(*succ).prev, succ, pred, node, node) {
+                return true;
+            }
+        }
+    }
+
+    // Unlinks `node` from its neighbours and marks it deleted. Returns `false`
+    // if somebody else removed it first, the caller retires it otherwise.
+    //
+    // safety: `node` is a value node of this list that the caller's guard
+    // keeps alive
+    pub(crate) unsafe fn unlink(&self, node: *const Node<T>) -> bool {
+        loop {
+            let succ = (*node).next.load();
+            if is_deleted(succ) {
+                return false;
+            }
+            let pred = (*node).prev.load();
+            let deleted = with_sentinel(succ);
+            if cas_n(
+                &[&(*pred).next, &(*node).next, &(*succ).prev],
+                &[node, succ, node],
+                &[succ, deleted, pred],
+            ) {
+                return true;
+            }
+        }
+    }
+
+    // the first value node, if there is one
+    #[cfg(feature = "structures")]
+    pub(crate) fn first(&self) -> Option<*const Node<T>> {
+        let first = unsafe { &*self.head }.next.load();
+        if first == self.tail {
+            None
+        } else {
+            Some(first)
+        }
+    }
+
+    // the last value node, if there is one
+    #[cfg(feature = "structures")]
+    pub(crate) fn last(&self) -> Option<*const Node<T>> {
+        let last = unsafe { &*self.tail }.prev.load();
+        if last == self.head {
+            None
+        } else {
+            Some(last)
+        }
+    }
+
+    fn node_ref<'g>(&'g self, node: *const Node<T>, _guard: &'g Guard) -> NodeRef<'g, T> {
+        NodeRef {
+            list: self,
+            node,
+            _guard: PhantomData,
+        }
+    }
+
+    fn assert_owns(&self, at: NodeRef<'_, T>) {
+        assert!(ptr::eq(at.list, self), "node of a different list");
+    }
+}
+
+impl<T: Send + 'static> Default for List<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: 'static> Drop for List<T> {
+    fn drop(&mut self) {
+        // removed nodes are retired already, only the linked ones are left
+        let mut node = self.head;
+        while !node.is_null() {
+            let mut boxed = unsafe { Box::from_raw(node as *mut Node<T>) };
+            let next = boxed.next.load();
+            if node != self.head && node != self.tail {
+                unsafe { boxed.value.assume_init_drop() };
+            }
+            node = next;
+        }
+    }
+}
+
+unsafe impl<T: Send + 'static> Send for List<T> {}
+// values are shared through `NodeRef`s
+unsafe impl<T: Send + Sync + 'static> Sync for List<T> {}
+
+/// A node of a `List`, valid for as long as the guard it was obtained with.
+/// The node may be removed in the meantime, its value stays readable.
+pub struct NodeRef<'g, T: 'static> {
+    list: &'g List<T>,
+    node: *const Node<T>,
+    _guard: PhantomData<&'g Guard>,
+}
+
+impl<'g, T: 'static> NodeRef<'g, T> {
+    pub fn value(&self) -> &'g T {
+        unsafe { (*self.node).value.assume_init_ref() }
+    }
+
+    /// Whether the node has been removed. Only a hint if other threads
+    /// remove nodes concurrently.
+    pub fn is_removed(&self) -> bool {
+        is_deleted(unsafe { &*self.node }.next.load())
+    }
+}
+
+impl<T: 'static> Clone for NodeRef<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: 'static> Copy for NodeRef<'_, T> {}
+
+pub struct Iter<'g, T: 'static> {
+    list: &'g List<T>,
+    next: *const Node<T>,
+    _guard: PhantomData<&'g Guard>,
+}
+
+impl<'g, T: 'static> Iterator for Iter<'g, T> {
+    type Item = NodeRef<'g, T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // `next` of a removed node still leads to nodes the guard keeps alive
+        // and on to the tail
+        while self.next != self.list.tail {
+            let node = self.next;
+            let succ = unsafe { &*node }.next.load();
+            self.next = unmarked(succ);
+            if !is_deleted(succ) {
+                return Some(NodeRef {
+                    list: self.list,
+                    node,
+                    _guard: PhantomData,
+                });
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossbeam_epoch::pin;
+    use std::{
+        sync::atomic::{AtomicIsize, Ordering},
+        thread,
+    };
+
+    fn values(list: &List<usize>) -> Vec<usize> {
+        let guard = pin();
+        list.iter(&guard).map(|node| *node.value()).collect()
+    }
+
+    #[test]
+    fn test_list() {
+        let list = List::new();
+        let guard = pin();
+        assert!(list.is_empty());
+        let two = list.push_back(2, &guard);
+        list.push_front(1, &guard);
+        let four = list.push_back(4, &guard);
+        list.insert_after(two, 3, &guard).unwrap();
+        assert_eq!(values(&list), [1, 2, 3, 4]);
+
+        assert!(list.remove(two, &guard));
+        assert!(!list.remove(two, &guard));
+        assert!(two.is_removed());
+        assert_eq!(*two.value(), 2);
+        assert_eq!(list.insert_after(two, 5, &guard).err(), Some(5));
+        list.insert_after(four, 5, &guard).unwrap();
+        assert_eq!(values(&list), [1, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_link_symmetry() {
+        let list = List::new();
+        // nodes in the list, by the inserts and removes that succeeded
+        let len = AtomicIsize::new(0);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let (list, len) = (&list, &len);
+                s.spawn(move || {
+                    for i in 0..500 {
+                        let guard = pin();
+                        // the first few nodes are the ones every thread
+                        // inserts after and removes, so their links race
+                        let at = list.iter(&guard).nth(i % 4);
+                        let added = match ((i + t) % 4, at) {
+                            (0, _) => {
+                                list.push_front(i, &guard);
+                                1
+                            },
+                            (1, _) => {
+                                list.push_back(i, &guard);
+                                1
+                            },
+                            (2, Some(at)) => {
+                                list.insert_after(at, i, &guard).is_ok() as isize
+                            },
+                            (_, Some(at)) => -(list.remove(at, &guard) as isize),
+                            _ => 0,
+                        };
+                        len.fetch_add(added, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+        // walking `next` from the head and `prev` from the tail passes the
+        // same nodes, so every `prev` points back at the node whose `next`
+        // leads to it
+        let mut forward = vec![list.head];
+        while *forward.last().unwrap() != list.tail {
+            let next = unsafe { &**forward.last().unwrap() }.next.load();
+            assert!(!is_deleted(next));
+            forward.push(next);
+        }
+        let mut backward = vec![list.tail];
+        while *backward.last().unwrap() != list.head {
+            backward.push(unsafe { &**backward.last().unwrap() }.prev.load());
+        }
+        backward.reverse();
+        assert_eq!(forward, backward);
+        assert_eq!(forward.len() as isize - 2, len.load(Ordering::Relaxed));
+    }
+}
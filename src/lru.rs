@@ -0,0 +1,310 @@
+//! A lock-free least recently used cache, enabled by the `structures`
+//! feature.
+
+use crate::{is_sentinel, skip_list::SkipListMap, with_sentinel, Atomic, CASN};
+use crossbeam_epoch::{pin, Guard};
+use std::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Nodes of the recency list, most recently used first. A node with a null
+// `prev` was detached to be moved to the front, anybody who comes across it
+// can finish the move. A removed node has a null `prev` and a marked `next`.
+struct Node<K: 'static, V: 'static> {
+    // `None` in the sentinels
+    entry: Option<(K, V)>,
+    prev: Atomic<*const Node<K, V>>,
+    next: Atomic<*const Node<K, V>>,
+}
+
+impl<K: 'static, V: 'static> Node<K, V> {
+    fn alloc(entry: Option<(K, V)>) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            entry,
+            prev: Atomic::new(ptr::null()),
+            next: Atomic::new(ptr::null()),
+        }))
+    }
+
+    fn entry(&self) -> &(K, V) {
+        self.entry.as_ref().unwrap()
+    }
+}
+
+// the `next` of a node that left the cache is in the sentinel state
+fn is_deleted<T>(next: *const T) -> bool {
+    is_sentinel(next)
+}
+
+// the node of a key, owned by the list
+struct NodePtr<K: 'static, V: 'static>(*const Node<K, V>);
+
+unsafe impl<K: Send + Sync, V: Send + Sync> Send for NodePtr<K, V> {}
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for NodePtr<K, V> {}
+
+/// A cache that evicts the least recently used entry once it holds more than
+/// its capacity.
+///
+/// Keys are looked up in a `SkipListMap`, recency is kept in a doubly linked
+/// list. Moving an entry to the front takes two `cas_n`s of up to 4 words: the
+/// first detaches the node from its neighbours, the second links it in after
+/// the head. A node found detached is moved by whoever finds it, so no
+/// operation waits for another. Evictions and removals unlink a node for good
+/// by also marking its `next`.
+///
+/// The capacity is approximate while entries are inserted concurrently, a
+/// race can evict an entry more than necessary.
+pub struct LruCache<K: 'static, V: 'static> {
+    head: *const Node<K, V>,
+    tail: *const Node<K, V>,
+    map: SkipListMap<K, NodePtr<K, V>>,
+    len: AtomicUsize,
+    capacity: usize,
+}
+
+impl<K, V> LruCache<K, V>
+where
+    K: Ord + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        let head = Node::alloc(None);
+        let tail = Node::alloc(None);
+        unsafe {
+            (*head).next = Atomic::new(tail as *const _);
+            (*tail).prev = Atomic::new(head as *const _);
+        }
+        Self {
+            head,
+            tail,
+            map: SkipListMap::new(),
+            len: AtomicUsize::new(0),
+            capacity,
+        }
+    }
+
+    /// The value of `key`, which becomes the most recently used entry.
+    pub fn get<'g>(&'g self, key: &K, guard: &'g Guard) -> Option<&'g V> {
+        let node = self.map.get(key, guard)?.0;
+        unsafe {
+            if is_deleted((*node).next.load()) {
+                return None;
+            }
+            self.touch(node);
+            Some(&(*node).entry().1)
+        }
+    }
+
+    /// Inserts the entry as the most recently used one if the cache doesn't
+    /// have `key` yet, evicting the least recently used entries past the
+    /// capacity. Returns whether it did.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let guard = pin();
+        let node = Node::alloc(Some((key.clone(), value)));
+        if !self.map.insert(key, NodePtr(node)) {
+            drop(unsafe { Box::from_raw(node) });
+            return false;
+        }
+        // starts out detached, so a concurrent `remove` can link it first
+        unsafe { self.move_to_front(node) };
+        self.len.fetch_add(1, Ordering::Relaxed);
+        while self.len.load(Ordering::Relaxed) > self.capacity {
+            self.evict(&guard);
+        }
+        true
+    }
+
+    /// Removes the entry of `key`, returns whether there was one. The value
+    /// is dropped once no guard can see it anymore.
+    pub fn remove(&self, key: &K) -> bool {
+        let guard = pin();
+        let node = match self.map.get(key, &guard) {
+            Some(node) => node.0,
+            None => return false,
+        };
+        unsafe { self.unlink(node, &guard) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn evict(&self, guard: &Guard) {
+        loop {
+            let last = unsafe { &*self.tail }.prev.load();
+            if last == self.head {
+                return;
+            }
+            // a node that was used in the meantime isn't the last one anymore
+            if unsafe { self.unlink(last, guard) } {
+                return;
+            }
+        }
+    }
+
+    // safety: `node` is a node of the cache the caller's guard keeps alive
+    unsafe fn touch(&self, node: *const Node<K, V>) {
+        if (*self.head).next.load() != node && self.detach(node, false) {
+            self.move_to_front(node);
+        }
+    }
+
+    // Removes `node` from the cache for good, returns `false` if somebody
+    // else did.
+    //
+    // safety: same as `touch`
+    unsafe fn unlink(&self, node: *const Node<K, V>, guard: &Guard) -> bool {
+        if !self.detach(node, true) {
+            return false;
+        }
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        self.map.remove(&(*node).entry().0);
+        let node = node as *mut Node<K, V>;
+        guard.defer_unchecked(move || drop(Box::from_raw(node)));
+        true
+    }
+
+    // Takes `node` out of the list, to be moved to the front or for good.
+    // Returns `false` if it's being moved by somebody else already or removed.
+    //
+    // safety: same as `touch`
+    unsafe fn detach(&self, node: *const Node<K, V>, remove: bool) -> bool {
+        loop {
+            let prev = (*node).prev.load();
+            let next = (*node).next.load();
+            if is_deleted(next) {
+                return false;
+            }
+            if prev.is_null() {
+                if !remove {
+                    return false;
+                }
+                self.move_to_front(node);
+                continue;
+            }
+            // `next.prev` pointing back to the node makes `next` its current
+            // successor, only linked nodes are pointed to
+            let mut cas_n = CASN::new();
+            cas_n.add_unchecked(&(*prev).next, node, next);
+            cas_n.add_unchecked(&(*node).prev, prev, ptr::null());
+            cas_n.add_unchecked(&(*next).prev, node, prev);
+            if remove {
+                let deleted = with_sentinel(next);
+                cas_n.add_unchecked(&(*node).next, next, deleted);
+            }
+            if cas_n.exec() {
+                return true;
+            }
+        }
+    }
+
+    // Links a detached `node` in after the head, unless somebody else did.
+    //
+    // safety: same as `touch`
+    unsafe fn move_to_front(&self, node: *const Node<K, V>) {
+        let head = &*self.head;
+        loop {
+            let next = (*node).next.load();
+            if !(*node).prev.load().is_null() || is_deleted(next) {
+                return;
+            }
+            let first = head.next.load();
+            let mut cas_n = CASN::new();
+            cas_n.add_unchecked(&head.next, first, node);
+            cas_n.add_unchecked(&(*first).prev, self.head, node);
+            cas_n.add_unchecked(&(*node).prev, ptr::null(), self.head);
+            cas_n.add_unchecked(&(*node).next, next, first);
+            if cas_n.exec() {
+                return;
+            }
+        }
+    }
+}
+
+impl<K: 'static, V: 'static> Drop for LruCache<K, V> {
+    fn drop(&mut self) {
+        // removed nodes are retired already, every other node is linked
+        let mut node = self.head;
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw(node as *mut Node<K, V>) };
+            node = boxed.next.load();
+        }
+    }
+}
+
+unsafe impl<K: Send + Sync + 'static, V: Send + Sync + 'static> Send for LruCache<K, V> {}
+// `get` lends `&V` while other threads may be moving the same node to the
+// front or evicting it
+unsafe impl<K: Send + Sync + 'static, V: Send + Sync + 'static> Sync for LruCache<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_lru_cache() {
+        let cache = LruCache::new(2);
+        assert!(cache.insert(1, "one"));
+        assert!(cache.insert(2, "two"));
+        assert!(!cache.insert(2, "deux"));
+        // 1 becomes the most recently used entry, so 2 is evicted
+        assert_eq!(cache.get(&1, &pin()), Some(&"one"));
+        assert!(cache.insert(3, "three"));
+        assert_eq!(cache.get(&2, &pin()), None);
+        assert_eq!(cache.len(), 2);
+        assert!(cache.remove(&1));
+        assert!(!cache.remove(&1));
+        assert_eq!(cache.get(&3, &pin()), Some(&"three"));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_use() {
+        let cache = LruCache::new(64);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let cache = &cache;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        let key = (i * 7 + t) % 128;
+                        let guard = pin();
+                        match cache.get(&key, &guard) {
+                            Some(value) => assert_eq!(*value, key * 10),
+                            None => {
+                                cache.insert(key, key * 10);
+                            },
+                        }
+                        if i % 10 == 0 {
+                            cache.remove(&((key + 1) % 128));
+                        }
+                    }
+                });
+            }
+        });
+        assert!(cache.len() <= 64);
+        // the list holds exactly the cached entries, linked both ways
+        let mut count = 0;
+        let mut prev = cache.head;
+        let mut node = unsafe { &*cache.head }.next.load();
+        while node != cache.tail {
+            let node_ref = unsafe { &*node };
+            assert_eq!(node_ref.prev.load(), prev);
+            assert!(cache.map.contains_key(&node_ref.entry().0));
+            count += 1;
+            prev = node;
+            node = node_ref.next.load();
+        }
+        assert_eq!(count, cache.len());
+    }
+}
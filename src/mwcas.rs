@@ -1,23 +1,54 @@
-pub use crate::atomic::Atomic;
+pub use crate::atomic::{
+    is_sentinel, with_sentinel, without_sentinel, Atomic, Linked, Versioned,
+};
+#[cfg(all(feature = "coalesce", target_arch = "x86_64", not(loom)))]
+use crate::coalesce;
+#[cfg(feature = "heap-descriptors")]
+use crate::heap;
+#[cfg(all(feature = "htm", target_arch = "x86_64"))]
+use crate::htm;
+#[cfg(feature = "per-cpu")]
+use crate::per_cpu::Lease;
+#[cfg(feature = "stats")]
+use crate::stats::{self, Event};
+#[cfg(feature = "test-internals")]
+use crate::test_hooks::{self, Point};
+#[cfg(not(feature = "heap-descriptors"))]
+use crate::{
+    atomic::AtomicAddress,
+    rdcss::ThreadRDCSSDescriptor,
+    sequence_number::{self, FIELD_LOAD, FIELD_STORE},
+};
 use crate::{
-    atomic::{AtomicAddress, AtomicBits, Bits, Word},
-    rdcss::RDCSS_DESCRIPTOR,
+    atomic::{AtomicBits, Bits, Word},
+    backoff::{self, BackoffPolicy},
+    domain::{MwCasDomain, DEFAULT_DOMAIN},
+    rdcss::RDCSSDescriptor,
     sequence_number::SeqNumber,
-    thread_local::ThreadLocal,
+    thread_local::{
+        DomainIds, ThreadId, ThreadTable, ThreadToken, MAX_REGISTERED, MAX_THREADS,
+    },
+    watchdog::{describe, Watchdog},
 };
 use arrayvec::ArrayVec;
-use crossbeam_utils::Backoff;
-use once_cell::sync::Lazy;
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicUsize as StdAtomicUsize, Ordering};
+#[cfg(feature = "heap-descriptors")]
+use std::mem;
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicUsize as StdAtomicUsize, Ordering};
 use std::{
-    mem,
-    mem::MaybeUninit,
-    sync::atomic::{fence, AtomicUsize as StdAtomicUsize, Ordering},
+    array, cell::Cell, error::Error, fmt, marker::PhantomData, ptr, sync::Arc,
+    time::Instant,
 };
 
-pub(crate) static CASN_DESCRIPTOR: Lazy<CasNDescriptor> = Lazy::new(CasNDescriptor::new);
-
 pub struct CASN<'a> {
     entries: ArrayVec<[Entry<'a>; MAX_ENTRIES]>,
+    masks: ArrayVec<[(&'a AtomicBits, Bits); MAX_ENTRIES]>,
+    deltas: ArrayVec<[(&'a AtomicBits, usize); MAX_ENTRIES]>,
+    backoff: Option<&'a dyn BackoffPolicy>,
+    domain: Option<&'a MwCasDomain>,
+    thread: Option<ThreadId>,
 }
 
 impl<'a> CASN<'a> {
@@ -25,22 +56,25 @@ impl<'a> CASN<'a> {
     pub fn new() -> Self {
         Self {
             entries: ArrayVec::new(),
+            masks: ArrayVec::new(),
+            deltas: ArrayVec::new(),
+            backoff: None,
+            domain: None,
+            thread: None,
         }
     }
 
     #[inline]
+    #[allow(clippy::result_unit_err)]
     pub fn add<T: Word>(
         &mut self,
         addr: &'a Atomic<T>,
         expected: T,
         new: T,
     ) -> Result<(), ()> {
-        let e = Entry {
-            addr: addr.as_atomic_bits(),
-            exp: expected.into(),
-            new: new.into(),
-        };
-        self.entries.try_push(e).map_err(|_| ())
+        self.entries
+            .try_push(Entry::new(addr, expected, new))
+            .map_err(|_| ())
     }
 
     #[inline]
@@ -48,11 +82,407 @@ impl<'a> CASN<'a> {
         self.add(addr, expected, new).unwrap()
     }
 
+    /// Adds an entry that only compares the bits of `expected` selected by
+    /// `mask`; on success the whole word is replaced with `new`. Bits above
+    /// `Atomic::<usize>::MAX` are ignored, a word can't hold them, so `!0`
+    /// compares the whole value.
+    ///
+    /// The descriptor itself compares whole words: the operation reads the
+    /// word and expects what it read, and is retried for as long as the
+    /// selected bits still match when it fails. A concurrent change of the
+    /// other bits costs an attempt but doesn't make the operation fail.
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn add_masked(
+        &mut self,
+        addr: &'a Atomic<usize>,
+        mask: usize,
+        expected: usize,
+        new: usize,
+    ) -> Result<(), ()> {
+        self.add(addr, expected, new)?;
+        let mask = mask & Atomic::<usize>::MAX;
+        self.masks.push((addr.as_atomic_bits(), mask.into()));
+        Ok(())
+    }
+
+    /// Adds an entry that adds `delta` to the word instead of replacing an
+    /// expected value. It matches whatever the word holds: the operation
+    /// reads the word, expects what it read and stores the sum, and is
+    /// retried if the word changed in between. Operations that conflict with
+    /// this one only on such words make it retry instead of fail.
+    ///
+    /// Deltas aren't merged by the operations that run into each other, every
+    /// operation retries on its own. Many threads adding to the same word keep
+    /// making each other retry, see `MwCounter` for a counter that spreads
+    /// them out.
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn add_delta(&mut self, addr: &'a Atomic<usize>, delta: usize) -> Result<(), ()> {
+        self.add(addr, 0, 0)?;
+        self.deltas.push((addr.as_atomic_bits(), delta));
+        Ok(())
+    }
+
+    #[inline]
+    #[allow(clippy::result_unit_err)]
+    pub fn add_entry<T: Word>(&mut self, entry: CasEntry<'a, T>) -> Result<(), ()> {
+        self.add(entry.addr, entry.expected, entry.new)
+    }
+
+    /// Overrides the global backoff policy for this operation.
+    #[inline]
+    pub fn set_backoff(&mut self, policy: &'a dyn BackoffPolicy) {
+        self.backoff = Some(policy);
+    }
+
+    /// Runs the operation in `domain` instead of the global one, see
+    /// `MwCasDomain`.
+    #[inline]
+    pub fn set_domain(&mut self, domain: &'a MwCasDomain) {
+        self.domain = Some(domain);
+    }
+
+    /// Runs the operation with the id `token` holds instead of looking up the
+    /// id of the calling thread, which saves the thread local accesses of the
+    /// operation. A domain set with `set_domain` looks up the id the thread
+    /// has in it anyway.
+    #[inline]
+    pub fn set_thread(&mut self, token: &ThreadToken) {
+        self.thread = Some(token.id());
+    }
+
+    #[must_use]
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn exec(self) -> bool {
+        match self.try_exec() {
+            Ok(succeeded) => succeeded,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Makes a single attempt that never helps other operations and fails on
+    /// any interference, so it may fail even if all entries match.
     #[must_use]
     #[allow(clippy::missing_safety_doc)]
-    pub unsafe fn exec(mut self) -> bool {
-        let descriptor_ptr = CASN_DESCRIPTOR.make_descriptor(&mut self.entries);
-        CASN_DESCRIPTOR.help(descriptor_ptr, false)
+    pub unsafe fn exec_weak(self) -> bool {
+        match self.run(true) {
+            Ok(succeeded) => succeeded,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Like `exec`, but reports an entry list that can't be executed, or a
+    /// thread that can't be registered, instead of panicking. Entries
+    /// repeating an address with the same expected and new values are merged
+    /// into one.
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn try_exec(self) -> Result<bool, CasNError> {
+        self.run(false)
+    }
+
+    unsafe fn run(mut self, weak: bool) -> Result<bool, CasNError> {
+        let domain = self.domain.unwrap_or(&DEFAULT_DOMAIN);
+        #[cfg(not(feature = "heap-descriptors"))]
+        let owner = match domain.casn.try_owner(self.thread)? {
+            Some(id) => Some(id),
+            None => Some(ThreadId::try_register()?),
+        };
+        // heap descriptors don't take a slot, there's nothing to register for
+        #[cfg(feature = "heap-descriptors")]
+        let owner = domain.casn.try_owner(self.thread)?;
+        // two entries of a word with the same placeholder values would be
+        // merged by the dedup
+        if self.deltas.iter().any(|(addr, _)| {
+            self.entries
+                .iter()
+                .filter(|e| ptr::eq(e.addr, *addr))
+                .count()
+                > 1
+        }) {
+            return Err(CasNError::DuplicateAddress);
+        }
+        let len = sort_and_dedup(&mut self.entries)?;
+        self.entries.truncate(len);
+        if !self.masks.is_empty() || !self.deltas.is_empty() {
+            return self.exec_masked(owner, weak);
+        }
+        let backoff = self.backoff.unwrap_or(backoff::global());
+        Ok(exec_sorted(
+            &domain.casn,
+            owner,
+            &self.entries,
+            weak,
+            backoff,
+            false,
+        ))
+    }
+
+    // Masked and delta entries are turned into plain ones by reading the current
+    // value of their words. A failed attempt is retried as long as all entries
+    // still match, so it only fails if some word didn't match at the time it was
+    // read. Delta entries always match.
+    unsafe fn exec_masked(
+        self,
+        owner: Option<ThreadId>,
+        weak: bool,
+    ) -> Result<bool, CasNError> {
+        let CASN {
+            mut entries,
+            masks,
+            deltas,
+            backoff,
+            domain,
+            ..
+        } = self;
+        let backoff = backoff.unwrap_or(backoff::global());
+        let descriptor = &domain.unwrap_or(&DEFAULT_DOMAIN).casn;
+        if masks
+            .iter()
+            .enumerate()
+            .any(|(i, (addr, _))| masks[..i].iter().any(|(a, _)| ptr::eq(*a, *addr)))
+        {
+            return Err(CasNError::DuplicateAddress);
+        }
+        let mask_of = |addr: &AtomicBits| {
+            masks
+                .iter()
+                .find(|(a, _)| ptr::eq(*a, addr))
+                .map(|(_, mask)| *mask)
+        };
+        let delta_of = |addr: &AtomicBits| {
+            deltas
+                .iter()
+                .find(|(a, _)| ptr::eq(*a, addr))
+                .map(|(_, delta)| *delta)
+        };
+        let templates: ArrayVec<[Bits; MAX_ENTRIES]> =
+            entries.iter().map(|e| e.exp).collect();
+        loop {
+            for (entry, template) in entries.iter_mut().zip(&templates) {
+                if let Some(mask) = mask_of(entry.addr) {
+                    let current = descriptor.read(entry.addr);
+                    if !current.eq_masked(*template, mask) {
+                        return Ok(false);
+                    }
+                    entry.exp = current;
+                } else if let Some(delta) = delta_of(entry.addr) {
+                    let current = descriptor.read(entry.addr);
+                    let sum = usize::from(current)
+                        .checked_add(delta)
+                        .filter(|sum| sum.fits())
+                        .ok_or(CasNError::InvalidOperand)?;
+                    entry.exp = current;
+                    entry.new = sum.into();
+                }
+            }
+
+            if weak {
+                return Ok(exec_sorted(
+                    descriptor, owner, &entries, true, backoff, false,
+                ));
+            }
+            if exec_sorted(descriptor, owner, &entries, false, backoff, false) {
+                return Ok(true);
+            }
+
+            let matching = entries.iter().zip(&templates).all(|(entry, template)| {
+                if delta_of(entry.addr).is_some() {
+                    return true;
+                }
+                let current = descriptor.read(entry.addr);
+                match mask_of(entry.addr) {
+                    Some(mask) => current.eq_masked(*template, mask),
+                    None => current == *template,
+                }
+            });
+            if !matching {
+                return Ok(false);
+            }
+        }
+    }
+
+    pub(crate) fn from_slices<T: Word>(
+        addresses: &[&'a Atomic<T>],
+        expected: &[T],
+        new: &[T],
+    ) -> Self {
+        assert_eq!(addresses.len(), expected.len());
+        assert_eq!(expected.len(), new.len());
+        assert!(addresses.len() <= MAX_ENTRIES);
+        let mut cas_n = CASN::new();
+        for ((addr, exp), new) in addresses.iter().zip(expected).zip(new) {
+            cas_n.add_unchecked(*addr, *exp, *new);
+        }
+        cas_n
+    }
+}
+
+// Entries have to be sorted by address and free of duplicates. `owner` is
+// the id of the calling thread in the tables of `descriptor` if the caller
+// knows it already, see `CasNDescriptor::owner`. With `record_mismatch` a failure leaves the
+// entry and the value it failed on in `MISMATCH`.
+fn exec_sorted(
+    descriptor: &CasNDescriptor,
+    owner: Option<ThreadId>,
+    entries: &[Entry<'_>],
+    weak: bool,
+    backoff: &dyn BackoffPolicy,
+    record_mismatch: bool,
+) -> bool {
+    let owner = descriptor.owner(owner);
+    #[cfg(feature = "heap-descriptors")]
+    let guard = crossbeam_epoch::pin();
+    #[cfg(feature = "per-cpu")]
+    let _lease = Lease::acquire();
+    #[cfg(feature = "wait-free")]
+    descriptor.help_announced(owner);
+    let succeeded = if let [entry] = entries {
+        cas1(descriptor, entry, weak, record_mismatch)
+    } else if let Some(succeeded) = cas_htm(entries) {
+        succeeded
+    } else {
+        let descriptor_ptr = descriptor.make_descriptor(owner, entries);
+        let succeeded = if weak {
+            descriptor.help_weak(owner, descriptor_ptr)
+        } else {
+            descriptor.run(
+                owner,
+                descriptor_ptr,
+                false,
+                false,
+                0,
+                backoff,
+                record_mismatch,
+            )
+        };
+        // phase 2 of the owner removed the descriptor from all words
+        #[cfg(feature = "heap-descriptors")]
+        unsafe {
+            heap::retire::<HeapCasNDescriptor>(&guard, descriptor_ptr)
+        };
+        succeeded
+    };
+    #[cfg(feature = "stats")]
+    {
+        stats::record(Event::Op);
+        if !succeeded {
+            stats::record(Event::Failed);
+        }
+    }
+    succeeded
+}
+
+// Runs the whole operation inside of a hardware transaction. Returns `None` if
+// the transaction keeps aborting or finds a descriptor in one of the words, the
+// descriptor based algorithm has to be used then.
+#[cfg(all(feature = "htm", target_arch = "x86_64"))]
+fn cas_htm(entries: &[Entry<'_>]) -> Option<bool> {
+    if !htm::available() {
+        return None;
+    }
+    // breakpoints are in the descriptor based algorithm only
+    #[cfg(feature = "test-internals")]
+    if test_hooks::any_armed() {
+        return None;
+    }
+    for _ in 0..htm::MAX_ATTEMPTS {
+        unsafe {
+            if htm::begin() {
+                let mut result = Some(true);
+                for entry in entries {
+                    let current = entry.addr.load(Ordering::Relaxed);
+                    if current.is_descriptor() {
+                        result = None;
+                        break;
+                    } else if current != entry.exp {
+                        result = Some(false);
+                        break;
+                    }
+                }
+                if result == Some(true) {
+                    for entry in entries {
+                        entry.addr.store(entry.new, Ordering::Relaxed);
+                    }
+                }
+                htm::end();
+                return result;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(not(all(feature = "htm", target_arch = "x86_64")))]
+#[inline(always)]
+fn cas_htm(_entries: &[Entry<'_>]) -> Option<bool> {
+    None
+}
+
+// A single word doesn't need a descriptor, a plain CAS is enough as long as
+// whatever descriptor is installed in the word gets helped out of it first.
+fn cas1(
+    descriptor: &CasNDescriptor,
+    entry: &Entry<'_>,
+    weak: bool,
+    record_mismatch: bool,
+) -> bool {
+    let mut watchdog = Watchdog::new();
+    loop {
+        match entry.addr.compare_exchange(entry.exp, entry.new) {
+            Ok(_) => return true,
+            Err(current) if !current.is_descriptor() => {
+                if record_mismatch {
+                    MISMATCH.with(|mismatch| mismatch.set(Some((0, current))));
+                }
+                return false;
+            },
+            Err(_) if weak => return false,
+            Err(current) => {
+                watchdog.tick(|| {
+                    format!("cas1 on {:p} against {}", entry.addr, describe(current))
+                });
+                descriptor.read(entry.addr);
+            },
+        }
+    }
+}
+
+// entries have to be installed in address order, otherwise two descriptors can
+// keep helping each other forever. Returns the number of entries left after
+// merging duplicates, they are moved to the front of the slice.
+fn sort_and_dedup(entries: &mut [Entry<'_>]) -> Result<usize, CasNError> {
+    // callers often pass the addresses in order already, one pass over them
+    // is cheaper than the sort
+    if is_sorted(entries) {
+        return Ok(entries.len());
+    }
+    entries.sort_unstable_by_key(|e| e.addr as *const AtomicBits);
+    let mut len = entries.len().min(1);
+    for i in 1..entries.len() {
+        let (prev, curr) = (entries[len - 1], entries[i]);
+        if ptr::eq(prev.addr, curr.addr) {
+            if prev.exp != curr.exp || prev.new != curr.new {
+                return Err(CasNError::DuplicateAddress);
+            }
+        } else {
+            entries[len] = curr;
+            len += 1;
+        }
+    }
+    Ok(len)
+}
+
+// strictly ascending, so free of duplicates as well
+fn is_sorted(entries: &[Entry<'_>]) -> bool {
+    entries
+        .windows(2)
+        .all(|w| (w[0].addr as *const AtomicBits) < (w[1].addr as *const _))
+}
+
+impl Default for CASN<'_> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -69,71 +499,923 @@ where
     T0: Word,
     T1: Word,
 {
-    let mut cas_n = CASN::new();
-    cas_n.add_unchecked(addr0, exp0, new0);
-    cas_n.add_unchecked(addr1, exp1, new1);
-    cas_n.exec()
+    cas2_entries(
+        &DEFAULT_DOMAIN.casn,
+        Entry::new(addr0, exp0, new0),
+        Entry::new(addr1, exp1, new1),
+        false,
+    )
+}
+
+/// Weak version of `cas2`, see `CASN::exec_weak`.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn cas2_weak<T0, T1>(
+    addr0: &Atomic<T0>,
+    addr1: &Atomic<T1>,
+    exp0: T0,
+    exp1: T1,
+    new0: T0,
+    new1: T1,
+) -> bool
+where
+    T0: Word,
+    T1: Word,
+{
+    cas2_entries(
+        &DEFAULT_DOMAIN.casn,
+        Entry::new(addr0, exp0, new0),
+        Entry::new(addr1, exp1, new1),
+        true,
+    )
+}
+
+// the dominant k=2 case orders its entries with a single comparison instead of
+// going through the sort in `CASN`
+pub(crate) fn cas2_entries(
+    descriptor: &CasNDescriptor,
+    e0: Entry<'_>,
+    e1: Entry<'_>,
+    weak: bool,
+) -> bool {
+    let addr0 = e0.addr as *const AtomicBits;
+    let addr1 = e1.addr as *const AtomicBits;
+    let entries = if addr0 < addr1 {
+        [e0, e1]
+    } else if addr0 > addr1 {
+        [e1, e0]
+    } else if e0.exp == e1.exp && e0.new == e1.new {
+        return cas1(descriptor, &e0, weak, false);
+    } else {
+        panic!("{}", CasNError::DuplicateAddress)
+    };
+    exec_sorted(descriptor, None, &entries, weak, backoff::global(), false)
 }
 
 #[allow(clippy::missing_safety_doc)]
 pub unsafe fn cas_n<T>(addresses: &[&Atomic<T>], expected: &[T], new: &[T]) -> bool
+where
+    T: Word,
+{
+    CASN::from_slices(addresses, expected, new).exec()
+}
+
+/// `cas_n` for addresses the caller passes in ascending order already, as
+/// tree algorithms usually know the order of their nodes. Skips sorting and
+/// checking the entries, the order is only asserted in debug builds.
+///
+/// # Safety
+///
+/// Same as for `cas_n`. Operations with unsorted addresses can keep helping
+/// each other forever.
+pub unsafe fn cas_n_presorted<T>(
+    addresses: &[&Atomic<T>],
+    expected: &[T],
+    new: &[T],
+) -> bool
 where
     T: Word,
 {
     assert_eq!(addresses.len(), expected.len());
     assert_eq!(expected.len(), new.len());
     assert!(addresses.len() <= MAX_ENTRIES);
+    let entries: ArrayVec<[Entry<'_>; MAX_ENTRIES]> = addresses
+        .iter()
+        .zip(expected)
+        .zip(new)
+        .map(|((addr, exp), new)| Entry::new(*addr, *exp, *new))
+        .collect();
+    debug_assert!(is_sorted(&entries), "addresses are not in ascending order");
+    exec_sorted(
+        &DEFAULT_DOMAIN.casn,
+        None,
+        &entries,
+        false,
+        backoff::global(),
+        false,
+    )
+}
+
+/// `cas_n` run with the thread id held by `thread`, see `CASN::set_thread`.
+///
+/// A thread that registers itself once and passes its token to every
+/// operation saves the thread local lookups of its id.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn cas_n_with<T>(
+    thread: &ThreadToken,
+    addresses: &[&Atomic<T>],
+    expected: &[T],
+    new: &[T],
+) -> bool
+where
+    T: Word,
+{
+    let mut cas_n = CASN::from_slices(addresses, expected, new);
+    cas_n.set_thread(thread);
+    cas_n.exec()
+}
+
+/// Limits on how long a `cas_n_bounded` keeps at it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Conflicting operations to help at most, `None` for no limit.
+    pub max_helps: Option<u32>,
+    /// Attempts that may fail on other operations, `None` for no limit.
+    pub max_attempts: Option<u32>,
+    /// No new attempt is made after this point in time.
+    pub deadline: Option<Instant>,
+}
+
+/// `cas_n` that fails with `CasNError::Timeout` once it used up the budget
+/// `options` gives it, instead of helping the operations in its words for as
+/// long as it takes.
+///
+/// Every attempt is a weak one. One that fails while the words still match
+/// lost to other operations and counts against `max_attempts`, then the
+/// operations in the words are helped, each one counting against
+/// `max_helps`. The deadline is checked between attempts and helps, a single
+/// help isn't cut short.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn cas_n_bounded<T>(
+    options: &Options,
+    addresses: &[&Atomic<T>],
+    expected: &[T],
+    new: &[T],
+) -> Result<bool, CasNError>
+where
+    T: Word,
+{
+    if expected.iter().chain(new).any(|w| !w.fits()) {
+        return Err(CasNError::InvalidOperand);
+    }
+    let out_of_time = || options.deadline.is_some_and(|d| Instant::now() >= d);
+    let (mut attempts, mut helps) = (0, 0);
+    loop {
+        if CASN::from_slices(addresses, expected, new).run(true)? {
+            return Ok(true);
+        }
+        // without helping, an operation in progress counts with its old values
+        let matches = addresses
+            .iter()
+            .zip(expected)
+            .all(|(addr, exp)| addr.peek().into() == (*exp).into());
+        if !matches {
+            return Ok(false);
+        }
+        attempts += 1;
+        if options.max_attempts.is_some_and(|max| attempts >= max) {
+            return Err(CasNError::Timeout);
+        }
+        for addr in addresses.iter().filter(|addr| addr.is_contended()) {
+            if out_of_time() || options.max_helps.is_some_and(|max| helps >= max) {
+                return Err(CasNError::Timeout);
+            }
+            helps += 1;
+            addr.settle();
+        }
+        if out_of_time() {
+            return Err(CasNError::Timeout);
+        }
+    }
+}
+
+/// Weak version of `cas_n`, see `CASN::exec_weak`.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn cas_n_weak<T>(addresses: &[&Atomic<T>], expected: &[T], new: &[T]) -> bool
+where
+    T: Word,
+{
+    CASN::from_slices(addresses, expected, new).exec_weak()
+}
+
+/// Adds `deltas[i]` to the i-th word, all at one point in time. Concurrent
+/// operations on the words make it retry until it succeeds, see
+/// `CASN::add_delta`.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn add_n(addresses: &[&Atomic<usize>], deltas: &[usize]) {
+    assert_eq!(addresses.len(), deltas.len());
+    assert!(addresses.len() <= MAX_ENTRIES);
     let mut cas_n = CASN::new();
-    for ((addr, exp), new) in addresses.iter().zip(expected).zip(new) {
-        cas_n.add_unchecked(*addr, *exp, *new);
+    for (addr, delta) in addresses.iter().zip(deltas) {
+        cas_n.add_delta(addr, *delta).unwrap();
+    }
+    assert!(cas_n.exec());
+}
+
+/// Runs independent operations one after the other and returns what
+/// `CASN::try_exec` returned for each of them, leaving `ops` empty.
+///
+/// Only the lookup of the thread id is shared: it's done once for the whole
+/// batch instead of once per operation, with `heap-descriptors` the thread
+/// stays pinned throughout instead. Operations run in a domain still look up
+/// the id they have there. Every operation does the rest of its setup on its
+/// own, its slot in the table and the fences that publish its descriptor
+/// included.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn exec_batch(ops: &mut [CASN<'_>]) -> Vec<Result<bool, CasNError>> {
+    #[cfg(not(feature = "heap-descriptors"))]
+    let owner = ThreadId::try_register();
+    #[cfg(feature = "heap-descriptors")]
+    let _guard = crossbeam_epoch::pin();
+    ops.iter_mut()
+        .map(|op| {
+            let op = std::mem::take(op);
+            #[cfg(not(feature = "heap-descriptors"))]
+            let op = CASN {
+                thread: Some(op.thread.map_or(owner, Ok)?),
+                ..op
+            };
+            op.try_exec()
+        })
+        .collect()
+}
+
+/// Same as `cas_n`, but the number of entries is checked at compile time.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn cas_n_array<T, const N: usize>(
+    addresses: [&Atomic<T>; N],
+    expected: [T; N],
+    new: [T; N],
+) -> bool
+where
+    T: Word,
+{
+    let () = Arity::<N>::CHECK;
+    let mut entries: [Entry<'_>; N] =
+        array::from_fn(|i| Entry::new(addresses[i], expected[i], new[i]));
+    let len = match sort_and_dedup(&mut entries) {
+        Ok(len) => len,
+        Err(err) => panic!("{}", err),
+    };
+    exec_sorted(
+        &DEFAULT_DOMAIN.casn,
+        None,
+        &entries[..len],
+        false,
+        backoff::global(),
+        false,
+    )
+}
+
+/// `cas_n` that tells which entry didn't match and what its word held when
+/// the operation came across it.
+///
+/// The value is the one the operation saw while installing its descriptor,
+/// unless another thread decided the failure. The words are read again then,
+/// and the operation is retried should all of them match by now.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn compare_exchange_n<T>(
+    addresses: &[&Atomic<T>],
+    expected: &[T],
+    new: &[T],
+) -> Result<(), CasNFailure<T>>
+where
+    T: Word,
+{
+    assert_eq!(addresses.len(), expected.len());
+    assert_eq!(expected.len(), new.len());
+    assert!(addresses.len() <= MAX_ENTRIES);
+    let mut entries: ArrayVec<[Entry<'_>; MAX_ENTRIES]> = addresses
+        .iter()
+        .zip(expected)
+        .zip(new)
+        .map(|((addr, exp), new)| Entry::new(*addr, *exp, *new))
+        .collect();
+    let len = match sort_and_dedup(&mut entries) {
+        Ok(len) => len,
+        Err(err) => panic!("{}", err),
+    };
+    let position = |addr: &AtomicBits| {
+        let index = addresses
+            .iter()
+            .position(|a| ptr::eq(a.as_atomic_bits(), addr));
+        index.unwrap()
+    };
+    loop {
+        MISMATCH.with(|mismatch| mismatch.set(None));
+        if exec_sorted(
+            &DEFAULT_DOMAIN.casn,
+            None,
+            &entries[..len],
+            false,
+            backoff::global(),
+            true,
+        ) {
+            return Ok(());
+        }
+        if let Some((i, current)) = MISMATCH.with(Cell::take) {
+            return Err(CasNFailure {
+                index: position(entries[i].addr),
+                current: current.into(),
+            });
+        }
+        for (index, (addr, exp)) in addresses.iter().zip(expected).enumerate() {
+            let current = addr.load();
+            if current.into() != (*exp).into() {
+                return Err(CasNFailure { index, current });
+            }
+        }
+    }
+}
+
+/// Stores `new` into the words and returns the values they held right before,
+/// all at one point in time. The addresses have to be distinct.
+///
+/// It's a `cas_n` of the loaded values retried until it succeeds, the values
+/// it expected are the ones it replaced.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn swap_n<T, const N: usize>(addresses: [&Atomic<T>; N], new: [T; N]) -> [T; N]
+where
+    T: Word,
+{
+    loop {
+        let old: [T; N] = array::from_fn(|i| addresses[i].load());
+        if cas_n_array(addresses, old, new) {
+            return old;
+        }
+    }
+}
+
+struct Arity<const N: usize>;
+
+impl<const N: usize> Arity<N> {
+    const CHECK: () = assert!(N <= MAX_ENTRIES, "too many entries for a single cas_n");
+}
+
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn cas_n_entries<T>(entries: &[CasEntry<'_, T>]) -> bool
+where
+    T: Word,
+{
+    assert!(entries.len() <= MAX_ENTRIES);
+    let mut cas_n = CASN::new();
+    for entry in entries {
+        cas_n.add_unchecked(entry.addr, entry.expected, entry.new);
     }
     cas_n.exec()
 }
 
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn try_cas_n<T>(
+    addresses: &[&Atomic<T>],
+    expected: &[T],
+    new: &[T],
+) -> Result<bool, CasNError>
+where
+    T: Word,
+{
+    if expected.iter().chain(new).any(|w| !w.fits()) {
+        return Err(CasNError::InvalidOperand);
+    }
+    CASN::from_slices(addresses, expected, new).try_exec()
+}
+
+/// Completes the operation that is updating `atomic` right now, if there is
+/// one, so later accesses find a plain value.
+pub fn help_at<T: Word>(atomic: &Atomic<T>) {
+    atomic.settle();
+}
+
+/// The value of a word used through `Atomic::<usize>::from_std`, helping the
+/// operation that is updating it right now, if there is one. Lets code that
+/// still works with the `AtomicUsize` read it without coming across a
+/// descriptor.
+///
+/// # Safety
+///
+/// Same as for `Atomic::from_std`.
+pub unsafe fn read_word(word: &StdAtomicUsize) -> usize {
+    Atomic::<usize>::from_std(word).load()
+}
+
+/// Makes the descriptors of the calling thread's past operations stale, so a
+/// thread that comes across a pointer to one of them from now on doesn't touch
+/// the words they list.
+///
+/// Together with `Atomic::settle` on every word of a node this is what a
+/// `Drop` impl needs before freeing the node. It doesn't wait for helpers
+/// that snapshotted a descriptor earlier and are still running, so the memory
+/// itself still has to be reclaimed with a grace period (e.g. an epoch).
+pub fn quiesce() {
+    DEFAULT_DOMAIN.quiesce();
+}
+
+/// Calls `help_at` on every word, meant for a background thread that keeps
+/// the words of a structure free of descriptors.
+pub fn help_all<'a, T, I>(atomics: I)
+where
+    T: Word,
+    I: IntoIterator<Item = &'a Atomic<T>>,
+{
+    for atomic in atomics {
+        help_at(atomic);
+    }
+}
+
+/// A multi-word CAS over a fixed set of addresses that can be executed many
+/// times. Addresses are sorted once on construction, every `exec` only
+/// overwrites expected and new values.
+pub struct MwCasOp<'a, T: Word> {
+    entries: ArrayVec<[Entry<'a>; MAX_ENTRIES]>,
+    // position in `entries` of the i-th address passed to `new`
+    order: ArrayVec<[usize; MAX_ENTRIES]>,
+    _marker: PhantomData<T>,
+}
+
+impl<'a, T: Word> MwCasOp<'a, T> {
+    pub fn new(addresses: &[&'a Atomic<T>]) -> Result<Self, CasNError> {
+        assert!(addresses.len() <= MAX_ENTRIES);
+        let mut by_addr: ArrayVec<[usize; MAX_ENTRIES]> = (0..addresses.len()).collect();
+        by_addr.sort_unstable_by_key(|&i| addresses[i] as *const Atomic<T>);
+        if by_addr
+            .windows(2)
+            .any(|w| ptr::eq(addresses[w[0]], addresses[w[1]]))
+        {
+            return Err(CasNError::DuplicateAddress);
+        }
+
+        let mut order: ArrayVec<[usize; MAX_ENTRIES]> = by_addr.clone();
+        for (pos, &i) in by_addr.iter().enumerate() {
+            order[i] = pos;
+        }
+        let entries = by_addr
+            .iter()
+            .map(|&i| Entry {
+                addr: addresses[i].as_atomic_bits(),
+                exp: Bits::from_usize(0),
+                new: Bits::from_usize(0),
+            })
+            .collect();
+        Ok(Self {
+            entries,
+            order,
+            _marker: PhantomData,
+        })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// `expected[i]` and `new[i]` belong to the i-th address passed to `new`.
+    #[must_use]
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn exec(&mut self, expected: &[T], new: &[T]) -> bool {
+        assert_eq!(expected.len(), self.len());
+        assert_eq!(new.len(), self.len());
+        for ((&pos, exp), new) in self.order.iter().zip(expected).zip(new) {
+            let entry = &mut self.entries[pos];
+            entry.exp = (*exp).into();
+            entry.new = (*new).into();
+        }
+        exec_sorted(
+            &DEFAULT_DOMAIN.casn,
+            None,
+            &self.entries,
+            false,
+            backoff::global(),
+            false,
+        )
+    }
+}
+
+/// A single `(address, expected, new)` triple of a multi-word CAS.
+#[derive(Clone, Copy)]
+pub struct CasEntry<'a, T: Word> {
+    pub addr: &'a Atomic<T>,
+    pub expected: T,
+    pub new: T,
+}
+
+impl<'a, T: Word> CasEntry<'a, T> {
+    #[inline]
+    pub fn new(addr: &'a Atomic<T>, expected: T, new: T) -> Self {
+        Self {
+            addr,
+            expected,
+            new,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CasNError {
+    /// A pointer has its reserved low bits set or an integer is larger
+    /// than `Atomic::<usize>::MAX`.
+    InvalidOperand,
+    /// The same address appears twice with different expected or new values.
+    DuplicateAddress,
+    /// The calling thread couldn't be registered, all thread ids are taken.
+    ThreadLimit,
+    /// The operation ran out of its `Options` budget while other operations
+    /// were in its words, it had no effect.
+    Timeout,
+}
+
+impl fmt::Display for CasNError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CasNError::InvalidOperand => {
+                f.write_str("operand overlaps the reserved descriptor bits")
+            },
+            CasNError::DuplicateAddress => {
+                f.write_str("address used twice with conflicting values")
+            },
+            CasNError::ThreadLimit => write!(
+                f,
+                "no free thread slots left, all {} are used",
+                MAX_REGISTERED
+            ),
+            CasNError::Timeout => f.write_str("gave up on other operations in the words"),
+        }
+    }
+}
+
+impl Error for CasNError {}
+
+/// Why a `compare_exchange_n` failed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CasNFailure<T> {
+    /// Position of the entry in the slices passed in.
+    pub index: usize,
+    /// What the word held instead of the expected value.
+    pub current: T,
+}
+
+thread_local! {
+    // sorted index and value of the entry the last `compare_exchange_n` of the
+    // thread failed on, if it saw the value itself
+    static MISMATCH: Cell<Option<(usize, Bits)>> = const { Cell::new(None) };
+}
+
+// Memory orderings:
+// - per thread descriptors are published like a seqlock: the owner invalidates
+//   the sequence number, issues a release fence, writes the entries relaxed
+//   and releases the new sequence number. Readers load the sequence number
+//   with acquire, read the entries relaxed, issue an acquire fence and
+//   validate with a relaxed load. With `--cfg tsan` the fences are replaced
+//   by release stores and acquire loads of the entries, see `sequence_number`.
+// - loads and CASes of the data words and the status CAS stay `SeqCst`, they
+//   are the linearization points of the operation and independent reads of
+//   several words must agree on the order of the writes (IRIW).
+// - the status loads of `run` only have to acquire. A decided status never
+//   changes again, so a stale read can only show UNDECIDED for a decided
+//   operation. Installing then goes through RDCSS, which compares the status
+//   with a `SeqCst` load and leaves the words alone, and the status CAS fails
+//   or is skipped. The failed CAS, or the load that skips it, reads the
+//   decided status, and by coherence so does the load after it, so the words
+//   are cleaned up with the decided status. `peek`
+//   returns a value based on the status without such a CAS in between, its
+//   load has to be `SeqCst` to be ordered after the load of the word.
+// - on x86_64 every load is acquire and `SeqCst` loads are plain moves, the
+//   same code as acquire ones, so the stronger ordering is kept there. The
+//   seqlock fences don't emit an instruction either, they only keep the
+//   compiler from reordering, so there is nothing to drop. On AArch64 an
+//   acquire load can be an LDAPR where `SeqCst` needs an LDAR that waits for
+//   earlier release stores.
 pub(crate) struct CasNDescriptor {
-    map: ThreadLocal<ThreadCasNDescriptor>,
+    threads: ThreadTable<ThreadDescriptors>,
+    // the ids the tables are indexed by in a domain that hands out ids of its
+    // own, `None` for the global thread ids
+    ids: Option<Arc<DomainIds>>,
+}
+
+// Everything a thread id owns in a domain, kept in one slot so that a domain
+// allocates and pads a single table.
+#[derive(Default)]
+pub(crate) struct ThreadDescriptors {
+    #[cfg(not(feature = "heap-descriptors"))]
+    casn: ThreadCasNDescriptor,
+    #[cfg(not(feature = "heap-descriptors"))]
+    pub(crate) rdcss: ThreadRDCSSDescriptor,
+    #[cfg(feature = "wait-free")]
+    announcement: AtomicBits,
+    // next announcement slot this thread is going to look at
+    #[cfg(feature = "wait-free")]
+    help_cursor: StdAtomicUsize,
 }
 
 impl CasNDescriptor {
     pub const MARK: usize = 2;
 
-    pub fn new() -> Self {
+    // tables indexed by the global thread ids
+    pub fn with_max_threads(max_threads: usize) -> Self {
         Self {
-            map: ThreadLocal::new(),
+            threads: ThreadTable::with_capacity(max_threads),
+            ids: None,
         }
     }
 
-    pub fn make_descriptor(&'static self, entries: &mut [Entry]) -> Bits {
-        let (tid, per_thread_descriptor) = CASN_DESCRIPTOR.map.get();
+    // Tables indexed by ids of their own, so that only the threads using them
+    // count against `max_threads`. Slots leased per operation with `per-cpu`
+    // don't belong to ids, and heap descriptors don't take a slot at all
+    // unless operations are announced.
+    pub fn with_own_ids(max_threads: usize) -> Self {
+        let ids = if cfg!(any(
+            feature = "per-cpu",
+            all(feature = "heap-descriptors", not(feature = "wait-free"))
+        )) {
+            None
+        } else if max_threads == MAX_THREADS {
+            // the table grows for every id there is
+            Some(DomainIds::new(MAX_REGISTERED))
+        } else {
+            Some(DomainIds::new(max_threads))
+        };
+        Self {
+            threads: ThreadTable::with_capacity(max_threads),
+            ids,
+        }
+    }
+
+    // The id of the calling thread in the tables: `global`, its global id if
+    // the caller knows it, or the id of its own the domain hands out.
+    pub fn try_owner(
+        &self,
+        global: Option<ThreadId>,
+    ) -> Result<Option<ThreadId>, CasNError> {
+        match &self.ids {
+            Some(ids) => ids.current().map(Some),
+            None => Ok(global),
+        }
+    }
+
+    // `owner` if the caller knows the id already, otherwise the id of the
+    // calling thread for a domain with ids of its own. `None` stays `None`
+    // for the global ids, `ThreadTable::get_slot_of` looks them up only if a
+    // slot is needed. Panics if the thread can't get an id.
+    pub fn owner(&self, owner: Option<ThreadId>) -> Option<ThreadId> {
+        match (owner, &self.ids) {
+            (None, Some(ids)) => match ids.current() {
+                Ok(id) => Some(id),
+                Err(err) => panic!("{}", err),
+            },
+            _ => owner,
+        }
+    }
+
+    // installs the descriptor into the words of its entries
+    pub(crate) fn rdcss(&self) -> RDCSSDescriptor<'_> {
+        RDCSSDescriptor::new(&self.threads)
+    }
+
+    // makes every pointer to the descriptor of the calling thread stale, its
+    // own operations are always finished by the time this can be called
+    #[cfg(not(feature = "heap-descriptors"))]
+    pub fn invalidate(&self) {
+        let (_, thread) = self.threads.get_slot_of(self.owner(None));
+        thread.casn.inc_seq(Ordering::SeqCst);
+    }
+
+    // heap descriptors are never reused, there is nothing to invalidate
+    #[cfg(feature = "heap-descriptors")]
+    pub fn invalidate(&self) {}
+
+    #[cfg(not(feature = "heap-descriptors"))]
+    pub fn make_descriptor(&self, owner: Option<ThreadId>, entries: &[Entry]) -> Bits {
+        let (tid, thread) = self.threads.get_slot_of(owner);
+        let per_thread_descriptor = &thread.casn;
 
         // invalidate current descriptor
-        per_thread_descriptor.inc_seq();
+        per_thread_descriptor.inc_seq(Ordering::Relaxed);
 
-        fence(Ordering::Release);
+        // keeps the entry stores below from becoming visible before the
+        // invalidation, helpers validate their snapshot against it
+        sequence_number::publish_fence();
 
-        // sort and store addresses
+        // store already sorted addresses
         per_thread_descriptor.store_entries(entries);
         // make descriptor fully initialized
-        per_thread_descriptor.inc_seq();
-        let current_seq_num = per_thread_descriptor
-            .status
-            .load(Ordering::SeqCst)
-            .seq_number();
+        let current_seq_num = per_thread_descriptor.inc_seq(Ordering::Release);
+
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            tid = tid.as_u16(),
+            seq = current_seq_num.as_usize(),
+            entries = entries.len(),
+            "descriptor created"
+        );
 
         // create a ptr for descriptor
         Bits::new_descriptor_ptr(tid, current_seq_num).with_mark(Self::MARK)
     }
 
+    #[cfg(feature = "heap-descriptors")]
+    pub fn make_descriptor(&self, _owner: Option<ThreadId>, entries: &[Entry]) -> Bits {
+        // same as for the per thread descriptors, the words of the entries have
+        // to outlive everybody who can still come across the descriptor
+        let entries = entries
+            .iter()
+            .map(|entry| unsafe { mem::transmute::<Entry<'_>, Entry<'static>>(*entry) })
+            .collect();
+        let descriptor = HeapCasNDescriptor {
+            entries,
+            status: AtomicCasNDescriptorStatus::new(),
+        };
+        let descriptor_ptr = heap::into_ptr(Box::new(descriptor), Self::MARK);
+        // the bits of the address take the place of the sequence number, so
+        // statuses are validated against descriptor pointers the usual way
+        let descriptor = unsafe { heap::deref::<HeapCasNDescriptor>(descriptor_ptr) };
+        descriptor.status.store(
+            CasNDescriptorStatus::undecided(descriptor_ptr.seq()),
+            Ordering::Relaxed,
+        );
+        #[cfg(feature = "tracing")]
+        tracing::trace!(
+            tid = descriptor_ptr.tid().as_u16(),
+            seq = descriptor_ptr.seq().as_usize(),
+            entries = descriptor.entries.len(),
+            "descriptor created"
+        );
+        descriptor_ptr
+    }
+
+    // reads the value of the word, helping any descriptor installed in it
+    pub fn read(&self, addr: &AtomicBits) -> Bits {
+        #[cfg(feature = "heap-descriptors")]
+        let _guard = crossbeam_epoch::pin();
+        #[cfg(feature = "per-cpu")]
+        let _lease = Lease::acquire();
+        let mut watchdog = Watchdog::new();
+        loop {
+            let curr = self.rdcss().read(addr);
+            if curr.mark() == Self::MARK {
+                watchdog
+                    .tick(|| format!("read of {:p} against {}", addr, describe(curr)));
+                self.help(None, curr, true, backoff::global());
+            } else {
+                return curr;
+            }
+        }
+    }
+
+    // same as `read`, but waits for the descriptor to go away for as long as
+    // `backoff` allows before helping it
+    pub fn read_with_backoff(
+        &self,
+        addr: &AtomicBits,
+        backoff: &dyn BackoffPolicy,
+    ) -> Bits {
+        #[cfg(feature = "heap-descriptors")]
+        let _guard = crossbeam_epoch::pin();
+        #[cfg(feature = "per-cpu")]
+        let _lease = Lease::acquire();
+        let mut step = 0;
+        let mut watchdog = Watchdog::new();
+        loop {
+            let curr = self.rdcss().read(addr);
+            if curr.mark() != Self::MARK {
+                return curr;
+            }
+            watchdog.tick(|| format!("read of {:p} against {}", addr, describe(curr)));
+            if backoff.wait(step) {
+                step += 1;
+            } else {
+                self.help(None, curr, true, backoff);
+            }
+        }
+    }
+
+    // Resolves the logical value of the word from the descriptor installed in
+    // it instead of helping: the new value once the operation has succeeded,
+    // the expected one before that or if it failed.
+    pub fn peek(&self, addr: &AtomicBits) -> Bits {
+        #[cfg(feature = "heap-descriptors")]
+        let _guard = crossbeam_epoch::pin();
+        loop {
+            let curr = self.rdcss().read(addr);
+            if curr.mark() != Self::MARK {
+                return curr;
+            }
+            // the descriptor can only go away after the operation is decided,
+            // so the status read here is valid for the time `curr` was read
+            if let Ok(snapshot) = self.try_snapshot(curr) {
+                if let Ok(status) = snapshot.try_read_status(curr, Ordering::SeqCst) {
+                    if let Some(entry) =
+                        snapshot.entries.iter().find(|e| ptr::eq(e.addr, addr))
+                    {
+                        return if status.status() == CasNDescriptorStatus::SUCCEEDED {
+                            entry.new
+                        } else {
+                            entry.exp
+                        };
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(not(feature = "heap-descriptors"))]
     fn try_snapshot(
-        &'static self,
+        &self,
         descriptor_ptr: Bits,
-    ) -> Result<ThreadCasNDescriptorSnapshot, ()> {
-        let thread_descriptor = self.map.get_for_thread(descriptor_ptr.tid());
+    ) -> Result<ThreadCasNDescriptorSnapshot<'_>, ()> {
+        let thread_descriptor = &self.threads.get_for_thread(descriptor_ptr.tid()).casn;
         thread_descriptor.try_snapshot(descriptor_ptr.seq())
     }
 
-    pub fn help(&'static self, descriptor_ptr: Bits, help_other: bool) -> bool {
+    // the caller is pinned, so the descriptor can't be freed under it
+    #[cfg(feature = "heap-descriptors")]
+    fn try_snapshot(
+        &self,
+        descriptor_ptr: Bits,
+    ) -> Result<ThreadCasNDescriptorSnapshot<'_>, ()> {
+        let descriptor = unsafe { heap::deref::<HeapCasNDescriptor>(descriptor_ptr) };
+        Ok(ThreadCasNDescriptorSnapshot {
+            entries: descriptor.entries.iter().copied().collect(),
+            status: &descriptor.status,
+        })
+    }
+
+    pub fn help(
+        &self,
+        owner: Option<ThreadId>,
+        descriptor_ptr: Bits,
+        help_other: bool,
+        backoff: &dyn BackoffPolicy,
+    ) -> bool {
+        // a helper found the descriptor installed, so at least the first entry
+        // is installed already
+        let start = if help_other { 1 } else { 0 };
+        self.run(
+            owner,
+            descriptor_ptr,
+            help_other,
+            false,
+            start,
+            backoff,
+            false,
+        )
+    }
+
+    // Runs our own descriptor without helping anyone, a foreign descriptor in
+    // any of the entries makes it fail.
+    pub fn help_weak(&self, owner: Option<ThreadId>, descriptor_ptr: Bits) -> bool {
+        self.run(
+            owner,
+            descriptor_ptr,
+            false,
+            true,
+            0,
+            backoff::global(),
+            false,
+        )
+    }
+
+    // Helps the operation announced in the next slot. Every thread visits all
+    // slots in turn, so an announced operation gets finished after at most
+    // as many operations of every other thread as there are slots.
+    #[cfg(feature = "wait-free")]
+    pub fn help_announced(&self, owner: Option<ThreadId>) {
+        let (_, thread) = self.threads.get_slot_of(owner);
+        let cursor = thread.help_cursor.load(Ordering::Relaxed);
+        thread
+            .help_cursor
+            .store((cursor + 1) % self.threads.capacity(), Ordering::Relaxed);
+        let announced = self
+            .threads
+            .get_for_thread(ThreadId::from_u16(cursor as u16))
+            .announcement
+            // a stale pointer is rejected by the descriptor snapshot
+            .load(Ordering::Relaxed);
+        if announced.mark() == Self::MARK {
+            // the announced operation may not have installed a single entry yet
+            self.run(owner, announced, true, false, 0, backoff::global(), false);
+        }
+    }
+
+    // `record_mismatch` is only set by the owner, see `exec_sorted`
+    #[allow(clippy::too_many_arguments)]
+    fn run(
+        &self,
+        owner: Option<ThreadId>,
+        descriptor_ptr: Bits,
+        help_other: bool,
+        weak: bool,
+        start: usize,
+        backoff: &dyn BackoffPolicy,
+        record_mismatch: bool,
+    ) -> bool {
+        // helpers reading a word come without an id
+        let owner = self.owner(owner);
         let descriptor_seq = descriptor_ptr.seq();
+        #[cfg(feature = "stats")]
+        if help_other {
+            stats::record(Event::Help);
+        }
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!(
+            "cas_n",
+            tid = descriptor_ptr.tid().as_u16(),
+            seq = descriptor_seq.as_usize(),
+            help_other
+        )
+        .entered();
 
         // try to snapshot descriptor we was helping
         let descriptor_snapshot = self.try_snapshot(descriptor_ptr);
@@ -141,64 +1423,170 @@ impl CasNDescriptor {
             Ok(descriptor_snapshot) => {
                 // Phase 1: try to install descriptor in all entries
                 // Only if des has status == UNDECIDED
-                let descriptor_current_status =
-                    match descriptor_snapshot.try_read_status(descriptor_ptr) {
-                        Ok(status) => status,
-                        Err(_) => {
-                            assert!(help_other);
-                            return false;
-                        },
-                    };
+                let descriptor_current_status = match descriptor_snapshot
+                    .try_read_status(descriptor_ptr, RUN_STATUS_LOAD)
+                {
+                    Ok(status) => status,
+                    Err(_) => {
+                        assert!(help_other);
+                        return false;
+                    },
+                };
                 if descriptor_current_status.status() == CasNDescriptorStatus::UNDECIDED {
+                    // the words are installed one after the other, fetching all
+                    // of them up front overlaps their misses
+                    for entry in &descriptor_snapshot.entries[start..] {
+                        prefetch(entry.addr);
+                    }
                     let mut new_status = CasNDescriptorStatus::succeeded(descriptor_seq);
-                    let start = if help_other { 1 } else { 0 };
-                    let backoff = Backoff::new();
+                    let mut step = 0;
+                    let mut watchdog = Watchdog::new();
+                    #[cfg(feature = "wait-free")]
+                    let mut retries = 0;
                     'entry_loop: for entry in &descriptor_snapshot.entries[start..] {
                         'install_loop: loop {
                             let entry_addr = entry.addr;
                             let entry_exp = entry.exp;
-                            let swapped = RDCSS_DESCRIPTOR.rdcss(
-                                &descriptor_snapshot.status,
+                            let swapped = self.rdcss().rdcss(
+                                owner,
+                                descriptor_snapshot.status.as_raw(),
                                 entry_addr,
-                                descriptor_current_status,
+                                descriptor_current_status.as_usize(),
                                 entry_exp,
                                 descriptor_ptr,
+                                weak,
                             );
 
-                            if swapped.mark() == CasNDescriptor::MARK
-                                && swapped != descriptor_ptr
-                            {
-                                if backoff.is_completed() {
-                                    self.help(swapped, true);
+                            #[cfg(feature = "stats")]
+                            if swapped == entry_exp {
+                                stats::record(Event::Install);
+                            }
+                            if swapped == entry_exp || swapped == descriptor_ptr {
+                                // installed by us or by someone helping us
+                                #[cfg(feature = "tracing")]
+                                tracing::trace!(
+                                    addr = ?(entry_addr as *const AtomicBits),
+                                    "entry installed"
+                                );
+                                break 'install_loop;
+                            } else if swapped.mark() == CasNDescriptor::MARK && !weak {
+                                #[cfg(feature = "wait-free")]
+                                {
+                                    retries += 1;
+                                    if !help_other && retries == ANNOUNCE_AFTER_RETRIES {
+                                        self.threads
+                                            .get_slot_of(owner)
+                                            .1
+                                            .announcement
+                                            .store(descriptor_ptr, Ordering::Relaxed);
+                                    }
+                                }
+                                watchdog.tick(|| {
+                                    let addresses: Vec<_> = descriptor_snapshot
+                                        .entries
+                                        .iter()
+                                        .map(|e| e.addr as *const AtomicBits)
+                                        .collect();
+                                    format!(
+                                        "{} with entries {:?} installing at {:p} against {}",
+                                        describe(descriptor_ptr),
+                                        addresses,
+                                        entry_addr,
+                                        describe(swapped)
+                                    )
+                                });
+                                if backoff.wait(step) {
+                                    step += 1;
                                 } else {
-                                    backoff.spin();
+                                    self.help(owner, swapped, true, backoff);
                                 }
                                 continue 'install_loop;
-                            } else if swapped != entry_exp {
+                            } else {
+                                if record_mismatch && !swapped.is_descriptor() {
+                                    let entries = &descriptor_snapshot.entries;
+                                    let i =
+                                        entries.iter().position(|e| ptr::eq(e, entry));
+                                    MISMATCH.with(|mismatch| {
+                                        mismatch.set(i.map(|i| (i, swapped)))
+                                    });
+                                }
+                                #[cfg(feature = "tracing")]
+                                tracing::trace!(
+                                    addr = ?(entry_addr as *const AtomicBits),
+                                    found = ?swapped,
+                                    "entry mismatch"
+                                );
                                 new_status = new_status.set_failed();
                                 break 'entry_loop;
-                            } else {
-                                break 'install_loop;
                             }
                         }
+                        #[cfg(feature = "test-internals")]
+                        {
+                            let entries = &descriptor_snapshot.entries;
+                            let i = entries.iter().position(|e| ptr::eq(e, entry));
+                            test_hooks::reached(Point::Installed(i.unwrap()));
+                        }
                     }
+                    #[cfg(feature = "test-internals")]
+                    test_hooks::reached(Point::BeforeStatusCas);
                     descriptor_snapshot.cas_status(descriptor_current_status, new_status);
+                    #[cfg(feature = "wait-free")]
+                    {
+                        if !help_other && retries >= ANNOUNCE_AFTER_RETRIES {
+                            self.threads
+                                .get_slot_of(owner)
+                                .1
+                                .announcement
+                                .store(Bits::from_usize(0), Ordering::Relaxed);
+                        }
+                    }
                 }
-                let descriptor_current_status =
-                    match descriptor_snapshot.try_read_status(descriptor_ptr) {
-                        Ok(status) => status,
-                        Err(()) => {
-                            assert!(help_other);
-                            return false;
-                        },
-                    };
+                let descriptor_current_status = match descriptor_snapshot
+                    .try_read_status(descriptor_ptr, RUN_STATUS_LOAD)
+                {
+                    Ok(status) => status,
+                    Err(()) => {
+                        assert!(help_other);
+                        return false;
+                    },
+                };
 
                 let succeeded =
                     descriptor_current_status.status() == CasNDescriptorStatus::SUCCEEDED;
-                for entry in &descriptor_snapshot.entries {
-                    let new = if succeeded { entry.new } else { entry.exp };
-                    let _ = entry.addr.compare_exchange(descriptor_ptr, new);
+                #[cfg(feature = "tracing")]
+                tracing::trace!(succeeded, "status decided");
+                #[cfg(feature = "test-internals")]
+                test_hooks::reached(Point::BeforeCleanup);
+                let entries = &descriptor_snapshot.entries;
+                let value =
+                    |entry: &Entry<'_>| if succeeded { entry.new } else { entry.exp };
+                let mut i = 0;
+                while i < entries.len() {
+                    // neighbouring words in one 16 byte pair are cleared together,
+                    // if one of them is cleared already it's one word at a time
+                    #[cfg(all(feature = "coalesce", target_arch = "x86_64", not(loom)))]
+                    if let Some(next) = entries.get(i + 1) {
+                        let (first, second) = (entries[i].addr, next.addr);
+                        if coalesce::is_pair(first, second)
+                            && coalesce::available()
+                            && unsafe {
+                                coalesce::cas_pair(
+                                    first,
+                                    [descriptor_ptr; 2],
+                                    [value(&entries[i]), value(next)],
+                                )
+                            }
+                        {
+                            i += 2;
+                            continue;
+                        }
+                    }
+                    let entry = &entries[i];
+                    let _ = entry.addr.compare_exchange(descriptor_ptr, value(entry));
+                    i += 1;
                 }
+                #[cfg(feature = "tracing")]
+                tracing::trace!("descriptor removed");
                 succeeded
             },
             Err(_) => {
@@ -210,26 +1598,45 @@ impl CasNDescriptor {
     }
 }
 
-const MAX_ENTRIES: usize = 4;
+pub(crate) const MAX_ENTRIES: usize = 4;
+
+// hints the cache to fetch the line of `ptr`, a no-op without a prefetch
+// instruction
+#[inline(always)]
+fn prefetch<T>(ptr: *const T) {
+    #[cfg(target_arch = "x86_64")]
+    unsafe {
+        use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+        _mm_prefetch::<_MM_HINT_T0>(ptr.cast());
+    }
+}
 
+// ordering of the status loads in `run`, see the memory orderings above. loom
+// checks the weaker one.
+#[cfg(all(target_arch = "x86_64", not(loom)))]
+const RUN_STATUS_LOAD: Ordering = Ordering::SeqCst;
+#[cfg(not(all(target_arch = "x86_64", not(loom))))]
+const RUN_STATUS_LOAD: Ordering = Ordering::Acquire;
+
+// number of times an operation runs into other descriptors before it asks
+// everyone else for help
+#[cfg(feature = "wait-free")]
+const ANNOUNCE_AFTER_RETRIES: usize = 64;
+
+// In the order a snapshot reads the fields, so a helper on another core
+// streams through adjacent lines instead of missing on them one by one.
+#[cfg(not(feature = "heap-descriptors"))]
+#[repr(C)]
 struct ThreadCasNDescriptor {
-    pub entries: [AtomicEntry; MAX_ENTRIES],
-    pub num_entries: StdAtomicUsize,
     pub status: AtomicCasNDescriptorStatus,
+    pub num_entries: StdAtomicUsize,
+    pub entries: [AtomicEntry; MAX_ENTRIES],
 }
 
+#[cfg(not(feature = "heap-descriptors"))]
 impl ThreadCasNDescriptor {
     fn new() -> Self {
-        let entries = {
-            let mut data: [MaybeUninit<AtomicEntry>; MAX_ENTRIES] =
-                unsafe { MaybeUninit::uninit().assume_init() };
-
-            for elem in &mut data[..] {
-                *elem = MaybeUninit::new(AtomicEntry::empty());
-            }
-
-            unsafe { mem::transmute::<_, [AtomicEntry; MAX_ENTRIES]>(data) }
-        };
+        let entries = array::from_fn(|_| AtomicEntry::empty());
         Self {
             status: AtomicCasNDescriptorStatus::new(),
             num_entries: StdAtomicUsize::new(0),
@@ -238,26 +1645,32 @@ impl ThreadCasNDescriptor {
     }
 
     // only thread who owns this descriptor is allowed to call this function
-    fn inc_seq(&self) {
+    fn inc_seq(&self, store_ordering: Ordering) -> SeqNumber {
         let seq_num = self.status.load(Ordering::Relaxed).seq_number().inc();
         self.status
-            .store(CasNDescriptorStatus::undecided(seq_num), Ordering::SeqCst)
+            .store(CasNDescriptorStatus::undecided(seq_num), store_ordering);
+        seq_num
     }
 
     fn try_snapshot(
         &self,
         seq_num: SeqNumber,
-    ) -> Result<ThreadCasNDescriptorSnapshot, ()> {
-        let current_seq_num = self.status.load(Ordering::SeqCst).seq_number();
+    ) -> Result<ThreadCasNDescriptorSnapshot<'_>, ()> {
+        // seqlock read: the acquire load pairs with the release store that
+        // published the entries, the fence keeps the entry loads from moving
+        // past the validating load
+        let current_seq_num = self.status.load(Ordering::Acquire).seq_number();
         if current_seq_num == seq_num {
-            let num_entries = self.num_entries.load(Ordering::Relaxed);
+            // the entries past the line of the status
+            prefetch(&self.entries[MAX_ENTRIES - 1]);
+            let num_entries = self.num_entries.load(FIELD_LOAD);
             let entries = self.entries[0..num_entries]
                 .iter()
                 .map(|atomic_entry| atomic_entry.load())
                 .collect();
 
-            fence(Ordering::Acquire);
-            if seq_num == self.status.load(Ordering::SeqCst).seq_number() {
+            sequence_number::validate_fence();
+            if seq_num == self.status.load(Ordering::Relaxed).seq_number() {
                 Ok(ThreadCasNDescriptorSnapshot {
                     entries,
                     status: &self.status,
@@ -270,29 +1683,42 @@ impl ThreadCasNDescriptor {
         }
     }
 
-    fn store_entries(&self, entries: &mut [Entry<'_>]) {
-        entries.sort_by_key(|e| e.addr as *const AtomicBits);
-        for (atomic_entry, entry) in self.entries.iter().zip(&*entries) {
+    fn store_entries(&self, entries: &[Entry<'_>]) {
+        debug_assert!(entries
+            .windows(2)
+            .all(|w| (w[0].addr as *const AtomicBits) < (w[1].addr as *const _)));
+        for (atomic_entry, entry) in self.entries.iter().zip(entries) {
             atomic_entry.store(entry);
         }
-        self.num_entries.store(entries.len(), Ordering::Relaxed);
+        self.num_entries.store(entries.len(), FIELD_STORE);
     }
 }
 
+#[cfg(not(feature = "heap-descriptors"))]
 impl Default for ThreadCasNDescriptor {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[cfg(feature = "heap-descriptors")]
+struct HeapCasNDescriptor {
+    entries: ArrayVec<[Entry<'static>; MAX_ENTRIES]>,
+    status: AtomicCasNDescriptorStatus,
+}
+
 struct ThreadCasNDescriptorSnapshot<'a> {
     entries: ArrayVec<[Entry<'a>; MAX_ENTRIES]>,
     status: &'a AtomicCasNDescriptorStatus,
 }
 
 impl ThreadCasNDescriptorSnapshot<'_> {
-    fn try_read_status(&self, descriptor_ptr: Bits) -> Result<CasNDescriptorStatus, ()> {
-        let status = self.status.load(Ordering::SeqCst);
+    fn try_read_status(
+        &self,
+        descriptor_ptr: Bits,
+        ordering: Ordering,
+    ) -> Result<CasNDescriptorStatus, ()> {
+        let status = self.status.load(ordering);
         if status.seq_number() == descriptor_ptr.seq() {
             Ok(status)
         } else {
@@ -306,7 +1732,8 @@ impl ThreadCasNDescriptorSnapshot<'_> {
         new_status: CasNDescriptorStatus,
     ) {
         assert_eq!(expected_status.status(), CasNDescriptorStatus::UNDECIDED);
-        let current_status = self.status.load(Ordering::SeqCst);
+        // only saves a CAS that is bound to fail, the CAS itself decides
+        let current_status = self.status.load(Ordering::Relaxed);
         if current_status == expected_status {
             let _ = self.status.compare_exchange(expected_status, new_status);
         }
@@ -328,6 +1755,10 @@ impl AtomicCasNDescriptorStatus {
         self.0.store(status.0, ordering);
     }
 
+    pub fn as_raw(&self) -> &StdAtomicUsize {
+        &self.0
+    }
+
     pub fn compare_exchange(
         &self,
         expected_status: CasNDescriptorStatus,
@@ -385,14 +1816,20 @@ impl CasNDescriptorStatus {
     fn from_usize(status: usize) -> Self {
         Self(status)
     }
+
+    pub(crate) fn as_usize(self) -> usize {
+        self.0
+    }
 }
 
+#[cfg(not(feature = "heap-descriptors"))]
 struct AtomicEntry {
     addr: AtomicAddress<AtomicBits>,
     exp: AtomicBits,
     new: AtomicBits,
 }
 
+#[cfg(not(feature = "heap-descriptors"))]
 impl AtomicEntry {
     fn empty() -> Self {
         Self {
@@ -403,30 +1840,82 @@ impl AtomicEntry {
     }
 
     fn load<'a>(&self) -> Entry<'a> {
-        let addr = unsafe { self.addr.load(Ordering::Relaxed) };
-        let exp = self.exp.load(Ordering::Relaxed);
-        let new = self.new.load(Ordering::Relaxed);
+        let addr = unsafe { self.addr.load(FIELD_LOAD) };
+        let exp = self.exp.load(FIELD_LOAD);
+        let new = self.new.load(FIELD_LOAD);
         Entry { addr, exp, new }
     }
 
     fn store(&self, e: &Entry) {
-        self.addr.store(e.addr, Ordering::Relaxed);
-        self.new.store(e.new, Ordering::Relaxed);
-        self.exp.store(e.exp, Ordering::Relaxed);
+        self.addr.store(e.addr, FIELD_STORE);
+        self.new.store(e.new, FIELD_STORE);
+        self.exp.store(e.exp, FIELD_STORE);
     }
 }
 
+#[derive(Clone, Copy)]
 pub(crate) struct Entry<'a> {
     addr: &'a AtomicBits,
     exp: Bits,
     new: Bits,
 }
 
+impl<'a> Entry<'a> {
+    #[inline]
+    pub(crate) fn new<T: Word>(addr: &'a Atomic<T>, exp: T, new: T) -> Self {
+        Self {
+            addr: addr.as_atomic_bits(),
+            exp: exp.into(),
+            new: new.into(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crossbeam_epoch::{pin, Owned, Shared};
-    use std::sync::Arc;
+    use std::sync::{atomic::AtomicU32, Arc};
+
+    // counts its calls and gives up waiting after `self.0` steps
+    struct HelpAfter(u32, AtomicU32);
+
+    impl BackoffPolicy for HelpAfter {
+        fn wait(&self, step: u32) -> bool {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            step < self.0
+        }
+    }
+
+    // Heap descriptors don't take a thread id, so more threads than there are
+    // ids can run operations. Features that keep per thread state still
+    // register.
+    #[test]
+    #[cfg(all(
+        feature = "heap-descriptors",
+        not(any(feature = "wait-free", feature = "per-cpu", feature = "stats", loom))
+    ))]
+    fn test_heap_descriptors_without_ids() {
+        // in waves, as many threads at once don't fit into the address space
+        // of every machine
+        for _ in 0..=MAX_REGISTERED / 64 {
+            std::thread::scope(|s| {
+                for _ in 0..64 {
+                    s.spawn(|| {
+                        let words = [Atomic::new(0usize), Atomic::new(0usize)];
+                        let mut cas_n = CASN::new();
+                        cas_n.add_unchecked(&words[0], 0, 1);
+                        cas_n.add_unchecked(&words[1], 0, 2);
+                        assert_eq!(unsafe { cas_n.try_exec() }, Ok(true));
+                        let mut batch = [CASN::new()];
+                        batch[0].add_unchecked(&words[0], 1, 3);
+                        assert_eq!(unsafe { exec_batch(&mut batch) }, [Ok(true)]);
+                        assert_eq!(ThreadId::registered(), None);
+                    });
+                }
+            });
+        }
+    }
 
     #[test]
     fn test_mcas() {
@@ -516,11 +2005,823 @@ mod test {
         unsafe {
             let first = counter.0.load();
             assert_eq!(*first, max);
-            Box::from_raw(first as *mut u32);
+            drop(Box::from_raw(first as *mut u64));
 
             let second = counter.1.load();
             assert_eq!(*second, max);
-            Box::from_raw(second as *mut u32);
+            drop(Box::from_raw(second as *mut u64));
+        }
+    }
+
+    #[test]
+    fn test_try_cas_n_invalid_operand() {
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(0usize);
+        let res = unsafe { try_cas_n(&[&atom0, &atom1], &[0, 0], &[1, usize::MAX]) };
+        assert_eq!(res, Err(CasNError::InvalidOperand));
+        assert_eq!(atom0.load(), 0);
+        assert_eq!(atom1.load(), 0);
+
+        let value = 0u64;
+        let aligned = &value as *const u64 as *const u8;
+        let atom = Atomic::new(aligned);
+        for offset in 1..4 {
+            let misaligned = aligned.wrapping_add(offset);
+            let res = unsafe { try_cas_n(&[&atom], &[aligned], &[misaligned]) };
+            assert_eq!(res, Err(CasNError::InvalidOperand));
+        }
+        assert_eq!(atom.load(), aligned);
+
+        let res = unsafe { try_cas_n(&[&atom0, &atom1], &[0, 0], &[1, 2]) };
+        assert_eq!(res, Ok(true));
+        assert_eq!(atom0.load(), 1);
+        assert_eq!(atom1.load(), 2);
+    }
+
+    #[test]
+    fn test_duplicate_address() {
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(0usize);
+
+        let res = unsafe { try_cas_n(&[&atom0, &atom1, &atom0], &[0, 0, 0], &[1, 1, 1]) };
+        assert_eq!(res, Ok(true));
+        assert_eq!(atom0.load(), 1);
+        assert_eq!(atom1.load(), 1);
+
+        let res = unsafe { try_cas_n(&[&atom0, &atom1, &atom0], &[1, 1, 1], &[2, 2, 3]) };
+        assert_eq!(res, Err(CasNError::DuplicateAddress));
+        let res = unsafe { try_cas_n(&[&atom0, &atom0], &[0, 1], &[2, 2]) };
+        assert_eq!(res, Err(CasNError::DuplicateAddress));
+        assert_eq!(atom0.load(), 1);
+        assert_eq!(atom1.load(), 1);
+    }
+
+    #[test]
+    fn test_cas_n_entries() {
+        let atoms: Vec<_> = (0..3).map(Atomic::new).collect();
+        let mut entries: Vec<_> = atoms
+            .iter()
+            .enumerate()
+            .map(|(i, a)| CasEntry::new(a, i, i + 10))
+            .collect();
+        assert!(unsafe { cas_n_entries(&entries) });
+        assert!(!unsafe { cas_n_entries(&entries) });
+        for (i, a) in atoms.iter().enumerate() {
+            assert_eq!(a.load(), i + 10);
+        }
+
+        entries.iter_mut().for_each(|e| e.expected = e.new);
+        entries[1].new = 100;
+        assert!(unsafe { cas_n_entries(&entries) });
+        assert_eq!(atoms[1].load(), 100);
+    }
+
+    #[test]
+    fn test_cas_n_array() {
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(1usize);
+        let atom2 = Atomic::new(2usize);
+        let addrs = [&atom2, &atom0, &atom1];
+        assert!(unsafe { cas_n_array(addrs, [2, 0, 1], [5, 3, 4]) });
+        assert!(!unsafe { cas_n_array(addrs, [2, 0, 1], [5, 3, 4]) });
+        assert_eq!(atom0.load(), 3);
+        assert_eq!(atom1.load(), 4);
+        assert_eq!(atom2.load(), 5);
+    }
+
+    #[test]
+    fn test_add_delta() {
+        let counters: Vec<_> = (0..3).map(|_| Atomic::new(0usize)).collect();
+        let flag = Atomic::new(0usize);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        unsafe { add_n(&[&counters[0], &counters[1]], &[1, 2]) };
+                        // conflicts on the counters don't fail the flag check
+                        let mut cas_n = CASN::new();
+                        cas_n.add_unchecked(&flag, 0, 0);
+                        cas_n.add_delta(&counters[2], 3).unwrap();
+                        assert!(unsafe { cas_n.exec() });
+                    }
+                });
+            }
+        });
+        let values: Vec<_> = counters.iter().map(Atomic::load).collect();
+        assert_eq!(values, [4000, 8000, 12000]);
+
+        let mut cas_n = CASN::new();
+        cas_n.add_unchecked(&flag, 1, 1);
+        cas_n.add_delta(&counters[0], 1).unwrap();
+        assert!(!unsafe { cas_n.exec() });
+        let mut cas_n = CASN::new();
+        cas_n.add_delta(&counters[0], 1).unwrap();
+        cas_n.add_delta(&counters[0], 1).unwrap();
+        assert_eq!(
+            unsafe { cas_n.try_exec() },
+            Err(CasNError::DuplicateAddress)
+        );
+        assert_eq!(counters[0].load(), 4000);
+    }
+
+    #[test]
+    #[cfg(all(not(loom), not(feature = "heap-descriptors")))]
+    fn test_descriptor_layout() {
+        // a snapshot reads at most two lines
+        let end = std::mem::offset_of!(ThreadCasNDescriptor, entries)
+            + std::mem::size_of::<[AtomicEntry; MAX_ENTRIES]>();
+        assert!(end <= 128);
+        assert_eq!(std::mem::offset_of!(ThreadCasNDescriptor, status), 0);
+    }
+
+    #[test]
+    fn test_read_word() {
+        let std_words = [StdAtomicUsize::new(1 << 2), StdAtomicUsize::new(2 << 2)];
+        let [first, second] =
+            std_words.each_ref().map(|w| unsafe { Atomic::from_std(w) });
+        assert_eq!(unsafe { read_word(&std_words[0]) }, 1);
+
+        // the owner stops with its descriptor in the words
+        #[cfg(feature = "per-cpu")]
+        let _lease = crate::per_cpu::Lease::acquire();
+        let descriptor = DEFAULT_DOMAIN
+            .casn
+            .make_descriptor(None, &[Entry::new(first, 1, 3), Entry::new(second, 2, 4)]);
+        for word in [first, second] {
+            word.as_atomic_bits().store(descriptor, Ordering::SeqCst);
+        }
+        assert_eq!(
+            std_words[1].load(Ordering::SeqCst) & 3,
+            CasNDescriptor::MARK
+        );
+        assert_eq!(unsafe { read_word(&std_words[1]) }, 4);
+        assert_eq!(std_words[0].load(Ordering::SeqCst), 3 << 2);
+    }
+
+    #[test]
+    fn test_exec_batch() {
+        let words: Vec<_> = (0..4).map(|_| Atomic::new(0usize)).collect();
+        let cas2 = |i: usize, j: usize, exp, new| {
+            let mut op = CASN::new();
+            op.add_unchecked(&words[i], exp, new);
+            op.add_unchecked(&words[j], exp, new);
+            op
+        };
+        // later operations see the effects of earlier ones
+        let mut ops = vec![
+            cas2(0, 1, 0, 1),
+            cas2(2, 3, 1, 2),
+            cas2(2, 2, 0, 3),
+            cas2(1, 2, 1, 4),
+            cas2(0, 1, 1, 5),
+        ];
+        let mut duplicate = CASN::new();
+        duplicate.add_unchecked(&words[3], 0, 1);
+        duplicate.add_unchecked(&words[3], 0, 2);
+        ops.push(duplicate);
+
+        let results = unsafe { exec_batch(&mut ops) };
+        assert_eq!(
+            results,
+            [
+                Ok(true),
+                Ok(false),
+                Ok(true),
+                Ok(false),
+                Ok(true),
+                Err(CasNError::DuplicateAddress)
+            ]
+        );
+        let values: Vec<_> = words.iter().map(Atomic::load).collect();
+        assert_eq!(values, [5, 5, 3, 0]);
+        assert!(ops.iter().all(|op| op.entries.is_empty()));
+    }
+
+    #[test]
+    fn test_cas_n_presorted() {
+        let words: Vec<_> = (0..3).map(Atomic::new).collect();
+        let sorted: Vec<_> = words.iter().collect();
+        assert!(unsafe { cas_n_presorted(&sorted, &[0, 1, 2], &[3, 4, 5]) });
+        assert!(!unsafe { cas_n_presorted(&sorted, &[0, 1, 2], &[3, 4, 5]) });
+        assert_eq!(
+            words.iter().map(Atomic::load).collect::<Vec<_>>(),
+            [3, 4, 5]
+        );
+
+        #[cfg(debug_assertions)]
+        {
+            let unsorted = [&words[1], &words[0]];
+            let result = std::panic::catch_unwind(|| unsafe {
+                cas_n_presorted(&unsorted, &[4, 3], &[0, 0])
+            });
+            assert!(result.is_err());
+        }
+    }
+
+    #[test]
+    fn test_compare_exchange_n() {
+        let words: Vec<_> = (0..3).map(Atomic::new).collect();
+        // passed in reverse order, the index refers to the caller's order
+        let addresses: Vec<_> = words.iter().rev().collect();
+        let failure = unsafe { compare_exchange_n(&addresses, &[2, 1, 5], &[0, 0, 0]) };
+        assert_eq!(
+            failure,
+            Err(CasNFailure {
+                index: 2,
+                current: 0
+            })
+        );
+        assert_eq!(
+            unsafe { compare_exchange_n(&addresses, &[2, 1, 0], &[3, 4, 5]) },
+            Ok(())
+        );
+        let failure = unsafe { compare_exchange_n(&[&words[1]], &[7], &[8]) };
+        assert_eq!(
+            failure,
+            Err(CasNFailure {
+                index: 0,
+                current: 4
+            })
+        );
+        assert_eq!(
+            words.iter().map(Atomic::load).collect::<Vec<_>>(),
+            [5, 4, 3]
+        );
+    }
+
+    #[test]
+    fn test_cas_n_bounded() {
+        let atoms: Vec<_> = (0..2).map(|_| Atomic::new(0usize)).collect();
+        let addresses: Vec<_> = atoms.iter().collect();
+        let no_helps = Options {
+            max_helps: Some(0),
+            ..Options::default()
+        };
+        let bounded = |options: Options, expected: [usize; 2], new: [usize; 2]| unsafe {
+            cas_n_bounded(&options, &addresses, &expected, &new)
+        };
+        assert_eq!(bounded(no_helps, [0, 0], [1, 1]), Ok(true));
+        assert_eq!(bounded(no_helps, [0, 0], [2, 2]), Ok(false));
+
+        // an operation of this thread sits in the first word until helped
+        let mut entries = [Entry::new(&atoms[0], 1, 2), Entry::new(&atoms[1], 1, 2)];
+        entries.sort_unstable_by_key(|e| e.addr as *const AtomicBits);
+        #[cfg(feature = "per-cpu")]
+        let _lease = crate::per_cpu::Lease::acquire();
+        let descriptor_ptr = DEFAULT_DOMAIN.casn.make_descriptor(None, &entries);
+        entries[0].addr.store(descriptor_ptr, Ordering::SeqCst);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let past = Options {
+                    deadline: Some(Instant::now()),
+                    ..Options::default()
+                };
+                let one_attempt = Options {
+                    max_attempts: Some(1),
+                    ..Options::default()
+                };
+                assert_eq!(bounded(no_helps, [1, 1], [3, 3]), Err(CasNError::Timeout));
+                assert_eq!(bounded(past, [1, 1], [3, 3]), Err(CasNError::Timeout));
+                assert_eq!(
+                    bounded(one_attempt, [1, 1], [3, 3]),
+                    Err(CasNError::Timeout)
+                );
+                // the helped operation wins
+                let one_help = Options {
+                    max_helps: Some(1),
+                    ..Options::default()
+                };
+                assert_eq!(bounded(one_help, [1, 1], [3, 3]), Ok(false));
+            });
+        });
+        assert_eq!((atoms[0].load(), atoms[1].load()), (2, 2));
+    }
+
+    #[test]
+    fn test_swap_n() {
+        let words: Vec<_> = (0..3).map(Atomic::new).collect();
+        let addrs = [&words[2], &words[0], &words[1]];
+        assert_eq!(unsafe { swap_n(addrs, [5, 3, 4]) }, [2, 0, 1]);
+        assert_eq!(unsafe { swap_n(addrs, [0, 0, 0]) }, [5, 3, 4]);
+
+        // every value swapped in is swapped out exactly once
+        let swapped_out = std::thread::scope(|s| {
+            let threads: Vec<_> = (1..=4usize)
+                .map(|t| {
+                    let words = &words;
+                    s.spawn(move || {
+                        let addrs = [&words[0], &words[1]];
+                        (0..500)
+                            .map(|i| unsafe { swap_n(addrs, [t * 1000 + i; 2]) })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            let mut all: Vec<_> = threads
+                .into_iter()
+                .flat_map(|t| t.join().unwrap())
+                .collect();
+            all.push([words[0].load(), words[1].load()]);
+            all
+        });
+        assert!(swapped_out.iter().all(|[v0, v1]| v0 == v1));
+        let mut values: Vec<_> = swapped_out.iter().map(|[v, _]| *v).collect();
+        values.sort_unstable();
+        values.dedup();
+        assert_eq!(values.len(), 4 * 500 + 1);
+    }
+
+    #[test]
+    fn test_mwcas_op() {
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(0usize);
+        let atom2 = Atomic::new(0usize);
+        let mut op = MwCasOp::new(&[&atom2, &atom0, &atom1]).unwrap();
+        for i in 0..10 {
+            assert!(unsafe {
+                op.exec(&[i, i * 2, i * 3], &[i + 1, i * 2 + 2, i * 3 + 3])
+            });
+        }
+        assert!(!unsafe { op.exec(&[0, 0, 0], &[1, 1, 1]) });
+        assert_eq!(atom2.load(), 10);
+        assert_eq!(atom0.load(), 20);
+        assert_eq!(atom1.load(), 30);
+
+        assert_eq!(
+            MwCasOp::new(&[&atom0, &atom1, &atom0]).err(),
+            Some(CasNError::DuplicateAddress)
+        );
+    }
+
+    #[test]
+    fn test_masked() {
+        const STATE_MASK: usize = 0b11;
+        const LOCKED: usize = 0b01;
+        const UNLOCKED: usize = 0b10;
+        let lock = Atomic::new(42 << 2 | LOCKED);
+        let value = Atomic::new(0usize);
+
+        let mut cas_n = CASN::new();
+        cas_n
+            .add_masked(&lock, STATE_MASK, LOCKED, 7 << 2 | UNLOCKED)
+            .unwrap();
+        cas_n.add_unchecked(&value, 0, 1);
+        assert!(unsafe { cas_n.exec() });
+        assert_eq!(lock.load(), 7 << 2 | UNLOCKED);
+        assert_eq!(value.load(), 1);
+
+        let mut cas_n = CASN::new();
+        cas_n.add_masked(&lock, STATE_MASK, LOCKED, 0).unwrap();
+        cas_n.add_unchecked(&value, 1, 2);
+        assert!(!unsafe { cas_n.exec() });
+
+        let mut cas_n = CASN::new();
+        cas_n.add_masked(&lock, STATE_MASK, UNLOCKED, 0).unwrap();
+        cas_n.add_unchecked(&value, 0, 2);
+        assert!(!unsafe { cas_n.exec() });
+        assert_eq!(lock.load(), 7 << 2 | UNLOCKED);
+        assert_eq!(value.load(), 1);
+
+        let mut cas_n = CASN::new();
+        cas_n.add_masked(&lock, !0, 7 << 2 | UNLOCKED, 0).unwrap();
+        assert!(unsafe { cas_n.exec() });
+        assert_eq!(lock.load(), 0);
+    }
+
+    #[test]
+    fn test_masked_concurrent() {
+        const STATE_MASK: usize = 0b11;
+        let lock = Atomic::new(0usize);
+        let value = Atomic::new(0usize);
+        let done = std::sync::atomic::AtomicBool::new(false);
+
+        std::thread::scope(|s| {
+            // keeps changing the bits above the state
+            s.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    let current = lock.load();
+                    unsafe { cas_n(&[&lock], &[current], &[current + (1 << 2)]) };
+                }
+            });
+            for i in 0..1000 {
+                let mut cas_n = CASN::new();
+                cas_n
+                    .add_masked(&lock, STATE_MASK, i % 4, (i + 1) % 4)
+                    .unwrap();
+                cas_n.add_unchecked(&value, i, i + 1);
+                assert!(unsafe { cas_n.exec() });
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(lock.load() & STATE_MASK, 1000 % 4);
+        assert_eq!(value.load(), 1000);
+    }
+
+    #[test]
+    fn weak_counter_test() {
+        let counter = Arc::new((Atomic::new(0usize), Atomic::new(0usize)));
+        let per_thread = 10_000;
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let counter = counter.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..per_thread {
+                        loop {
+                            let first = counter.0.load();
+                            let second = counter.1.load();
+                            if unsafe {
+                                cas2_weak(
+                                    &counter.0,
+                                    &counter.1,
+                                    first,
+                                    second,
+                                    first + 1,
+                                    second + 1,
+                                )
+                            } {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(counter.0.load(), 8 * per_thread);
+        assert_eq!(counter.1.load(), 8 * per_thread);
+
+        assert!(!unsafe { cas_n_weak(&[&counter.0, &counter.1], &[0, 0], &[1, 1]) });
+        assert_eq!(counter.0.load(), 8 * per_thread);
+    }
+
+    #[test]
+    fn test_single_entry() {
+        let atom = Atomic::new(0usize);
+        assert!(unsafe { cas_n(&[&atom], &[0], &[1]) });
+        assert!(!unsafe { cas_n(&[&atom], &[0], &[2]) });
+        assert!(unsafe { cas_n_array([&atom], [1], [2]) });
+        assert!(unsafe { cas_n_weak(&[&atom, &atom], &[2, 2], &[3, 3]) });
+        assert_eq!(atom.load(), 3);
+
+        // single word updates racing with cas2 over the same word
+        let counter = Arc::new((Atomic::new(0usize), Atomic::new(0usize)));
+        let per_thread = 10_000;
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let counter = counter.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..per_thread {
+                        loop {
+                            let first = counter.0.load();
+                            let second = counter.1.load();
+                            let succeeded = unsafe {
+                                if i % 2 == 0 {
+                                    cas_n(&[&counter.0], &[first], &[first + 1])
+                                } else {
+                                    cas2(
+                                        &counter.0,
+                                        &counter.1,
+                                        first,
+                                        second,
+                                        first + 1,
+                                        second + 1,
+                                    )
+                                }
+                            };
+                            if succeeded {
+                                break;
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(counter.0.load(), 8 * per_thread);
+        assert_eq!(counter.1.load(), 4 * per_thread);
+    }
+
+    #[test]
+    fn test_cas2_address_order() {
+        let atoms = [Atomic::new(0usize), Atomic::new(0usize)];
+        assert!(unsafe { cas2(&atoms[1], &atoms[0], 0, 0, 1, 2) });
+        assert!(unsafe { cas2(&atoms[0], &atoms[1], 2, 1, 3, 4) });
+        assert!(!unsafe { cas2(&atoms[1], &atoms[0], 4, 2, 5, 5) });
+        assert!(unsafe { cas2(&atoms[1], &atoms[1], 4, 4, 5, 5) });
+        assert_eq!(atoms[0].load(), 3);
+        assert_eq!(atoms[1].load(), 5);
+    }
+
+    #[cfg(feature = "wait-free")]
+    #[test]
+    fn test_announced_operation_is_helped() {
+        use crate::thread_local::MAX_THREADS;
+
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(0usize);
+        let mut entries = [Entry::new(&atom0, 0, 1), Entry::new(&atom1, 0, 1)];
+        entries.sort_unstable_by_key(|e| e.addr as *const AtomicBits);
+        #[cfg(feature = "per-cpu")]
+        let _lease = crate::per_cpu::Lease::acquire();
+        let descriptor_ptr = DEFAULT_DOMAIN.casn.make_descriptor(None, &entries);
+        DEFAULT_DOMAIN
+            .casn
+            .threads
+            .get_slot()
+            .1
+            .announcement
+            .store(descriptor_ptr, Ordering::SeqCst);
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let other0 = Atomic::new(0usize);
+                let other1 = Atomic::new(0usize);
+                for i in 0..MAX_THREADS {
+                    assert!(unsafe { cas2(&other0, &other1, i, i, i + 1, i + 1) });
+                }
+            });
+        });
+        assert_eq!(atom0.load(), 1);
+        assert_eq!(atom1.load(), 1);
+        assert!(DEFAULT_DOMAIN
+            .casn
+            .help(None, descriptor_ptr, false, backoff::global()));
+        DEFAULT_DOMAIN
+            .casn
+            .threads
+            .get_slot()
+            .1
+            .announcement
+            .store(Bits::from_usize(0), Ordering::SeqCst);
+    }
+
+    #[test]
+    fn test_backoff_policy() {
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(0usize);
+        let atom2 = Atomic::new(0usize);
+        let mut entries = [Entry::new(&atom0, 0, 1), Entry::new(&atom1, 0, 1)];
+        entries.sort_unstable_by_key(|e| e.addr as *const AtomicBits);
+        // an operation that stalled right after installing its first entry
+        // a descriptor made by hand needs a slot that stays leased until it's done
+        #[cfg(feature = "per-cpu")]
+        let _lease = crate::per_cpu::Lease::acquire();
+        let descriptor_ptr = DEFAULT_DOMAIN.casn.make_descriptor(None, &entries);
+        entries[0].addr.store(descriptor_ptr, Ordering::SeqCst);
+
+        let policy = HelpAfter(2, AtomicU32::new(0));
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut cas_n = CASN::new();
+                cas_n.add_unchecked(&atom0, 0, 2);
+                cas_n.add_unchecked(&atom2, 0, 2);
+                cas_n.set_backoff(&policy);
+                assert!(!unsafe { cas_n.exec() });
+            });
+        });
+        assert_eq!(policy.1.load(Ordering::Relaxed), 3);
+        assert_eq!(atom0.load(), 1);
+        assert_eq!(atom1.load(), 1);
+        assert_eq!(atom2.load(), 0);
+    }
+
+    #[test]
+    fn test_load_with_backoff() {
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(0usize);
+        let mut entries = [Entry::new(&atom0, 0, 1), Entry::new(&atom1, 0, 1)];
+        entries.sort_unstable_by_key(|e| e.addr as *const AtomicBits);
+        #[cfg(feature = "per-cpu")]
+        let _lease = crate::per_cpu::Lease::acquire();
+        let descriptor_ptr = DEFAULT_DOMAIN.casn.make_descriptor(None, &entries);
+        entries[0].addr.store(descriptor_ptr, Ordering::SeqCst);
+
+        let policy = HelpAfter(1, AtomicU32::new(0));
+        let first = if ptr::eq(entries[0].addr, atom0.as_atomic_bits()) {
+            &atom0
+        } else {
+            &atom1
+        };
+        std::thread::scope(|s| {
+            s.spawn(|| assert_eq!(first.load_with_backoff(&policy), 1));
+        });
+        assert_eq!(policy.1.load(Ordering::Relaxed), 2);
+        assert_eq!(atom0.load(), 1);
+        assert_eq!(atom1.load(), 1);
+    }
+
+    #[test]
+    fn test_peek() {
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(0usize);
+        assert_eq!(atom0.peek(), 0);
+
+        let mut entries = [Entry::new(&atom0, 0, 1), Entry::new(&atom1, 0, 1)];
+        entries.sort_unstable_by_key(|e| e.addr as *const AtomicBits);
+        #[cfg(feature = "per-cpu")]
+        let _lease = crate::per_cpu::Lease::acquire();
+        let descriptor_ptr = DEFAULT_DOMAIN.casn.make_descriptor(None, &entries);
+        atom0
+            .as_atomic_bits()
+            .store(descriptor_ptr, Ordering::SeqCst);
+        atom1
+            .as_atomic_bits()
+            .store(descriptor_ptr, Ordering::SeqCst);
+        assert_eq!(atom0.peek(), 0);
+        assert_eq!(atom1.peek(), 0);
+        assert_eq!(
+            atom0.as_atomic_bits().load(Ordering::SeqCst),
+            descriptor_ptr
+        );
+
+        // decide the operation without running phase 2
+        let status = match DEFAULT_DOMAIN.casn.try_snapshot(descriptor_ptr) {
+            Ok(snapshot) => snapshot.status,
+            Err(()) => unreachable!(),
+        };
+        let undecided = status.load(Ordering::SeqCst);
+        let succeeded = CasNDescriptorStatus::succeeded(undecided.seq_number());
+        assert!(status.compare_exchange(undecided, succeeded).is_ok());
+        assert_eq!(atom0.peek(), 1);
+        assert_eq!(atom1.peek(), 1);
+        assert_eq!(
+            atom1.as_atomic_bits().load(Ordering::SeqCst),
+            descriptor_ptr
+        );
+
+        assert!(DEFAULT_DOMAIN
+            .casn
+            .help(None, descriptor_ptr, false, backoff::global()));
+        assert_eq!(atom0.as_atomic_bits().load(Ordering::SeqCst).mark(), 0);
+        assert_eq!(atom1.peek(), 1);
+    }
+
+    #[test]
+    fn test_help_all() {
+        let atoms: Vec<_> = (0..3).map(|_| Atomic::new(0usize)).collect();
+        let mut entries = [Entry::new(&atoms[0], 0, 1), Entry::new(&atoms[1], 0, 1)];
+        entries.sort_unstable_by_key(|e| e.addr as *const AtomicBits);
+        #[cfg(feature = "per-cpu")]
+        let _lease = crate::per_cpu::Lease::acquire();
+        let descriptor_ptr = DEFAULT_DOMAIN.casn.make_descriptor(None, &entries);
+        entries[0].addr.store(descriptor_ptr, Ordering::SeqCst);
+
+        std::thread::scope(|s| {
+            s.spawn(|| help_all(&atoms));
+        });
+        assert!(atoms.iter().all(|atom| !atom.is_contended()));
+        assert_eq!(atoms[0].load(), 1);
+        assert_eq!(atoms[1].load(), 1);
+        assert_eq!(atoms[2].load(), 0);
+    }
+
+    #[test]
+    fn test_settle_and_quiesce() {
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(0usize);
+        let mut entries = [Entry::new(&atom0, 0, 1), Entry::new(&atom1, 0, 1)];
+        entries.sort_unstable_by_key(|e| e.addr as *const AtomicBits);
+        #[cfg(feature = "per-cpu")]
+        let lease = crate::per_cpu::Lease::acquire();
+        let descriptor_ptr = DEFAULT_DOMAIN.casn.make_descriptor(None, &entries);
+        entries[0].addr.store(descriptor_ptr, Ordering::SeqCst);
+
+        atom0.settle();
+        atom1.settle();
+        assert!(!atom0.is_contended() && !atom1.is_contended());
+
+        #[cfg(feature = "per-cpu")]
+        drop(lease);
+        quiesce();
+        #[cfg(not(feature = "heap-descriptors"))]
+        {
+            assert!(DEFAULT_DOMAIN.casn.try_snapshot(descriptor_ptr).is_err());
+            assert!(!DEFAULT_DOMAIN.casn.help(
+                None,
+                descriptor_ptr,
+                true,
+                backoff::global()
+            ));
         }
     }
+
+    #[test]
+    #[should_panic]
+    fn test_duplicate_address_exec_panics() {
+        let atom = Atomic::new(0usize);
+        let _ = unsafe { cas2(&atom, &atom, 0, 0, 1, 2) };
+    }
+
+    #[test]
+    fn test_cas_n_with() {
+        let words: Vec<_> = (0..3).map(|_| Atomic::new(0usize)).collect();
+        let addresses: Vec<_> = words.iter().collect();
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    let thread = crate::register_current_thread();
+                    for _ in 0..1000 {
+                        loop {
+                            let exp: Vec<_> = words.iter().map(|w| w.load()).collect();
+                            let new: Vec<_> = exp.iter().map(|v| v + 1).collect();
+                            if unsafe { cas_n_with(&thread, &addresses, &exp, &new) } {
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert!(words.iter().all(|w| w.load() == 2000));
+    }
+
+    // small enough to run under Miri
+    #[test]
+    #[cfg(not(feature = "heap-descriptors"))]
+    fn test_thread_descriptor() {
+        let descriptor = ThreadCasNDescriptor::new();
+        assert_eq!(descriptor.num_entries.load(Ordering::Relaxed), 0);
+        let words = [Atomic::new(0usize), Atomic::new(0usize)];
+        let entries = [Entry::new(&words[0], 0, 1), Entry::new(&words[1], 0, 2)];
+        descriptor.inc_seq(Ordering::Relaxed);
+        descriptor.store_entries(&entries);
+        let seq = descriptor.inc_seq(Ordering::Release);
+        let snapshot = descriptor.try_snapshot(seq).unwrap();
+        assert_eq!(snapshot.entries.len(), 2);
+        assert!(snapshot
+            .entries
+            .iter()
+            .zip(&entries)
+            .all(|(read, written)| {
+                ptr::eq(read.addr, written.addr) && read.new == written.new
+            }));
+        descriptor.inc_seq(Ordering::Relaxed);
+        assert!(descriptor.try_snapshot(seq).is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "heap-descriptors"))]
+    fn test_seq_number_wraps() {
+        let descriptor = ThreadCasNDescriptor::new();
+        let last = SeqNumber::from_usize(SeqNumber::MASK - 1);
+        descriptor
+            .status
+            .store(CasNDescriptorStatus::undecided(last), Ordering::Relaxed);
+        let words = [Atomic::new(0usize), Atomic::new(0usize)];
+        let entries = [Entry::new(&words[0], 0, 1), Entry::new(&words[1], 0, 2)];
+        descriptor.inc_seq(Ordering::Relaxed);
+        descriptor.store_entries(&entries);
+        let seq = descriptor.inc_seq(Ordering::Release);
+        assert_eq!(seq.as_usize(), 0);
+        assert!(descriptor.try_snapshot(seq).is_ok());
+
+        // the thread id above the sequence number is left alone
+        let tid = ThreadId::from_u16(3);
+        let ptr = Bits::new_descriptor_ptr(tid, SeqNumber::from_usize(SeqNumber::MASK));
+        assert_eq!(ptr.tid(), tid);
+        let ptr =
+            Bits::new_descriptor_ptr(tid, SeqNumber::from_usize(SeqNumber::MASK).inc());
+        assert_eq!((ptr.tid(), ptr.seq()), (tid, seq));
+    }
+
+    // short lived threads keep taking over the ids of the ones that just
+    // exited while their descriptors may still be helped by the others
+    #[test]
+    fn test_thread_churn() {
+        let first = Atomic::new(0usize);
+        let second = Atomic::new(0usize);
+        let increment = || loop {
+            let (v0, v1) = (first.load(), second.load());
+            if unsafe { cas2(&first, &second, v0, v1, v0 + 1, v1 + 1) } {
+                break;
+            }
+        };
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        increment();
+                    }
+                });
+            }
+            for _ in 0..200 {
+                std::thread::scope(|s| {
+                    for _ in 0..2 {
+                        s.spawn(|| {
+                            for _ in 0..5 {
+                                increment();
+                            }
+                        });
+                    }
+                });
+            }
+        });
+        assert_eq!(first.load(), 4000);
+        assert_eq!(second.load(), 4000);
+    }
 }
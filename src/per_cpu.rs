@@ -0,0 +1,181 @@
+// Descriptor slots leased per operation, enabled by the experimental
+// `per-cpu` feature.
+//
+// Instead of owning the slot of its thread id for its whole life, a thread
+// leases a slot when it starts an operation and gives it back when it's done,
+// so the descriptor tables only need slots for the operations that can be in
+// flight at once: `SLOTS_PER_CPU` per core. The search for a free slot starts
+// at the ones of the core the thread runs on, which keeps a slot on the same
+// core most of the time. A thread that finds all slots leased after
+// `FALLBACK_ROUNDS` passes over them uses the slot of its thread id instead,
+// `MAX_THREADS` plus the id, so operations stay lock-free when more of them
+// than there are slots get preempted in the middle. Only a thread that can't
+// get an id keeps waiting for a leased slot.
+//
+// Slots are reused by different threads like thread ids are, their sequence
+// numbers keep counting up across leases.
+
+use crate::thread_local::{ThreadId, MAX_THREADS};
+use once_cell::sync::Lazy;
+use std::{
+    cell::Cell,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+const SLOTS_PER_CPU: usize = 16;
+// passes over all leased slots before falling back to the slot of the id
+const FALLBACK_ROUNDS: usize = 2;
+
+static LEASED: Lazy<Vec<AtomicBool>> =
+    Lazy::new(|| (0..num_slots()).map(|_| AtomicBool::new(false)).collect());
+
+thread_local! {
+    // leased slot and number of nested leases of the current operation
+    static LEASE: Cell<(u16, usize)> = const { Cell::new((0, 0)) };
+}
+
+pub(crate) fn num_slots() -> usize {
+    static NUM_SLOTS: Lazy<usize> = Lazy::new(|| {
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        (cpus * SLOTS_PER_CPU).min(MAX_THREADS)
+    });
+    *NUM_SLOTS
+}
+
+// the slot only the thread with `id` uses, past all leased ones
+pub(crate) fn fallback_slot(id: ThreadId) -> usize {
+    MAX_THREADS + id.as_u16() as usize
+}
+
+// the slot of the operation the calling thread is running
+pub(crate) fn current() -> ThreadId {
+    let (slot, depth) = LEASE.with(Cell::get);
+    assert!(depth > 0, "descriptor slot used outside of an operation");
+    ThreadId::from_u16(slot)
+}
+
+// Held for the whole of an operation. Operations nested into it, like the
+// helping of a load, share its slot.
+pub(crate) struct Lease(());
+
+impl Lease {
+    pub fn acquire() -> Self {
+        let (slot, depth) = LEASE.with(Cell::get);
+        let slot = if depth > 0 {
+            slot
+        } else {
+            let slots = &*LEASED;
+            let first = cpu() * SLOTS_PER_CPU % slots.len();
+            let mut attempt = 0;
+            loop {
+                let index = (first + attempt) % slots.len();
+                // acquire pairs with the release in `drop`, like a thread id
+                // the slot comes with the descriptors of its previous user
+                if !slots[index].load(Ordering::Relaxed)
+                    && slots[index]
+                        .compare_exchange(
+                            false,
+                            true,
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        )
+                        .is_ok()
+                {
+                    break index as u16;
+                }
+                attempt += 1;
+                if attempt % slots.len() == 0 {
+                    if attempt / slots.len() >= FALLBACK_ROUNDS {
+                        if let Ok(id) = ThreadId::try_register() {
+                            break fallback_slot(id) as u16;
+                        }
+                    }
+                    thread::yield_now();
+                }
+            }
+        };
+        LEASE.with(|lease| lease.set((slot, depth + 1)));
+        Lease(())
+    }
+
+    // leases `slot` itself, waiting for its current user to give it back,
+    // or takes the fallback slot of the calling thread
+    pub fn acquire_slot(slot: usize) -> Self {
+        let (_, depth) = LEASE.with(Cell::get);
+        assert_eq!(
+            depth, 0,
+            "a specific slot can't be leased inside of an operation"
+        );
+        while slot < LEASED.len()
+            && LEASED[slot]
+                .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+        {
+            thread::yield_now();
+        }
+        LEASE.with(|lease| lease.set((slot as u16, 1)));
+        Lease(())
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let (slot, depth) = LEASE.with(Cell::get);
+        LEASE.with(|lease| lease.set((slot, depth - 1)));
+        // fallback slots aren't leased, they stay with the id
+        if depth == 1 && (slot as usize) < LEASED.len() {
+            LEASED[slot as usize].store(false, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn cpu() -> usize {
+    let cpu = unsafe { libc::sched_getcpu() };
+    if cpu < 0 {
+        0
+    } else {
+        cpu as usize
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpu() -> usize {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cas2, Atomic};
+    use std::sync::Barrier;
+
+    #[test]
+    fn test_fallback_slot() {
+        // other threads hold every leased slot
+        let (leased, done) =
+            (Barrier::new(num_slots() + 1), Barrier::new(num_slots() + 1));
+        thread::scope(|s| {
+            for slot in 0..num_slots() {
+                let (leased, done) = (&leased, &done);
+                s.spawn(move || {
+                    let _lease = Lease::acquire_slot(slot);
+                    leased.wait();
+                    done.wait();
+                });
+            }
+            leased.wait();
+            {
+                let _lease = Lease::acquire();
+                assert_eq!(
+                    current().as_u16() as usize,
+                    fallback_slot(ThreadId::current())
+                );
+            }
+            let (a, b) = (Atomic::new(0usize), Atomic::new(0usize));
+            assert!(unsafe { cas2(&a, &b, 0, 0, 1, 1) });
+            done.wait();
+        });
+    }
+}
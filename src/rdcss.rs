@@ -1,153 +1,295 @@
+#[cfg(feature = "heap-descriptors")]
+use crate::heap;
+#[cfg(feature = "stats")]
+use crate::stats::{self, Event};
+#[cfg(feature = "test-internals")]
+use crate::test_hooks::{self, Point};
+#[cfg(not(feature = "heap-descriptors"))]
 use crate::{
-    atomic::{AtomicAddress, AtomicBits, Bits},
-    mwcas::{AtomicCasNDescriptorStatus, CasNDescriptorStatus},
-    sequence_number::SeqNumberGenerator,
-    thread_local::ThreadLocal,
+    atomic::AtomicAddress,
+    sequence_number::{self, SeqNumberGenerator, FIELD_LOAD, FIELD_STORE},
 };
-use crossbeam_utils::Backoff;
-use once_cell::sync::Lazy;
-use std::sync::atomic::{fence, Ordering};
+use crate::{
+    atomic::{Atomic, AtomicBits, Bits, Word},
+    backoff,
+    domain::DEFAULT_DOMAIN,
+    mwcas::{CasNDescriptor, ThreadDescriptors},
+    thread_local::{ThreadId, ThreadTable},
+    watchdog::{describe, Watchdog},
+};
+#[cfg(loom)]
+use loom::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "heap-descriptors")]
+use std::marker::PhantomData;
+#[cfg(not(loom))]
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-pub(crate) static RDCSS_DESCRIPTOR: Lazy<RDCSSDescriptor> =
-    Lazy::new(RDCSSDescriptor::new);
+/// Restricted double-compare single-swap: replaces the value of `data` with
+/// `new_data` if `data` holds `exp_data` and `control` holds `exp_control`,
+/// and returns the value `data` held before.
+///
+/// `control` is compared by its raw word, so while a `cas_n` is in the middle of
+/// updating `control` it compares unequal.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn rdcss<T: Word>(
+    control: &Atomic<usize>,
+    data: &Atomic<T>,
+    exp_control: usize,
+    exp_data: T,
+    new_data: T,
+) -> T {
+    #[cfg(feature = "heap-descriptors")]
+    let _guard = crossbeam_epoch::pin();
+    #[cfg(feature = "per-cpu")]
+    let _lease = crate::per_cpu::Lease::acquire();
+    let exp_control: Bits = exp_control.into();
+    loop {
+        let current = DEFAULT_DOMAIN.casn.rdcss().rdcss(
+            None,
+            control.as_atomic_bits().as_raw(),
+            data.as_atomic_bits(),
+            exp_control.into_usize(),
+            exp_data.into(),
+            new_data.into(),
+            false,
+        );
+        if current.mark() == CasNDescriptor::MARK {
+            DEFAULT_DOMAIN
+                .casn
+                .help(None, current, true, backoff::global());
+        } else {
+            return current.into();
+        }
+    }
+}
 
-struct ThreadRDCSSDescriptor {
-    status_address: AtomicAddress<AtomicCasNDescriptorStatus>,
+#[cfg(not(feature = "heap-descriptors"))]
+pub(crate) struct ThreadRDCSSDescriptor {
+    control_address: AtomicAddress<AtomicUsize>,
     data_address: AtomicAddress<AtomicBits>,
-    expected_status_cell: AtomicCasNDescriptorStatus,
+    expected_control_cell: AtomicUsize,
     expected_ptr_cell: AtomicBits,
-    kcas_ptr_cell: AtomicBits,
+    new_data_cell: AtomicBits,
     seq_number: SeqNumberGenerator,
 }
 
+#[cfg(not(feature = "heap-descriptors"))]
 impl ThreadRDCSSDescriptor {
     fn new() -> Self {
         Self {
-            status_address: AtomicAddress::empty(),
+            control_address: AtomicAddress::empty(),
             data_address: AtomicAddress::empty(),
-            expected_status_cell: AtomicCasNDescriptorStatus::new(),
+            expected_control_cell: AtomicUsize::new(0),
             expected_ptr_cell: AtomicBits::empty(),
-            kcas_ptr_cell: AtomicBits::empty(),
+            new_data_cell: AtomicBits::empty(),
             seq_number: SeqNumberGenerator::new(),
         }
     }
 
-    fn snapshot(&self) -> ThreadRDCSSDescriptorSnapshot {
+    fn snapshot(&self) -> ThreadRDCSSDescriptorSnapshot<'_> {
         unsafe {
-            let status_location: &AtomicCasNDescriptorStatus =
-                self.status_address.load(Ordering::Relaxed);
-            let data_location: &AtomicBits = self.data_address.load(Ordering::Relaxed);
-            let expected_status: CasNDescriptorStatus =
-                self.expected_status_cell.load(Ordering::Relaxed);
-            let expected_data_ptr = self.expected_ptr_cell.load(Ordering::Relaxed);
-            let kcas_ptr = self.kcas_ptr_cell.load(Ordering::Relaxed);
+            let control_location: &AtomicUsize = self.control_address.load(FIELD_LOAD);
+            let data_location: &AtomicBits = self.data_address.load(FIELD_LOAD);
+            let expected_control = self.expected_control_cell.load(FIELD_LOAD);
+            let expected_data_ptr = self.expected_ptr_cell.load(FIELD_LOAD);
+            let new_data = self.new_data_cell.load(FIELD_LOAD);
             ThreadRDCSSDescriptorSnapshot {
-                status_location,
+                control_location,
                 data_location,
-                expected_status,
+                expected_control,
                 expected_data_ptr,
-                kcas_ptr,
+                new_data,
             }
         }
     }
 }
 
+#[cfg(not(feature = "heap-descriptors"))]
 impl Default for ThreadRDCSSDescriptor {
     fn default() -> Self {
         Self::new()
     }
 }
 
+#[derive(Clone, Copy)]
 struct ThreadRDCSSDescriptorSnapshot<'g> {
-    status_location: &'static AtomicCasNDescriptorStatus,
+    control_location: &'g AtomicUsize,
     data_location: &'g AtomicBits,
-    expected_status: CasNDescriptorStatus,
+    expected_control: usize,
     expected_data_ptr: Bits,
-    kcas_ptr: Bits,
+    new_data: Bits,
+}
+
+#[cfg(feature = "heap-descriptors")]
+type HeapRDCSSDescriptor = ThreadRDCSSDescriptorSnapshot<'static>;
+
+// every return from `rdcss` leaves the descriptor uninstalled
+#[cfg(feature = "heap-descriptors")]
+struct RetireOnDrop(Bits);
+
+#[cfg(feature = "heap-descriptors")]
+impl Drop for RetireOnDrop {
+    fn drop(&mut self) {
+        let guard = crossbeam_epoch::pin();
+        unsafe { heap::retire::<HeapRDCSSDescriptor>(&guard, self.0) };
+    }
 }
 
-pub struct RDCSSDescriptor {
-    per_thread_descriptors: ThreadLocal<ThreadRDCSSDescriptor>,
+// The RDCSS half of the per thread records of a `CasNDescriptor`, which owns
+// the table.
+#[derive(Clone, Copy)]
+pub struct RDCSSDescriptor<'a> {
+    #[cfg(not(feature = "heap-descriptors"))]
+    threads: &'a ThreadTable<ThreadDescriptors>,
+    // heap descriptors aren't kept in the table
+    #[cfg(feature = "heap-descriptors")]
+    threads: PhantomData<&'a ThreadTable<ThreadDescriptors>>,
 }
 
-impl RDCSSDescriptor {
+impl<'a> RDCSSDescriptor<'a> {
     pub const MARK: usize = 1;
 
-    fn new() -> Self {
+    #[cfg(not(feature = "heap-descriptors"))]
+    pub(crate) fn new(threads: &'a ThreadTable<ThreadDescriptors>) -> Self {
+        Self { threads }
+    }
+
+    #[cfg(feature = "heap-descriptors")]
+    pub(crate) fn new(_threads: &'a ThreadTable<ThreadDescriptors>) -> Self {
         Self {
-            per_thread_descriptors: ThreadLocal::new(),
+            threads: PhantomData,
         }
     }
 
+    // makes every pointer to the descriptor of the calling thread stale,
+    // `owner` is its id in the table, see `CasNDescriptor::owner`
+    #[cfg(not(feature = "heap-descriptors"))]
+    pub(crate) fn invalidate(&self, owner: Option<ThreadId>) {
+        let (_, thread) = self.threads.get_slot_of(owner);
+        thread.rdcss.seq_number.inc(Ordering::SeqCst);
+    }
+
+    #[cfg(feature = "heap-descriptors")]
+    pub(crate) fn invalidate(&self, _owner: Option<ThreadId>) {}
+
+    #[cfg(not(feature = "heap-descriptors"))]
     fn make_descriptor(
-        &'static self,
-        status_ref: &'static AtomicCasNDescriptorStatus,
+        &self,
+        owner: Option<ThreadId>,
+        control_ref: &AtomicUsize,
         data_ref: &AtomicBits,
-        expected_status: CasNDescriptorStatus,
+        expected_control: usize,
         expected_data: Bits,
-        new_kcas_ptr: Bits,
+        new_data: Bits,
     ) -> Bits {
-        let (thread_id, per_thread_descriptor) = self.per_thread_descriptors.get();
+        let (thread_id, thread) = self.threads.get_slot_of(owner);
+        let per_thread_descriptor = &thread.rdcss;
 
         per_thread_descriptor.seq_number.inc(Ordering::Relaxed);
-        fence(Ordering::Release);
+        sequence_number::publish_fence();
 
         per_thread_descriptor
-            .status_address
-            .store(status_ref, Ordering::Relaxed);
+            .control_address
+            .store(control_ref, FIELD_STORE);
         per_thread_descriptor
             .data_address
-            .store(data_ref, Ordering::Relaxed);
+            .store(data_ref, FIELD_STORE);
 
         per_thread_descriptor
-            .expected_status_cell
-            .store(expected_status, Ordering::Relaxed);
+            .expected_control_cell
+            .store(expected_control, FIELD_STORE);
         per_thread_descriptor
             .expected_ptr_cell
-            .store(expected_data, Ordering::Relaxed);
+            .store(expected_data, FIELD_STORE);
         per_thread_descriptor
-            .kcas_ptr_cell
-            .store(new_kcas_ptr, Ordering::Relaxed);
+            .new_data_cell
+            .store(new_data, FIELD_STORE);
 
         let new_seq = per_thread_descriptor.seq_number.inc(Ordering::Release);
         Bits::new_descriptor_ptr(thread_id, new_seq).with_mark(Self::MARK)
     }
 
+    #[cfg(feature = "heap-descriptors")]
+    fn make_descriptor(
+        &self,
+        _owner: Option<ThreadId>,
+        control_ref: &AtomicUsize,
+        data_ref: &AtomicBits,
+        expected_control: usize,
+        expected_data: Bits,
+        new_data: Bits,
+    ) -> Bits {
+        // the locations outlive the descriptor, it's retired once `rdcss` returns
+        let descriptor: HeapRDCSSDescriptor = unsafe {
+            ThreadRDCSSDescriptorSnapshot {
+                control_location: &*(control_ref as *const AtomicUsize),
+                data_location: &*(data_ref as *const AtomicBits),
+                expected_control,
+                expected_data_ptr: expected_data,
+                new_data,
+            }
+        };
+        heap::into_ptr(Box::new(descriptor), Self::MARK)
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn rdcss(
-        &'static self,
-        status_location: &'static AtomicCasNDescriptorStatus,
+        &self,
+        owner: Option<ThreadId>,
+        control_location: &AtomicUsize,
         data_location: &AtomicBits,
-        expected_status: CasNDescriptorStatus,
+        expected_control: usize,
         expected_data_ptr: Bits,
-        new_kcas_ptr: Bits,
+        new_data: Bits,
+        weak: bool,
     ) -> Bits {
         let des_ptr = self.make_descriptor(
-            status_location,
+            owner,
+            control_location,
             data_location,
-            expected_status,
+            expected_control,
             expected_data_ptr,
-            new_kcas_ptr,
+            new_data,
         );
-        let backoff = Backoff::new();
+        #[cfg(feature = "heap-descriptors")]
+        let _retire = RetireOnDrop(des_ptr);
+        let backoff = backoff::global();
+        let mut step = 0;
+        let mut watchdog = Watchdog::new();
         loop {
             let current = data_location.load(Ordering::SeqCst);
             if is_marked(current) {
-                if backoff.is_completed() {
-                    self.rdcss_help(des_ptr);
+                if weak {
+                    return current;
+                }
+                watchdog.tick(|| {
+                    format!("rdcss on {:p} against {}", data_location, describe(current))
+                });
+                if backoff.wait(step) {
+                    step += 1;
                 } else {
-                    backoff.spin();
+                    self.rdcss_help(current);
                 }
+                #[cfg(feature = "stats")]
+                stats::record(Event::RdcssRetry);
                 continue;
             }
             if current != expected_data_ptr {
                 return current;
             }
-            let installed = data_location.compare_exchange(expected_data_ptr, des_ptr);
-            if installed.is_ok() {
-                self.rdcss_help(des_ptr);
-                return expected_data_ptr;
-            } else {
-                backoff.reset();
+            match data_location.compare_exchange(expected_data_ptr, des_ptr) {
+                Ok(_) => {
+                    #[cfg(feature = "test-internals")]
+                    test_hooks::reached(Point::RdcssInstalled);
+                    self.rdcss_help(des_ptr);
+                    return expected_data_ptr;
+                },
+                Err(current) if weak => return current,
+                Err(_) => {
+                    step = 0;
+                    #[cfg(feature = "stats")]
+                    stats::record(Event::RdcssRetry);
+                },
             }
         }
     }
@@ -155,11 +297,11 @@ impl RDCSSDescriptor {
     fn rdcss_help(&self, des: Bits) {
         let snapshot = self.try_snapshot(des);
         if let Ok(snapshot) = snapshot {
-            let curr_status = snapshot.status_location.load(Ordering::SeqCst);
-            if curr_status == snapshot.expected_status {
+            let curr_control = snapshot.control_location.load(Ordering::SeqCst);
+            if curr_control == snapshot.expected_control {
                 let _ = snapshot
                     .data_location
-                    .compare_exchange(des, snapshot.kcas_ptr);
+                    .compare_exchange(des, snapshot.new_data);
             } else {
                 let _ = snapshot
                     .data_location
@@ -168,16 +310,23 @@ impl RDCSSDescriptor {
         }
     }
 
-    fn try_snapshot(&self, des: Bits) -> Result<ThreadRDCSSDescriptorSnapshot, ()> {
+    // the caller is pinned, so the descriptor can't be freed under it
+    #[cfg(feature = "heap-descriptors")]
+    fn try_snapshot(&self, des: Bits) -> Result<ThreadRDCSSDescriptorSnapshot<'a>, ()> {
+        Ok(*unsafe { heap::deref::<HeapRDCSSDescriptor>(des) })
+    }
+
+    #[cfg(not(feature = "heap-descriptors"))]
+    fn try_snapshot(&self, des: Bits) -> Result<ThreadRDCSSDescriptorSnapshot<'a>, ()> {
         let tid = des.tid();
         let seq = des.seq();
-        let curr_thread_descriptor = self.per_thread_descriptors.get_for_thread(tid);
+        let curr_thread_descriptor = &self.threads.get_for_thread(tid).rdcss;
         if seq != curr_thread_descriptor.seq_number.current(Ordering::Acquire) {
             Err(())
         } else {
             let fields = curr_thread_descriptor.snapshot();
 
-            fence(Ordering::Acquire);
+            sequence_number::validate_fence();
             if seq != curr_thread_descriptor.seq_number.current(Ordering::Relaxed) {
                 Err(())
             } else {
@@ -204,34 +353,26 @@ pub fn is_marked(ptr: Bits) -> bool {
 
 #[cfg(test)]
 mod tests {
-    /*
     use super::*;
-    use crate::rdcss::RDCSSDescriptor;
-    use crossbeam_epoch::pin;
 
     #[test]
-    fn test_descriptor() {
-        let g = pin();
-        let atom = AtomicUsize::new(1000);
-        let atom_exp = 1000;
-        let rdcss_atom = AtomicUsize::new(10);
-        let rdcss_exp = 10;
-        let des = RDCSSDescriptor::new();
-        let rdcss_new = 2000;
-        let ptr = des.make_descriptor(&atom, &rdcss_atom, atom_exp, rdcss_exp, rdcss_new);
-        assert!(is_marked(ptr));
-        let swapped = des.rdcss(&atom, &rdcss_atom, atom_exp, rdcss_exp, rdcss_new);
-        assert_eq!(swapped, rdcss_exp);
-
-        atom.store(10001, Ordering::SeqCst);
-        let swapped = des.rdcss(&atom, &rdcss_atom, atom_exp, rdcss_exp, rdcss_new);
-        assert_ne!(swapped, rdcss_exp);
-
-        atom.store(1000, Ordering::SeqCst);
-        rdcss_atom.store(11, Ordering::SeqCst);
-        let swapped = des.rdcss(&atom, &rdcss_atom, atom_exp, rdcss_exp, rdcss_new);
-        assert_ne!(swapped, rdcss_exp);
-        assert_ne!(11, rdcss_exp);
-    }
-    */
+    fn test_rdcss() {
+        let control = Atomic::new(1000usize);
+        let data = Atomic::new(10usize);
+
+        let swapped = unsafe { rdcss(&control, &data, 1000, 10, 2000) };
+        assert_eq!(swapped, 10);
+        assert_eq!(data.load(), 2000);
+
+        // data doesn't match
+        let swapped = unsafe { rdcss(&control, &data, 1000, 10, 3000) };
+        assert_eq!(swapped, 2000);
+        assert_eq!(data.load(), 2000);
+
+        // control doesn't match
+        let swapped = unsafe { rdcss(&control, &data, 1001, 2000, 3000) };
+        assert_eq!(swapped, 2000);
+        assert_eq!(data.load(), 2000);
+        assert_eq!(control.load(), 1000);
+    }
 }
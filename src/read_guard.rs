@@ -0,0 +1,94 @@
+use crate::{
+    atomic::{Atomic, Bits, Word},
+    mwcas::MAX_ENTRIES,
+};
+use arrayvec::ArrayVec;
+
+/// An optimistic read of up to 4 `Atomic`s, like the read side of a seqlock.
+///
+/// The guard remembers the values the cells held when it was taken. A reader
+/// works with them, then calls `validate` to learn whether the cells still
+/// hold all of them. Validation only reads: it loads every cell again,
+/// helping operations it finds in them, and compares. With the loads that
+/// took the guard that's a double collect, cells that didn't change between
+/// the two passes all held their values at the moment the first pass ended.
+/// The comparison is by value: cells that changed and changed back in the
+/// meantime pass, unlike links to a `Versioned` word.
+pub struct ReadGuard<'a, T: Word> {
+    cells: ArrayVec<[&'a Atomic<T>; MAX_ENTRIES]>,
+    values: ArrayVec<[T; MAX_ENTRIES]>,
+}
+
+impl<'a, T: Word> ReadGuard<'a, T> {
+    pub fn new(cells: &[&'a Atomic<T>]) -> Self {
+        assert!(
+            cells.len() <= MAX_ENTRIES,
+            "too many cells for a single cas_n"
+        );
+        let cells: ArrayVec<[&Atomic<T>; MAX_ENTRIES]> = cells.iter().copied().collect();
+        let values = cells.iter().map(|cell| cell.load()).collect();
+        Self { cells, values }
+    }
+
+    /// The values read, in the order of the cells.
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    /// Whether every cell still holds the value it was read with.
+    pub fn validate(&self) -> bool {
+        self.cells.iter().zip(&self.values).all(|(cell, value)| {
+            let current: Bits = cell.load().into();
+            current == (*value).into()
+        })
+    }
+
+    /// Reads the cells again, for a retry after a failed validation.
+    pub fn refresh(&mut self) {
+        for (cell, value) in self.cells.iter().zip(&mut self.values) {
+            *value = cell.load();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cas2;
+    use std::thread;
+
+    #[test]
+    fn test_read_guard() {
+        let (a, b) = (Atomic::new(1usize), Atomic::new(2usize));
+        let mut guard = ReadGuard::new(&[&a, &b]);
+        assert_eq!(guard.values(), [1, 2]);
+        assert!(guard.validate());
+        assert!(unsafe { cas2(&a, &b, 1, 2, 3, 4) });
+        assert!(!guard.validate());
+        guard.refresh();
+        assert_eq!(guard.values(), [3, 4]);
+        assert!(guard.validate());
+    }
+
+    #[test]
+    fn test_consistent_reads() {
+        // writers keep both cells equal, validated reads never see them differ
+        let (a, b) = (Atomic::new(0usize), Atomic::new(0usize));
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..1000 {
+                    assert!(unsafe { cas2(&a, &b, i, i, i + 1, i + 1) });
+                }
+            });
+            s.spawn(|| {
+                for _ in 0..1000 {
+                    let mut guard = ReadGuard::new(&[&a, &b]);
+                    while !guard.validate() {
+                        guard.refresh();
+                    }
+                    assert_eq!(guard.values()[0], guard.values()[1]);
+                }
+            });
+        });
+    }
+}
@@ -0,0 +1,381 @@
+//! Pluggable reclamation of values that were swapped out of pointer words.
+
+use crate::{cas_n, mwcas::MAX_ENTRIES, thread_local::ThreadTable, Atomic};
+use arrayvec::ArrayVec;
+use std::{
+    marker::PhantomData,
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// Keeps values alive while some thread may still be reading them.
+///
+/// Readers `pin` a guard and load pointer words through `protect`, the value
+/// behind a returned pointer stays valid as long as the guard lives. Values
+/// swapped out of a word are handed to `retire` instead of being freed.
+pub trait Reclaimer: Sync {
+    type Guard<'a>
+    where
+        Self: 'a;
+
+    fn pin(&self) -> Self::Guard<'_>;
+
+    fn protect<T: 'static>(
+        guard: &mut Self::Guard<'_>,
+        atomic: &Atomic<*const T>,
+    ) -> *const T;
+
+    /// Frees the value behind `ptr` once no guard protects it anymore.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` has to come from `Box::into_raw`, must not be reachable from any
+    /// word anymore and must be retired only once.
+    unsafe fn retire<T: Send + 'static>(guard: &Self::Guard<'_>, ptr: *const T);
+}
+
+/// Replaces the values behind up to 4 pointer words at once, read-copy-update
+/// style: the values are cloned, `update` changes the copies in the order of
+/// `atomics`, and one `cas_n` swings every word to its copy. The old values
+/// are retired through `reclaimer`. If another thread replaced any of them in
+/// the meantime the copies are dropped and `update` runs again on fresh ones.
+///
+/// # Safety
+///
+/// The words must be distinct, hold pointers from `Box::into_raw` and only be
+/// replaced by operations that retire the old values through `reclaimer`.
+pub unsafe fn replace_many<R, T, F>(
+    reclaimer: &R,
+    atomics: &[&Atomic<*const T>],
+    mut update: F,
+) where
+    R: Reclaimer,
+    T: Clone + Send + 'static,
+    F: FnMut(&mut [T]),
+{
+    assert!(
+        atomics.len() <= MAX_ENTRIES,
+        "too many words for a single cas_n"
+    );
+    loop {
+        let mut guard = reclaimer.pin();
+        let old: ArrayVec<[*const T; MAX_ENTRIES]> = atomics
+            .iter()
+            .map(|atomic| R::protect(&mut guard, atomic))
+            .collect();
+        let mut copies: ArrayVec<[T; MAX_ENTRIES]> =
+            old.iter().map(|value| (**value).clone()).collect();
+        update(&mut copies);
+        let new: ArrayVec<[*const T; MAX_ENTRIES]> = copies
+            .into_iter()
+            .map(|copy| Box::into_raw(Box::new(copy)) as *const T)
+            .collect();
+        if cas_n(atomics, &old, &new) {
+            for value in old {
+                R::retire(&guard, value);
+            }
+            return;
+        }
+        for copy in new {
+            drop(Box::from_raw(copy as *mut T));
+        }
+    }
+}
+
+/// `Reclaimer` on top of the global `crossbeam_epoch` collector.
+#[cfg(feature = "epoch")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Epoch;
+
+#[cfg(feature = "epoch")]
+impl Reclaimer for Epoch {
+    type Guard<'a> = crossbeam_epoch::Guard;
+
+    fn pin(&self) -> Self::Guard<'_> {
+        crossbeam_epoch::pin()
+    }
+
+    fn protect<T: 'static>(
+        _guard: &mut Self::Guard<'_>,
+        atomic: &Atomic<*const T>,
+    ) -> *const T {
+        atomic.load()
+    }
+
+    unsafe fn retire<T: Send + 'static>(guard: &Self::Guard<'_>, ptr: *const T) {
+        guard.defer_destroy(crossbeam_epoch::Shared::from(ptr));
+    }
+}
+
+/// Hazard pointer `Reclaimer`: every thread can protect up to
+/// `HazardPointers::SLOTS` pointers at a time, over all of its live guards.
+///
+/// Retired values of a thread are kept in the slot of its thread id, when a
+/// thread exits the next thread with the same id takes them over.
+pub struct HazardPointers {
+    threads: ThreadTable<HazardSlots>,
+}
+
+impl HazardPointers {
+    pub const SLOTS: usize = 8;
+    // number of retired values that makes a thread look for freeable ones
+    const SCAN_THRESHOLD: usize = 2 * Self::SLOTS;
+
+    pub fn new() -> Self {
+        Self {
+            threads: ThreadTable::new(),
+        }
+    }
+
+    // frees the retired values of the calling thread that no thread protects
+    fn scan(&self, slots: &HazardSlots) {
+        let mut protected = Vec::new();
+        for thread in self.threads.iter() {
+            for hazard in &thread.hazards {
+                let ptr = hazard.load(Ordering::SeqCst);
+                if !ptr.is_null() {
+                    protected.push(ptr);
+                }
+            }
+        }
+        let mut retired = slots.retired.lock().unwrap();
+        retired.retain(|r| {
+            if protected.contains(&r.ptr) {
+                true
+            } else {
+                unsafe { (r.free)(r.ptr) };
+                false
+            }
+        });
+    }
+}
+
+impl Drop for HazardPointers {
+    fn drop(&mut self) {
+        // no guard can be alive, they borrow the domain
+        for thread in self.threads.iter() {
+            for r in thread.retired.lock().unwrap().drain(..) {
+                unsafe { (r.free)(r.ptr) };
+            }
+        }
+    }
+}
+
+impl Default for HazardPointers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reclaimer for HazardPointers {
+    type Guard<'a> = HazardGuard<'a>;
+
+    fn pin(&self) -> Self::Guard<'_> {
+        // the thread keeps its id while the guard refers to its slots
+        let (_, slots) = self.threads.get_pinned();
+        HazardGuard {
+            domain: self,
+            slots,
+            used: 0,
+            _not_send: PhantomData,
+        }
+    }
+
+    fn protect<T: 'static>(
+        guard: &mut Self::Guard<'_>,
+        atomic: &Atomic<*const T>,
+    ) -> *const T {
+        let in_use = guard.slots.in_use.load(Ordering::Relaxed);
+        let free = !in_use & ((1 << Self::SLOTS) - 1);
+        assert!(
+            free != 0,
+            "more than {} pointers protected by one thread",
+            Self::SLOTS
+        );
+        let index = free.trailing_zeros() as usize;
+        guard
+            .slots
+            .in_use
+            .store(in_use | 1 << index, Ordering::Relaxed);
+        guard.used |= 1 << index;
+        let hazard = &guard.slots.hazards[index];
+        let mut ptr = atomic.load();
+        loop {
+            hazard.store(ptr as *mut u8, Ordering::SeqCst);
+            // the value can't have been retired before the hazard was published
+            // if the word still points to it
+            let current = atomic.load();
+            if current == ptr {
+                return ptr;
+            }
+            ptr = current;
+        }
+    }
+
+    unsafe fn retire<T: Send + 'static>(guard: &Self::Guard<'_>, ptr: *const T) {
+        unsafe fn free<T>(ptr: *mut u8) {
+            drop(Box::from_raw(ptr as *mut T));
+        }
+
+        let len = {
+            let mut retired = guard.slots.retired.lock().unwrap();
+            retired.push(Retired {
+                ptr: ptr as *mut u8,
+                free: free::<T>,
+            });
+            retired.len()
+        };
+        if len >= Self::SCAN_THRESHOLD {
+            guard.domain.scan(guard.slots);
+        }
+    }
+}
+
+pub struct HazardGuard<'a> {
+    domain: &'a HazardPointers,
+    slots: &'a HazardSlots,
+    // bit set of the hazard slots this guard protects pointers in, guards of
+    // a thread can be dropped in any order
+    used: usize,
+    // the slots belong to the thread that pinned the guard
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Drop for HazardGuard<'_> {
+    fn drop(&mut self) {
+        for (index, hazard) in self.slots.hazards.iter().enumerate() {
+            if self.used & 1 << index != 0 {
+                hazard.store(ptr::null_mut(), Ordering::Release);
+            }
+        }
+        let in_use = self.slots.in_use.load(Ordering::Relaxed);
+        self.slots
+            .in_use
+            .store(in_use & !self.used, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct HazardSlots {
+    hazards: [AtomicPtr<u8>; HazardPointers::SLOTS],
+    // bit set of the hazards taken by live guards, only touched by the
+    // owning thread
+    in_use: AtomicUsize,
+    retired: Mutex<Vec<Retired>>,
+}
+
+struct Retired {
+    ptr: *mut u8,
+    free: unsafe fn(*mut u8),
+}
+
+// retired values are `Send`, see `Reclaimer::retire`
+unsafe impl Send for Retired {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // counts its drops in the counter it points to
+    struct Tracked(&'static AtomicUsize);
+
+    impl Drop for Tracked {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_hazard_pointers() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        let domain = HazardPointers::new();
+        let word =
+            Atomic::<*const Tracked>::new(Box::into_raw(Box::new(Tracked(&DROPPED))));
+
+        let mut reader = domain.pin();
+        let protected = HazardPointers::protect(&mut reader, &word);
+
+        let writer = domain.pin();
+        let new: *const _ = Box::into_raw(Box::new(Tracked(&DROPPED)));
+        assert!(unsafe { crate::cas2(&word, &word, protected, protected, new, new) });
+        unsafe { HazardPointers::retire(&writer, protected) };
+        domain.scan(writer.slots);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+
+        drop(writer);
+        drop(reader);
+        domain.scan(domain.threads.get().1);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+        unsafe { drop(Box::from_raw(word.load() as *mut Tracked)) };
+    }
+
+    #[test]
+    fn test_guards_dropped_out_of_order() {
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        let domain = HazardPointers::new();
+        let first =
+            Atomic::<*const Tracked>::new(Box::into_raw(Box::new(Tracked(&DROPPED))));
+        let second =
+            Atomic::<*const Tracked>::new(Box::into_raw(Box::new(Tracked(&DROPPED))));
+
+        // pinned before either protects anything
+        let mut outer = domain.pin();
+        let mut inner = domain.pin();
+        let protected = HazardPointers::protect(&mut outer, &first);
+        HazardPointers::protect(&mut inner, &second);
+        drop(inner);
+
+        let writer = domain.pin();
+        let new: *const _ = Box::into_raw(Box::new(Tracked(&DROPPED)));
+        assert!(unsafe { crate::cas2(&first, &first, protected, protected, new, new) });
+        unsafe { HazardPointers::retire(&writer, protected) };
+        domain.scan(writer.slots);
+        // `outer` still protects the value
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 0);
+
+        drop((writer, outer));
+        domain.scan(domain.threads.get().1);
+        assert_eq!(DROPPED.load(Ordering::Relaxed), 1);
+        for word in [first, second] {
+            unsafe { drop(Box::from_raw(word.load() as *mut Tracked)) };
+        }
+    }
+
+    #[test]
+    fn test_replace_many() {
+        let domain = HazardPointers::new();
+        let words: Vec<_> = (0..3)
+            .map(|i| Atomic::<*const Vec<usize>>::new(Box::into_raw(Box::new(vec![i]))))
+            .collect();
+        let atomics: Vec<_> = words.iter().collect();
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    for _ in 0..100 {
+                        // moves the last element of every vector to the next one
+                        unsafe {
+                            replace_many(&domain, &atomics, |values| {
+                                let moved: Vec<_> =
+                                    values.iter_mut().map(|v| v.pop()).collect();
+                                for (i, value) in moved.into_iter().enumerate() {
+                                    values[(i + 1) % 3].extend(value);
+                                }
+                            })
+                        };
+                    }
+                });
+            }
+        });
+        let mut all: Vec<usize> = words
+            .iter()
+            .flat_map(|word| {
+                unsafe { Box::from_raw(word.load() as *mut Vec<usize>) }.into_iter()
+            })
+            .collect();
+        all.sort_unstable();
+        assert_eq!(all, [0, 1, 2]);
+    }
+}
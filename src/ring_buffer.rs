@@ -0,0 +1,243 @@
+//! A bounded multi-producer multi-consumer queue, enabled by the
+//! `structures` feature.
+
+use crate::{atomic::Bits, cas2, Atomic, CASN};
+use std::{marker::PhantomData, mem, ptr};
+
+/// A fixed capacity queue over a ring of slots.
+///
+/// `head` and `tail` count the values popped and pushed so far, so they
+/// never repeat. A push fills the slot at `tail` and bumps `tail` in one
+/// `cas2`, a pop empties the slot at `head` and bumps `head` the same way,
+/// which is all it takes to rule out the usual ABA problems of ring buffers.
+/// `push_two` and `pop_pair` move two values with a single 3-word `cas_n`, so
+/// the pair is adjacent in the queue and no other pop can split it.
+///
+/// Slots hold boxed values, so `T` needs an alignment of at least 4: the low
+/// bits of a slot would read as a descriptor mark otherwise.
+pub struct RingBuffer<T: 'static> {
+    // a boxed value or null
+    slots: Box<[Atomic<*const T>]>,
+    head: Atomic<usize>,
+    tail: Atomic<usize>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Send + 'static> RingBuffer<T> {
+    const ALIGNED: () = assert!(
+        mem::align_of::<T>() > Bits::RESERVED_MASK,
+        "pointers to T don't leave the reserved bits free"
+    );
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let () = Self::ALIGNED;
+        assert!(capacity > 0);
+        Self {
+            slots: (0..capacity).map(|_| Atomic::new(ptr::null())).collect(),
+            head: Atomic::new(0),
+            tail: Atomic::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Appends `value`, hands it back if the queue is full.
+    pub fn push(&self, value: T) -> Result<(), T> {
+        let new = Box::into_raw(Box::new(value)) as *const T;
+        loop {
+            let tail = self.tail.load();
+            let slot = self.slot(tail);
+            if !slot.load().is_null() {
+                // the slot may have been filled after `tail` was read
+                if self.tail.load() == tail {
+                    return Err(unsafe { *Box::from_raw(new as *mut T) });
+                }
+                continue;
+            }
+            if unsafe { cas2(&self.tail, slot, tail, ptr::null(), tail + 1, new) } {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Appends both values next to each other, or neither if there's room for
+    /// only one.
+    pub fn push_two(&self, first: T, second: T) -> Result<(), (T, T)> {
+        if self.capacity() < 2 {
+            return Err((first, second));
+        }
+        let new = [
+            Box::into_raw(Box::new(first)) as *const T,
+            Box::into_raw(Box::new(second)) as *const T,
+        ];
+        loop {
+            let tail = self.tail.load();
+            let slots = [self.slot(tail), self.slot(tail + 1)];
+            if slots.iter().any(|slot| !slot.load().is_null()) {
+                if self.tail.load() == tail {
+                    return Err(unsafe {
+                        (
+                            *Box::from_raw(new[0] as *mut T),
+                            *Box::from_raw(new[1] as *mut T),
+                        )
+                    });
+                }
+                continue;
+            }
+            let mut cas_n = CASN::new();
+            cas_n.add_unchecked(&self.tail, tail, tail + 2);
+            cas_n.add_unchecked(slots[0], ptr::null(), new[0]);
+            cas_n.add_unchecked(slots[1], ptr::null(), new[1]);
+            if unsafe { cas_n.exec() } {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T> {
+        loop {
+            let head = self.head.load();
+            let slot = self.slot(head);
+            let value = slot.load();
+            if value.is_null() {
+                // the slot may have been emptied after `head` was read
+                if self.head.load() == head {
+                    return None;
+                }
+                continue;
+            }
+            if unsafe { cas2(&self.head, slot, head, value, head + 1, ptr::null()) } {
+                return Some(unsafe { *Box::from_raw(value as *mut T) });
+            }
+        }
+    }
+
+    /// Takes the first two values, or nothing if there's only one.
+    pub fn pop_pair(&self) -> Option<(T, T)> {
+        if self.capacity() < 2 {
+            return None;
+        }
+        loop {
+            let head = self.head.load();
+            let slots = [self.slot(head), self.slot(head + 1)];
+            let values = [slots[0].load(), slots[1].load()];
+            if values.iter().any(|value| value.is_null()) {
+                if self.head.load() == head {
+                    return None;
+                }
+                continue;
+            }
+            let mut cas_n = CASN::new();
+            cas_n.add_unchecked(&self.head, head, head + 2);
+            cas_n.add_unchecked(slots[0], values[0], ptr::null());
+            cas_n.add_unchecked(slots[1], values[1], ptr::null());
+            if unsafe { cas_n.exec() } {
+                return Some(unsafe {
+                    (
+                        *Box::from_raw(values[0] as *mut T),
+                        *Box::from_raw(values[1] as *mut T),
+                    )
+                });
+            }
+        }
+    }
+
+    /// Number of values in the queue, may be stale by the time it's
+    /// returned.
+    pub fn len(&self) -> usize {
+        loop {
+            let tail = self.tail.load();
+            let head = self.head.load();
+            if self.tail.load() == tail {
+                return tail - head;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn slot(&self, index: usize) -> &Atomic<*const T> {
+        &self.slots[index % self.slots.len()]
+    }
+}
+
+impl<T: 'static> Drop for RingBuffer<T> {
+    fn drop(&mut self) {
+        for slot in self.slots.iter() {
+            let value = slot.load();
+            if !value.is_null() {
+                drop(unsafe { Box::from_raw(value as *mut T) });
+            }
+        }
+    }
+}
+
+unsafe impl<T: Send + 'static> Send for RingBuffer<T> {}
+// the `cas2` or `cas_n` of a pop empties the slot together with moving `head`,
+// so only the thread that won it reads the value back
+unsafe impl<T: Send + 'static> Sync for RingBuffer<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_ring_buffer() {
+        let queue = RingBuffer::with_capacity(3);
+        assert_eq!(queue.pop(), None);
+        queue.push(1).unwrap();
+        queue.push_two(2, 3).unwrap();
+        assert_eq!(queue.push(4), Err(4));
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.push_two(4, 5), Err((4, 5)));
+        queue.push(4).unwrap();
+        assert_eq!(queue.len(), 3);
+        assert_eq!(queue.pop_pair(), Some((2, 3)));
+        assert_eq!(queue.pop_pair(), None);
+        assert_eq!(queue.pop(), Some(4));
+        assert!(queue.is_empty());
+
+        let strings = RingBuffer::with_capacity(2);
+        strings.push(String::from("freed with the queue")).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_pairs() {
+        let queue = RingBuffer::with_capacity(16);
+        thread::scope(|s| {
+            for t in 0..2 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        let value = (t * 1000 + i) * 2;
+                        while queue.push_two(value, value + 1).is_err() {
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+            for _ in 0..2 {
+                let queue = &queue;
+                s.spawn(move || {
+                    for _ in 0..1000 {
+                        loop {
+                            if let Some((first, second)) = queue.pop_pair() {
+                                // pairs stay together
+                                assert_eq!(first + 1, second);
+                                break;
+                            }
+                            thread::yield_now();
+                        }
+                    }
+                });
+            }
+        });
+        assert!(queue.is_empty());
+    }
+}
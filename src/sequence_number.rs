@@ -1,19 +1,68 @@
+use crate::layout::DescriptorLayout;
+#[cfg(all(loom, not(tsan), not(feature = "heap-descriptors")))]
+use loom::sync::atomic::fence;
+#[cfg(all(loom, not(feature = "heap-descriptors")))]
+use loom::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(all(not(loom), not(tsan), not(feature = "heap-descriptors")))]
+use std::sync::atomic::fence;
+#[cfg(all(not(loom), not(feature = "heap-descriptors")))]
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+// Orderings of the fields of the per thread descriptors, which are published
+// like a seqlock. The fields are relaxed and fences order them against the
+// sequence number. ThreadSanitizer doesn't model fences, so with `--cfg tsan`
+// the fields are stored with release and loaded with acquire instead, which
+// orders them against the sequence number just the same.
+#[cfg(all(not(tsan), not(feature = "heap-descriptors")))]
+pub(crate) const FIELD_STORE: Ordering = Ordering::Relaxed;
+#[cfg(all(tsan, not(feature = "heap-descriptors")))]
+pub(crate) const FIELD_STORE: Ordering = Ordering::Release;
+#[cfg(all(not(tsan), not(feature = "heap-descriptors")))]
+pub(crate) const FIELD_LOAD: Ordering = Ordering::Relaxed;
+#[cfg(all(tsan, not(feature = "heap-descriptors")))]
+pub(crate) const FIELD_LOAD: Ordering = Ordering::Acquire;
+
+// between invalidating the sequence number and storing the fields
+#[cfg(not(feature = "heap-descriptors"))]
+#[inline]
+pub(crate) fn publish_fence() {
+    #[cfg(not(tsan))]
+    fence(Ordering::Release);
+}
+
+// between loading the fields and validating the sequence number
+#[cfg(not(feature = "heap-descriptors"))]
+#[inline]
+pub(crate) fn validate_fence() {
+    #[cfg(not(tsan))]
+    fence(Ordering::Acquire);
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct SeqNumber(usize);
 
 impl SeqNumber {
-    pub const LENGTH: usize = 48;
+    pub const LENGTH: usize = DescriptorLayout::SEQ_BITS;
+    pub const MASK: usize = DescriptorLayout::SEQ_MASK;
 
+    // Wraps around instead of carrying into the thread id above it in a
+    // descriptor pointer, which would make the pointer name the descriptor of
+    // another thread. A stale pointer then only aliases a live one if its
+    // holder stalls through 2^`LENGTH` increments of the owning thread, two
+    // per operation. `LENGTH` is 48 bits, 46 with `wide-thread-ids`, so a
+    // thread only wraps after days of back to back operations. The wrap is
+    // left unreported on purpose: it is harmless unless a helper stalls that
+    // long, and a panic would take down every process that ran long enough.
     pub fn inc(self) -> SeqNumber {
-        Self(self.0 + 1)
+        Self((self.0 + 1) & Self::MASK)
     }
 }
 
+#[cfg(not(feature = "heap-descriptors"))]
 #[derive(Debug)]
 pub struct SeqNumberGenerator(AtomicUsize);
 
+#[cfg(not(feature = "heap-descriptors"))]
 impl SeqNumberGenerator {
     pub fn new() -> Self {
         Self(AtomicUsize::new(0))
@@ -21,9 +70,9 @@ impl SeqNumberGenerator {
 
     #[allow(clippy::trivially_copy_pass_by_ref)]
     pub fn inc(&self, store_ordering: Ordering) -> SeqNumber {
-        let new = self.0.load(Ordering::Relaxed) + 1;
-        self.0.store(new, store_ordering);
-        SeqNumber(new)
+        let new = self.current(Ordering::Relaxed).inc();
+        self.0.store(new.0, store_ordering);
+        new
     }
 
     pub fn current(&self, ordering: Ordering) -> SeqNumber {
@@ -0,0 +1,335 @@
+//! A lock-free ordered map over a skip list, enabled by the `structures`
+//! feature.
+
+use crate::{cas_n, is_sentinel, with_sentinel, without_sentinel, Atomic};
+use crossbeam_epoch::{pin, Guard};
+use std::{array, cell::Cell, marker::PhantomData, ptr};
+
+// every level of a node is linked by one `cas_n`, which takes this many words
+const MAX_LEVEL: usize = 4;
+
+struct Node<K: 'static, V: 'static> {
+    // `None` in the head
+    key: Option<K>,
+    value: Option<V>,
+    height: usize,
+    // null past the last node of a level
+    next: [Atomic<*const Node<K, V>>; MAX_LEVEL],
+}
+
+impl<K: 'static, V: 'static> Node<K, V> {
+    fn alloc(key: Option<K>, value: Option<V>, height: usize) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            key,
+            value,
+            height,
+            next: array::from_fn(|_| Atomic::new(ptr::null())),
+        }))
+    }
+
+    fn key(&self) -> &K {
+        self.key.as_ref().unwrap()
+    }
+}
+
+// the links of a removed node are in the sentinel state
+fn is_deleted<T>(next: *const T) -> bool {
+    is_sentinel(next)
+}
+
+fn unmarked<T>(next: *const T) -> *const T {
+    without_sentinel(next)
+}
+
+fn marked<T>(next: *const T) -> *const T {
+    with_sentinel(next)
+}
+
+// levels are spread like coin flips, 1 in 2^i nodes reaches level i
+fn random_height() -> usize {
+    thread_local! {
+        static STATE: Cell<u32> = const { Cell::new(0) };
+    }
+    STATE.with(|state| {
+        let mut x = state.get();
+        if x == 0 {
+            // every thread starts from a different point
+            x = (state as *const Cell<u32> as usize as u32) | 1;
+        }
+        // xorshift32
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        state.set(x);
+        (x.trailing_ones() as usize + 1).min(MAX_LEVEL)
+    })
+}
+
+/// A skip list whose nodes appear on all of their levels at once.
+///
+/// An insert links the new node into every level with a single `cas_n`, so
+/// there's no window in which a node can be found on its lower levels but
+/// not on the upper ones. A remove marks the links of the node on all levels
+/// with one `cas_n`, after which searches unlink it level by level. Nodes are
+/// at most `MAX_LEVEL` (4) levels high, the number of words one `cas_n` takes.
+pub struct SkipListMap<K: 'static, V: 'static> {
+    head: *const Node<K, V>,
+    _marker: PhantomData<(K, V)>,
+}
+
+impl<K, V> SkipListMap<K, V>
+where
+    K: Ord + Send + 'static,
+    V: Send + 'static,
+{
+    pub fn new() -> Self {
+        Self {
+            head: Node::alloc(None, None, MAX_LEVEL),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Inserts the entry if the map doesn't have `key` yet, returns whether it
+    /// did.
+    pub fn insert(&self, key: K, value: V) -> bool {
+        let _guard = pin();
+        let height = random_height();
+        let node = Node::alloc(Some(key), Some(value), height);
+        let key = unsafe { (*node).key() };
+        loop {
+            let (preds, succs) = unsafe { self.find(key) };
+            if !succs[0].is_null() && unsafe { (*succs[0]).key() } == key {
+                drop(unsafe { Box::from_raw(node) });
+                return false;
+            }
+            for (level, succ) in succs.iter().enumerate().take(height) {
+                // the node isn't published yet
+                unsafe { (*node).next[level] = Atomic::new(*succ) };
+            }
+            let links: Vec<_> = (0..height)
+                .map(|level| unsafe { &(*preds[level]).next[level] })
+                .collect();
+            if unsafe { cas_n(&links, &succs[..height], &vec![node as *const _; height]) }
+            {
+                return true;
+            }
+        }
+    }
+
+    /// Removes the entry of `key`, returns whether there was one. The value
+    /// is dropped once no guard can see it anymore.
+    pub fn remove(&self, key: &K) -> bool {
+        let guard = pin();
+        loop {
+            let (_, succs) = unsafe { self.find(key) };
+            let node = succs[0];
+            if node.is_null() || unsafe { (*node).key() } != key {
+                return false;
+            }
+            let node = unsafe { &*node };
+            let links: Vec<_> = node.next[..node.height].iter().collect();
+            let nexts: Vec<_> = links.iter().map(|link| link.load()).collect();
+            if nexts.iter().any(|next| is_deleted(*next)) {
+                // somebody else removes it
+                return false;
+            }
+            let deleted: Vec<_> = nexts.iter().map(|next| marked(*next)).collect();
+            if unsafe { cas_n(&links, &nexts, &deleted) } {
+                // unlinks the node from every level
+                unsafe { self.find(key) };
+                unsafe { retire(&guard, node) };
+                return true;
+            }
+        }
+    }
+
+    pub fn get<'g>(&'g self, key: &K, _guard: &'g Guard) -> Option<&'g V> {
+        let mut pred = unsafe { &*self.head };
+        for level in (0..MAX_LEVEL).rev() {
+            loop {
+                let curr = unmarked(pred.next[level].load());
+                if curr.is_null() {
+                    break;
+                }
+                let curr = unsafe { &*curr };
+                if curr.key() >= key {
+                    if level == 0 && curr.key() == key && !is_deleted(curr.next[0].load())
+                    {
+                        return curr.value.as_ref();
+                    }
+                    break;
+                }
+                pred = curr;
+            }
+        }
+        None
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.get(key, &pin()).is_some()
+    }
+
+    // Returns the last node before `key` and the first one at or after it on
+    // every level, unlinking the removed nodes it comes across.
+    //
+    // safety: the caller is pinned
+    #[allow(clippy::type_complexity)]
+    unsafe fn find(
+        &self,
+        key: &K,
+    ) -> (
+        [*const Node<K, V>; MAX_LEVEL],
+        [*const Node<K, V>; MAX_LEVEL],
+    ) {
+        'retry: loop {
+            let mut preds = [ptr::null(); MAX_LEVEL];
+            let mut succs = [ptr::null(); MAX_LEVEL];
+            let mut pred = self.head;
+            for level in (0..MAX_LEVEL).rev() {
+                let mut curr = (*pred).next[level].load();
+                if is_deleted(curr) {
+                    continue 'retry;
+                }
+                while !curr.is_null() {
+                    let succ = (*curr).next[level].load();
+                    if is_deleted(succ) {
+                        let link = &(*pred).next[level];
+                        if !cas_n(&[link], &[curr], &[unmarked(succ)]) {
+                            continue 'retry;
+                        }
+                        curr = unmarked(succ);
+                    } else if (*curr).key() < key {
+                        pred = curr;
+                        curr = succ;
+                    } else {
+                        break;
+                    }
+                }
+                preds[level] = pred;
+                succs[level] = curr;
+            }
+            return (preds, succs);
+        }
+    }
+}
+
+// safety: `node` was unlinked from every level, nobody else retires it
+unsafe fn retire<K: Send + 'static, V: Send + 'static>(
+    guard: &Guard,
+    node: *const Node<K, V>,
+) {
+    let node = node as *mut Node<K, V>;
+    guard.defer_unchecked(move || drop(Box::from_raw(node)));
+}
+
+impl<K, V> Default for SkipListMap<K, V>
+where
+    K: Ord + Send + 'static,
+    V: Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: 'static, V: 'static> Drop for SkipListMap<K, V> {
+    fn drop(&mut self) {
+        // every node that wasn't retired is on the lowest level
+        let mut node = self.head;
+        while !node.is_null() {
+            let boxed = unsafe { Box::from_raw(node as *mut Node<K, V>) };
+            node = unmarked(boxed.next[0].load());
+        }
+    }
+}
+
+unsafe impl<K: Send + 'static, V: Send + 'static> Send for SkipListMap<K, V> {}
+// a search reads the key of every tower it passes on each level, and `get`
+// lends a node's `&V` to every thread that finds it until their guards unpin
+unsafe impl<K: Send + Sync + 'static, V: Send + Sync + 'static> Sync
+    for SkipListMap<K, V>
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicIsize, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn test_skip_list_map() {
+        let map = SkipListMap::new();
+        for key in [5, 1, 4, 2, 3] {
+            assert!(map.insert(key, key * 10));
+        }
+        assert!(!map.insert(3, 0));
+        assert_eq!(map.get(&3, &pin()), Some(&30));
+        assert!(map.remove(&3));
+        assert!(!map.remove(&3));
+        assert!(!map.contains_key(&3));
+        assert!([1, 2, 4, 5].iter().all(|key| map.contains_key(key)));
+        assert!(map.insert(3, 0));
+        assert_eq!(map.get(&3, &pin()), Some(&0));
+    }
+
+    #[test]
+    fn test_levels_sorted() {
+        let map = SkipListMap::new();
+        // per key, how many more inserts than removes succeeded
+        let net: Vec<_> = (0..64).map(|_| AtomicIsize::new(0)).collect();
+        thread::scope(|s| {
+            for t in 0..4 {
+                let (map, net) = (&map, &net);
+                s.spawn(move || {
+                    // every thread goes over all keys, so towers are linked
+                    // and marked next to each other on every level
+                    for i in 0..2000 {
+                        let key = (i * 7 + t * 3) % 64;
+                        if (i + t) % 3 == 0 {
+                            if map.remove(&key) {
+                                net[key].fetch_sub(1, Ordering::Relaxed);
+                            }
+                        } else if map.insert(key, i) {
+                            net[key].fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                });
+            }
+        });
+        let net: Vec<_> = net.iter().map(|n| n.load(Ordering::Relaxed)).collect();
+        assert!(net.iter().all(|&n| n == 0 || n == 1));
+
+        let head = unsafe { &*map.head };
+        let mut below: Vec<&Node<usize, usize>> = Vec::new();
+        for level in 0..MAX_LEVEL {
+            let mut nodes = Vec::new();
+            let mut next = head.next[level].load();
+            while let Some(node) = unsafe { next.as_ref() } {
+                next = node.next[level].load();
+                // no removed node is left on any level
+                assert!(!is_deleted(next));
+                nodes.push(node);
+            }
+            assert!(nodes.windows(2).all(|pair| pair[0].key() < pair[1].key()));
+            if level == 0 {
+                let keys: Vec<_> = nodes.iter().map(|node| *node.key()).collect();
+                let expected: Vec<_> = (0..64).filter(|&key| net[key] == 1).collect();
+                assert_eq!(keys, expected);
+            } else {
+                // a level holds exactly the nodes of the level below that
+                // are high enough, every tower is linked on all its levels
+                let expected: Vec<_> = below
+                    .iter()
+                    .filter(|node| node.height > level)
+                    .map(|&node| node as *const Node<_, _>)
+                    .collect();
+                let nodes: Vec<_> = nodes.iter().map(|&node| node as *const _).collect();
+                assert_eq!(nodes, expected);
+            }
+            below = nodes;
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! A fixed capacity slot allocator with generational handles, enabled by the
+//! `structures` feature.
+
+use crate::{atomic::Bits, cas2, cas_n, Atomic, CASN};
+use crossbeam_epoch::{pin, Guard};
+use std::{marker::PhantomData, mem, ptr};
+
+// ends the free list
+const NONE: usize = Atomic::<usize>::MAX;
+
+// The generation is even while the slot is free and odd while it holds a
+// value, every allocation and removal bumps it.
+struct Entry<T: 'static> {
+    generation: Atomic<usize>,
+    // the next free slot, only meaningful while the slot is free
+    next_free: Atomic<usize>,
+    // a boxed value or null
+    value: Atomic<*const T>,
+}
+
+/// Refers to a value of a `SlotMap`. A handle of a removed value stays
+/// invalid after its slot was reused, the slot's generation moved on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Handle {
+    index: usize,
+    generation: usize,
+}
+
+/// Stores values in a fixed number of slots and hands out `Handle`s to them.
+///
+/// Free slots form a stack threaded through the slots. An insert pops the
+/// top slot and bumps its generation with one `cas2`, which is what makes
+/// the pop safe from ABA: a slot that was popped and pushed back in the
+/// meantime has a different generation, even if the free list looks the
+/// same. A remove bumps the generation, takes the value out and pushes the
+/// slot back with a single 4-word `cas_n`.
+///
+/// Values are boxed into pointer words, which rules out types aligned to
+/// less than 4 bytes, zero sized ones included. That's checked at compile
+/// time.
+pub struct SlotMap<T: 'static> {
+    entries: Box<[Entry<T>]>,
+    free: Atomic<usize>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Send + 'static> SlotMap<T> {
+    const ALIGNED: () = assert!(
+        mem::align_of::<T>() > Bits::RESERVED_MASK,
+        "pointers to T don't leave the reserved bits free"
+    );
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        let () = Self::ALIGNED;
+        assert!(capacity < NONE);
+        let entries = (0..capacity)
+            .map(|index| Entry {
+                generation: Atomic::new(0),
+                next_free: Atomic::new(if index + 1 < capacity {
+                    index + 1
+                } else {
+                    NONE
+                }),
+                value: Atomic::new(ptr::null()),
+            })
+            .collect();
+        Self {
+            entries,
+            free: Atomic::new(if capacity > 0 { 0 } else { NONE }),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Stores `value` in a free slot, hands it back if there is none.
+    pub fn insert(&self, value: T) -> Result<Handle, T> {
+        let (index, generation) = loop {
+            let index = self.free.load();
+            if index == NONE {
+                return Err(value);
+            }
+            let entry = &self.entries[index];
+            let generation = entry.generation.load();
+            let next = entry.next_free.load();
+            if generation & 1 == 0
+                && unsafe {
+                    cas2(
+                        &self.free,
+                        &entry.generation,
+                        index,
+                        generation,
+                        next,
+                        generation + 1,
+                    )
+                }
+            {
+                break (index, generation + 1);
+            }
+        };
+        let entry = &self.entries[index];
+        // nobody else has a handle to the slot yet
+        let value = Box::into_raw(Box::new(value)) as *const T;
+        assert!(unsafe { cas_n(&[&entry.value], &[ptr::null()], &[value]) });
+        Ok(Handle { index, generation })
+    }
+
+    /// Removes the value of `handle`, returns `false` if it was removed
+    /// already. The value is dropped once no guard can see it anymore.
+    pub fn remove(&self, handle: Handle) -> bool {
+        let guard = pin();
+        let entry = match self.entries.get(handle.index) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        loop {
+            if entry.generation.load() != handle.generation {
+                return false;
+            }
+            let value = entry.value.load();
+            let free = self.free.load();
+            let next = entry.next_free.load();
+            let mut cas_n = CASN::new();
+            cas_n.add_unchecked(
+                &entry.generation,
+                handle.generation,
+                handle.generation + 1,
+            );
+            cas_n.add_unchecked(&entry.value, value, ptr::null());
+            cas_n.add_unchecked(&self.free, free, handle.index);
+            cas_n.add_unchecked(&entry.next_free, next, free);
+            if unsafe { cas_n.exec() } {
+                let value = value as *mut T;
+                unsafe { guard.defer_unchecked(move || drop(Box::from_raw(value))) };
+                return true;
+            }
+        }
+    }
+
+    pub fn get<'g>(&'g self, handle: Handle, _guard: &'g Guard) -> Option<&'g T> {
+        let entry = self.entries.get(handle.index)?;
+        if entry.generation.load() != handle.generation {
+            return None;
+        }
+        let value = entry.value.load();
+        // the value belongs to the handle if the slot wasn't reused since
+        if entry.generation.load() != handle.generation {
+            return None;
+        }
+        unsafe { value.as_ref() }
+    }
+
+    pub fn contains(&self, handle: Handle) -> bool {
+        self.get(handle, &pin()).is_some()
+    }
+}
+
+impl<T: 'static> Drop for SlotMap<T> {
+    fn drop(&mut self) {
+        for entry in self.entries.iter() {
+            let value = entry.value.load();
+            if !value.is_null() {
+                drop(unsafe { Box::from_raw(value as *mut T) });
+            }
+        }
+    }
+}
+
+unsafe impl<T: Send + 'static> Send for SlotMap<T> {}
+// `get` lends `&T` to every thread holding the handle, the value stays in its
+// slot until `remove` swaps it out
+unsafe impl<T: Send + Sync + 'static> Sync for SlotMap<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_slot_map() {
+        let map = SlotMap::with_capacity(2);
+        let one = map.insert("one").unwrap();
+        let two = map.insert("two").unwrap();
+        assert_eq!(map.insert("three"), Err("three"));
+        assert_eq!(map.get(one, &pin()), Some(&"one"));
+        assert!(map.remove(one));
+        assert!(!map.remove(one));
+        // reuses the slot of `one` under a new generation
+        let three = map.insert("three").unwrap();
+        assert_eq!(three.index, one.index);
+        assert!(!map.contains(one));
+        assert_eq!(map.get(three, &pin()), Some(&"three"));
+        assert!(map.contains(two));
+    }
+
+    #[test]
+    fn test_concurrent_insert_remove() {
+        let map = SlotMap::with_capacity(8);
+        thread::scope(|s| {
+            for t in 0..4 {
+                let map = &map;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        let handle = match map.insert(t * 1000 + i) {
+                            Ok(handle) => handle,
+                            Err(_) => continue,
+                        };
+                        // nobody else can reach the value through a stale handle
+                        assert_eq!(map.get(handle, &pin()), Some(&(t * 1000 + i)));
+                        assert!(map.remove(handle));
+                    }
+                });
+            }
+        });
+        let handles: Vec<_> = (0..8).map(|i| map.insert(i).unwrap()).collect();
+        assert!(map.insert(8).is_err());
+        assert!(handles.iter().all(|handle| map.contains(*handle)));
+    }
+}
@@ -0,0 +1,112 @@
+//! Per thread operation counters, enabled by the `stats` feature.
+
+use crate::thread_local::ThreadTable;
+use once_cell::sync::Lazy;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static STATS: Lazy<ThreadTable<ThreadStats>> = Lazy::new(ThreadTable::new);
+
+/// Totals of all counters, see `snapshot`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Descriptors installed into a word, by the owner or by a helper.
+    pub installs: u64,
+    /// RDCSS attempts that had to be retried because of a conflict.
+    pub rdcss_retries: u64,
+    /// Times an operation of another thread was helped.
+    pub helps: u64,
+    /// Multi-word CAS attempts that failed.
+    pub failed: u64,
+    /// Multi-word CAS attempts, failed ones included.
+    pub ops: u64,
+}
+
+impl Stats {
+    /// The counts between `earlier` and this snapshot.
+    pub fn since(self, earlier: Stats) -> Stats {
+        Stats {
+            installs: self.installs - earlier.installs,
+            rdcss_retries: self.rdcss_retries - earlier.rdcss_retries,
+            helps: self.helps - earlier.helps,
+            failed: self.failed - earlier.failed,
+            ops: self.ops - earlier.ops,
+        }
+    }
+
+    /// The share of the attempts that succeeded, NaN without any.
+    pub fn success_ratio(&self) -> f64 {
+        (self.ops - self.failed) as f64 / self.ops as f64
+    }
+
+    /// Operations of other threads helped per attempt, NaN without any.
+    pub fn helps_per_op(&self) -> f64 {
+        self.helps as f64 / self.ops as f64
+    }
+}
+
+#[derive(Default)]
+struct ThreadStats {
+    installs: AtomicU64,
+    rdcss_retries: AtomicU64,
+    helps: AtomicU64,
+    failed: AtomicU64,
+    ops: AtomicU64,
+}
+
+pub(crate) enum Event {
+    Install,
+    RdcssRetry,
+    Help,
+    Failed,
+    Op,
+}
+
+pub(crate) fn record(event: Event) {
+    let (_, stats) = STATS.get();
+    let counter = match event {
+        Event::Install => &stats.installs,
+        Event::RdcssRetry => &stats.rdcss_retries,
+        Event::Help => &stats.helps,
+        Event::Failed => &stats.failed,
+        Event::Op => &stats.ops,
+    };
+    // only the owning thread writes its counters
+    counter.store(counter.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+}
+
+/// Sums up the counters of all threads. Counters of exited threads are kept,
+/// and the next thread to get the same id keeps adding to them.
+pub fn snapshot() -> Stats {
+    let mut total = Stats::default();
+    for stats in STATS.iter() {
+        total.installs += stats.installs.load(Ordering::Relaxed);
+        total.rdcss_retries += stats.rdcss_retries.load(Ordering::Relaxed);
+        total.helps += stats.helps.load(Ordering::Relaxed);
+        total.failed += stats.failed.load(Ordering::Relaxed);
+        total.ops += stats.ops.load(Ordering::Relaxed);
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cas2, Atomic};
+
+    #[test]
+    fn test_snapshot() {
+        let atom0 = Atomic::new(0usize);
+        let atom1 = Atomic::new(0usize);
+
+        let before = snapshot();
+        assert!(unsafe { cas2(&atom0, &atom1, 0, 0, 1, 1) });
+        assert!(!unsafe { cas2(&atom0, &atom1, 0, 0, 2, 2) });
+        let after = snapshot();
+        let stats = after.since(before);
+        assert!(stats.failed >= 1 && stats.ops >= 2);
+        assert!(stats.success_ratio() > 0.0 && stats.success_ratio() < 1.0);
+        // a hardware transaction doesn't install descriptors
+        #[cfg(not(feature = "htm"))]
+        assert!(after.installs >= before.installs + 2);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::{
+    atomic::{Atomic, Bits, Word},
+    mwcas::{CASN, MAX_ENTRIES},
+};
+use arrayvec::ArrayVec;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+// unwinds out of the closure of a `tx` whose reads stopped fitting together
+struct Conflict;
+
+/// Runs `f` as a transaction over `Atomic`s and returns its result.
+///
+/// Reads and writes go through the `Tx` passed to `f`, which records every
+/// cell it touches with the value it first read and the value written last.
+/// On return the whole set is committed with one `cas_n`: cells only read are
+/// written back unchanged, which validates them, written cells get their new
+/// values. If any cell changed in the meantime nothing is written and `f`
+/// runs again, so it must not have side effects besides the `Tx` calls.
+///
+/// Every read of a new cell checks that the cells read before still hold
+/// their values, so `f` only sees values that were in memory together. If
+/// one changed, `f` is unwound out of and starts over right away instead of
+/// running on to a commit that would fail. Values are compared, a cell that
+/// changed and changed back in between counts as unchanged.
+///
+/// A transaction touches at most 4 cells, the number of words one `cas_n`
+/// takes, and panics beyond that.
+#[allow(clippy::missing_safety_doc)]
+pub unsafe fn tx<'a, T, R, F>(mut f: F) -> R
+where
+    T: Word,
+    F: FnMut(&mut Tx<'a, T>) -> R,
+{
+    loop {
+        let mut tx = Tx {
+            entries: ArrayVec::new(),
+        };
+        match panic::catch_unwind(AssertUnwindSafe(|| f(&mut tx))) {
+            Ok(result) => {
+                if tx.commit() {
+                    return result;
+                }
+            },
+            Err(payload) => {
+                if !payload.is::<Conflict>() {
+                    panic::resume_unwind(payload);
+                }
+            },
+        }
+    }
+}
+
+/// The read and write set of a running `tx`.
+pub struct Tx<'a, T: Word> {
+    // cell, value first read, value to commit
+    entries: ArrayVec<[(&'a Atomic<T>, T, T); MAX_ENTRIES]>,
+}
+
+impl<'a, T: Word> Tx<'a, T> {
+    /// The value the transaction wrote to `cell` or the one `cell` holds.
+    pub fn read(&mut self, cell: &'a Atomic<T>) -> T {
+        self.entry(cell).2
+    }
+
+    pub fn write(&mut self, cell: &'a Atomic<T>, value: T) {
+        self.entry(cell).2 = value;
+    }
+
+    fn entry(&mut self, cell: &'a Atomic<T>) -> &mut (&'a Atomic<T>, T, T) {
+        let index = match self.entries.iter().position(|entry| ptr::eq(entry.0, cell)) {
+            Some(index) => index,
+            None => {
+                assert!(!self.entries.is_full(), "too many cells for a single cas_n");
+                let value = cell.load();
+                // the cells read before still hold their values after this
+                // read, so all of them held them when it happened
+                if !self.unchanged() {
+                    panic::resume_unwind(Box::new(Conflict));
+                }
+                self.entries.push((cell, value, value));
+                self.entries.len() - 1
+            },
+        };
+        &mut self.entries[index]
+    }
+
+    fn unchanged(&self) -> bool {
+        self.entries.iter().all(|(cell, read, _)| {
+            let current: Bits = cell.load().into();
+            current == (*read).into()
+        })
+    }
+
+    unsafe fn commit(&self) -> bool {
+        let mut cas_n = CASN::new();
+        for (cell, expected, new) in &self.entries {
+            cas_n.add_unchecked(cell, *expected, *new);
+        }
+        cas_n.exec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cas2;
+    use std::thread;
+
+    #[test]
+    fn test_tx() {
+        let (x, y) = (Atomic::new(1usize), Atomic::new(0usize));
+        let sum = unsafe {
+            tx(|t| {
+                let a = t.read(&x);
+                t.write(&y, a + 1);
+                t.read(&x) + t.read(&y)
+            })
+        };
+        assert_eq!(sum, 3);
+        assert_eq!((x.load(), y.load()), (1, 2));
+    }
+
+    #[test]
+    fn test_reads_are_consistent() {
+        // the cells are kept equal, a transaction never sees them differ
+        let (a, b) = (Atomic::new(0usize), Atomic::new(0usize));
+        let mut runs = 0;
+        unsafe {
+            tx(|t| {
+                runs += 1;
+                let first = t.read(&a);
+                if runs == 1 {
+                    assert!(cas2(&a, &b, 0, 0, 1, 1));
+                }
+                assert_eq!(t.read(&b), first);
+            })
+        };
+        assert_eq!(runs, 2);
+    }
+
+    #[test]
+    fn test_concurrent_transfers() {
+        // transfers between accounts keep the total
+        let accounts = [Atomic::new(100usize), Atomic::new(100), Atomic::new(100)];
+        thread::scope(|s| {
+            for t in 0..3 {
+                let accounts = &accounts;
+                s.spawn(move || {
+                    for i in 0..1000 {
+                        let (from, to) =
+                            (&accounts[(t + i) % 3], &accounts[(t + i + 1) % 3]);
+                        unsafe {
+                            tx(|t| {
+                                let amount = t.read(from).min(5);
+                                let balance = t.read(from);
+                                t.write(from, balance - amount);
+                                let balance = t.read(to);
+                                t.write(to, balance + amount);
+                            })
+                        };
+                    }
+                });
+            }
+        });
+        assert_eq!(accounts.iter().map(|a| a.load()).sum::<usize>(), 300);
+    }
+}
@@ -0,0 +1,162 @@
+//! Breakpoints between the phases of an operation, enabled by the
+//! `test-internals` feature. A thread stops at an armed breakpoint until the
+//! test resumes it, so the test can run other operations against the half
+//! done one and reach the helping paths deterministically.
+//!
+//! ```ignore
+//! let installed = Breakpoint::new(Point::Installed(0));
+//! thread::scope(|s| {
+//!     s.spawn(|| {
+//!         let _armed = installed.arm();
+//!         unsafe { cas2(&a, &b, 0, 0, 1, 1) }
+//!     });
+//!     installed.wait_paused();
+//!     // helps the paused `cas2` to completion
+//!     assert_eq!(b.load(), 1);
+//!     installed.resume();
+//! });
+//! ```
+
+use std::{
+    cell::RefCell,
+    marker::PhantomData,
+    sync::{Arc, Condvar, Mutex},
+};
+
+/// The places an operation can be stopped at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Point {
+    /// The descriptor of a `cas_n` is installed in the entry with the given
+    /// index, entries are sorted by address.
+    Installed(usize),
+    /// All entries of a `cas_n` are installed or one failed, the status isn't
+    /// decided yet.
+    BeforeStatusCas,
+    /// The status of a `cas_n` is decided, its descriptor is still in the
+    /// words.
+    BeforeCleanup,
+    /// A RDCSS descriptor is installed, its control word isn't checked yet.
+    RdcssInstalled,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Armed,
+    Paused,
+    Resumed,
+}
+
+struct Shared {
+    point: Point,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+/// Stops the first armed thread that reaches its point, once.
+#[derive(Clone)]
+pub struct Breakpoint(Arc<Shared>);
+
+/// Keeps a breakpoint armed on the calling thread until dropped.
+pub struct Armed {
+    shared: Arc<Shared>,
+    // the breakpoint is armed in a thread local
+    _not_send: PhantomData<*const ()>,
+}
+
+thread_local! {
+    static ARMED: RefCell<Vec<Arc<Shared>>> = const { RefCell::new(Vec::new()) };
+}
+
+impl Breakpoint {
+    pub fn new(point: Point) -> Self {
+        Self(Arc::new(Shared {
+            point,
+            state: Mutex::new(State::Armed),
+            changed: Condvar::new(),
+        }))
+    }
+
+    /// Arms the breakpoint on the calling thread. Other threads, helpers of
+    /// the operations of this one included, run through it.
+    pub fn arm(&self) -> Armed {
+        ARMED.with(|armed| armed.borrow_mut().push(self.0.clone()));
+        Armed {
+            shared: self.0.clone(),
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Blocks until a thread is stopped at the breakpoint.
+    pub fn wait_paused(&self) {
+        let state = self.0.state.lock().unwrap();
+        let _state = self
+            .0
+            .changed
+            .wait_while(state, |state| *state == State::Armed)
+            .unwrap();
+    }
+
+    /// Lets the stopped thread go on, or the next one to reach the
+    /// breakpoint run through it.
+    pub fn resume(&self) {
+        *self.0.state.lock().unwrap() = State::Resumed;
+        self.0.changed.notify_all();
+    }
+}
+
+impl Drop for Armed {
+    fn drop(&mut self) {
+        ARMED.with(|armed| {
+            armed
+                .borrow_mut()
+                .retain(|shared| !Arc::ptr_eq(shared, &self.shared))
+        });
+    }
+}
+
+// whether the calling thread has to take the paths with breakpoints
+#[cfg(all(feature = "htm", target_arch = "x86_64"))]
+pub(crate) fn any_armed() -> bool {
+    ARMED.with(|armed| !armed.borrow().is_empty())
+}
+
+// called by the operations at every point
+pub(crate) fn reached(point: Point) {
+    let hit = ARMED.with(|armed| {
+        let mut armed = armed.borrow_mut();
+        let position = armed.iter().position(|shared| shared.point == point)?;
+        Some(armed.remove(position))
+    });
+    if let Some(shared) = hit {
+        let mut state = shared.state.lock().unwrap();
+        if *state == State::Armed {
+            *state = State::Paused;
+            shared.changed.notify_all();
+            let _state = shared
+                .changed
+                .wait_while(state, |state| *state == State::Paused)
+                .unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cas2, Atomic};
+    use std::thread;
+
+    #[test]
+    fn test_unarmed_threads_run_through() {
+        let breakpoint = Breakpoint::new(Point::BeforeStatusCas);
+        let (first, second) = (Atomic::new(0usize), Atomic::new(0usize));
+        // armed on another thread only
+        thread::scope(|s| {
+            s.spawn(|| drop(breakpoint.arm())).join().unwrap();
+        });
+        assert!(unsafe { cas2(&first, &second, 0, 0, 1, 1) });
+        let _armed = breakpoint.arm();
+        breakpoint.resume();
+        assert!(unsafe { cas2(&first, &second, 1, 1, 2, 2) });
+    }
+}
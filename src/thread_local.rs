@@ -1,87 +1,754 @@
+//! Thread ids and per thread values indexed by them.
+
+use crate::{layout::DescriptorLayout, CasNError};
+#[cfg(feature = "cache-padded")]
 use crossbeam_utils::CachePadded;
+#[cfg(not(loom))]
 use once_cell::sync::Lazy;
-use std::sync::atomic::{AtomicBool, Ordering};
+use once_cell::sync::OnceCell;
+#[cfg(not(feature = "cache-padded"))]
+use std::ops::{Deref, DerefMut};
+use std::{
+    cell::{Cell, RefCell},
+    marker::PhantomData,
+    ptr, slice,
+    sync::{
+        atomic::{fence, AtomicBool, AtomicPtr, AtomicU64, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, Weak,
+    },
+    time::{Duration, Instant},
+};
+
+// slots every table allocates up front
+pub(crate) const MAX_THREADS: usize = 1024;
+// ids the tid field of a descriptor pointer can hold
+pub(crate) const MAX_THREAD_IDS: usize = DescriptorLayout::MAX_TIDS;
+const _: () = assert!(MAX_THREAD_IDS <= u16::MAX as usize + 1);
+// ids handed out to threads. With `per-cpu` the table slots from
+// `MAX_THREADS` on belong to the ids, as fallbacks for when all leased slots
+// are taken, so that many fewer ids fit into a descriptor pointer.
+#[cfg(not(feature = "per-cpu"))]
+pub(crate) const MAX_REGISTERED: usize = MAX_THREAD_IDS;
+#[cfg(feature = "per-cpu")]
+pub(crate) const MAX_REGISTERED: usize = MAX_THREAD_IDS - MAX_THREADS;
+// ids past `MAX_THREADS` get their slots in segments of this size, allocated
+// the first time a thread with an id in the segment uses the table
+const SEGMENT_LEN: usize = MAX_THREADS;
+const NUM_SEGMENTS: usize = (MAX_THREAD_IDS - MAX_THREADS) / SEGMENT_LEN;
+
+// Every slot of a table gets cache lines of its own unless the default
+// `cache-padded` feature is turned off, then neighbouring threads share lines
+// and a table takes a fraction of the memory.
+#[cfg(feature = "cache-padded")]
+pub(crate) type Slot<V> = CachePadded<V>;
+#[cfg(not(feature = "cache-padded"))]
+pub(crate) type Slot<V> = Unpadded<V>;
+
+#[cfg(not(feature = "cache-padded"))]
+pub(crate) struct Unpadded<V>(V);
+
+#[cfg(not(feature = "cache-padded"))]
+impl<V> Unpadded<V> {
+    pub(crate) fn new(value: V) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(not(feature = "cache-padded"))]
+impl<V> Deref for Unpadded<V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.0
+    }
+}
+
+#[cfg(not(feature = "cache-padded"))]
+impl<V> DerefMut for Unpadded<V> {
+    fn deref_mut(&mut self) -> &mut V {
+        &mut self.0
+    }
+}
+
+#[cfg(not(loom))]
+static THREAD_IDS: Lazy<Vec<AtomicBool>> = Lazy::new(|| {
+    (0..MAX_REGISTERED)
+        .map(|_| AtomicBool::new(false))
+        .collect()
+});
+
+// Every thread of a loom execution gets an id of its own, counted from 0.
+// They aren't given back: loom drops thread locals after `join` returns, or
+// for the main thread after the statics, so the model may be gone already.
+#[cfg(loom)]
+loom::lazy_static! {
+    static ref NEXT_ID: loom::sync::atomic::AtomicUsize =
+        loom::sync::atomic::AtomicUsize::new(0);
+}
+
+// registrations that wait for a thread to exit, see `ThreadId::register_timeout`
+static WAITERS: AtomicUsize = AtomicUsize::new(0);
+static FREED_LOCK: Mutex<()> = Mutex::new(());
+static FREED: Condvar = Condvar::new();
+// of the registrations that operations do on their own, in nanoseconds
+static REGISTER_TIMEOUT: AtomicU64 = AtomicU64::new(0);
 
-pub const MAX_THREADS: usize = 1024;
-static THREAD_IDS: Lazy<Vec<AtomicBool>> =
-    Lazy::new(|| (0..MAX_THREADS).map(|_| AtomicBool::new(false)).collect());
+/// Sets how long a thread waits for another one to exit when it registers
+/// while all thread ids are taken, see `ThreadId::register_timeout`. Applies
+/// to `ThreadId::try_register` and to the registration every operation does
+/// on first use. Zero, the default, fails right away.
+pub fn set_register_timeout(timeout: Duration) {
+    let nanos = timeout.as_nanos().min(u64::MAX as u128) as u64;
+    REGISTER_TIMEOUT.store(nanos, Ordering::Relaxed);
+}
 
+#[cfg(not(loom))]
 thread_local! {
-       static REG_ID: RegisteredThreadId = ThreadId::register();
-       pub static THREAD_ID: ThreadId = ThreadId(REG_ID.with(|id| id.0));
+       // registered on first use, the id is given back when the thread exits
+       // or its last `ThreadToken` is dropped
+       static REG_ID: RefCell<Option<RegisteredThreadId>> = const { RefCell::new(None) };
+       // number of live `ThreadToken`s of the thread
+       static TOKENS: Cell<usize> = const { Cell::new(0) };
+       // set once the thread borrowed a slot of its id beyond an operation,
+       // see `ThreadTable::get_pinned`
+       static PINNED: Cell<bool> = const { Cell::new(false) };
+}
+
+// every loom thread runs on the same OS thread, the model needs its own thread
+// locals to give them ids of their own
+#[cfg(loom)]
+loom::thread_local! {
+       static REG_ID: RefCell<Option<RegisteredThreadId>> = RefCell::new(None);
+       static TOKENS: Cell<usize> = Cell::new(0);
+       static PINNED: Cell<bool> = Cell::new(false);
+}
+
+/// Registers the calling thread right away instead of on its first
+/// operation, e.g. when a pool starts its workers, and returns a token that
+/// unregisters it on drop. Panics if the thread can't be registered, see
+/// `ThreadId::try_register`.
+///
+/// Threads are unregistered when they exit anyway, the token is for threads
+/// that stop using the crate early or whose thread local destructors never
+/// run, like threads created by foreign code. A thread that is registered
+/// already gets a token for its current id. Dropping the last token of a
+/// thread unregisters it, a later operation registers it again. A thread that
+/// used a `ThreadLocal` or `reclaim::HazardPointers` keeps its id until it
+/// exits though, references into the slots of the id may still be around.
+///
+/// The token can be passed to `cas_n_with` and `CASN::set_thread`, which then
+/// skip the lookup of the thread's id.
+pub fn register_current_thread() -> ThreadToken {
+    let id = ThreadId::current();
+    TOKENS.with(|tokens| tokens.set(tokens.get() + 1));
+    ThreadToken {
+        id,
+        _marker: PhantomData,
+    }
+}
+
+/// Keeps the calling thread registered, see `register_current_thread`.
+#[derive(Debug)]
+pub struct ThreadToken {
+    id: ThreadId,
+    // has to be dropped by the thread it belongs to
+    _marker: PhantomData<*const ()>,
+}
+
+impl ThreadToken {
+    pub fn id(&self) -> ThreadId {
+        self.id
+    }
 }
 
-// 14bit thread id
+impl Drop for ThreadToken {
+    fn drop(&mut self) {
+        // The thread can't be in the middle of an operation, but references
+        // `get_pinned` handed out live as long as their tables, so a pinned
+        // id is only given back when the thread exits. Other tokens keep the
+        // id, operations run with them use it without checking.
+        let last = TOKENS
+            .try_with(|tokens| {
+                tokens.set(tokens.get() - 1);
+                tokens.get() == 0
+            })
+            .unwrap_or(true);
+        let pinned = PINNED.try_with(Cell::get).unwrap_or(true);
+        if last && !pinned {
+            let _ = REG_ID.try_with(|id| id.borrow_mut().take());
+        }
+    }
+}
+
+/// Id of a thread that uses the crate, its slot in the per thread descriptor
+/// tables. 14 bits wide, 16 with the `wide-thread-ids` feature.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct ThreadId(u16);
 
-pub struct RegisteredThreadId(u16);
+pub(crate) struct RegisteredThreadId(u16);
 
 impl ThreadId {
-    fn register() -> RegisteredThreadId {
-        for (index, slot) in (&*THREAD_IDS).iter().enumerate() {
-            let occupied = slot.load(Ordering::SeqCst);
+    /// Returns the id of the calling thread, registering it first if it's
+    /// the thread's first call. Fails with `CasNError::ThreadLimit` if all
+    /// ids are taken by live threads and none of them exits within the
+    /// timeout set with `set_register_timeout`.
+    ///
+    /// Every operation registers the calling thread the same way and panics
+    /// on failure, except `try_cas_n` and `CASN::try_exec`, which return the
+    /// error. With `heap-descriptors` operations don't need an id. Calling
+    /// this up front lets a thread fall back to something else before it gets
+    /// that far.
+    pub fn try_register() -> Result<ThreadId, CasNError> {
+        if let Some(id) = Self::registered() {
+            return Ok(id);
+        }
+        let timeout = Duration::from_nanos(REGISTER_TIMEOUT.load(Ordering::Relaxed));
+        Self::register_timeout(timeout)
+    }
+
+    /// Same as `try_register`, but parks the calling thread for up to
+    /// `timeout` until another thread exits and gives its id back if all of
+    /// them are taken.
+    pub fn register_timeout(timeout: Duration) -> Result<ThreadId, CasNError> {
+        REG_ID.with(|reg_id| {
+            let mut reg_id = reg_id.borrow_mut();
+            if let Some(id) = &*reg_id {
+                return Ok(ThreadId(id.0));
+            }
+            let id = Self::register(timeout)?;
+            let tid = ThreadId(id.0);
+            *reg_id = Some(id);
+            Ok(tid)
+        })
+    }
+
+    pub(crate) fn registered() -> Option<ThreadId> {
+        REG_ID.with(|id| id.borrow().as_ref().map(|id| ThreadId(id.0)))
+    }
+
+    // the calling thread's id, panics if it can't be registered
+    pub(crate) fn current() -> ThreadId {
+        match Self::try_register() {
+            Ok(id) => id,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn register(timeout: Duration) -> Result<RegisteredThreadId, CasNError> {
+        if let Some(id) = Self::take_free_id() {
+            return Ok(id);
+        }
+        if timeout.is_zero() {
+            return Err(CasNError::ThreadLimit);
+        }
+        let deadline = Instant::now() + timeout;
+        let mut lock = FREED_LOCK.lock().unwrap();
+        WAITERS.fetch_add(1, Ordering::Relaxed);
+        // pairs with the fence in `drop`: either the exiting thread sees the
+        // waiter and notifies it, or the waiter sees the free id
+        fence(Ordering::SeqCst);
+        let result = loop {
+            if let Some(id) = Self::take_free_id() {
+                break Ok(id);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break Err(CasNError::ThreadLimit);
+            }
+            lock = FREED.wait_timeout(lock, deadline - now).unwrap().0;
+        };
+        WAITERS.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+
+    #[cfg(loom)]
+    fn take_free_id() -> Option<RegisteredThreadId> {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        if id < loom::MAX_THREADS {
+            Some(RegisteredThreadId(id as _))
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(loom))]
+    fn take_free_id() -> Option<RegisteredThreadId> {
+        for (index, slot) in THREAD_IDS.iter().enumerate() {
+            let occupied = slot.load(Ordering::Relaxed);
             if !occupied {
+                // acquire pairs with the release in `drop`, the new owner of
+                // the slot reuses the descriptors of the previous one. The
+                // sequence numbers are part of the descriptors, so they keep
+                // counting up across owners and a stale pointer to an
+                // operation of an earlier owner never validates again.
                 match slot.compare_exchange(
                     false,
                     true,
-                    Ordering::SeqCst,
+                    Ordering::Acquire,
                     Ordering::Relaxed,
                 ) {
-                    Ok(_) => return RegisteredThreadId(index as _),
+                    Ok(_) => return Some(RegisteredThreadId(index as _)),
                     Err(_) => {
                         continue;
                     },
                 }
             }
         }
-        panic!("no free slots left, all {} slots are used", MAX_THREADS);
+        None
     }
 
     pub fn as_u16(self) -> u16 {
         self.0
     }
 
-    pub fn from_u16(v: u16) -> Self {
+    pub(crate) fn from_u16(v: u16) -> Self {
         Self(v)
     }
 }
 
+#[cfg(not(loom))]
 impl Drop for RegisteredThreadId {
     fn drop(&mut self) {
         let ids = &*THREAD_IDS;
-        ids[self.0 as usize].store(false, Ordering::SeqCst);
+        ids[self.0 as usize].store(false, Ordering::Release);
+        fence(Ordering::SeqCst);
+        if WAITERS.load(Ordering::Relaxed) > 0 {
+            // taking the lock makes sure a waiter that didn't see the id is
+            // waiting already
+            let _lock = FREED_LOCK.lock().unwrap();
+            FREED.notify_all();
+        }
     }
 }
 
-pub struct ThreadLocal<V> {
-    map: Vec<CachePadded<V>>,
+// Ids a domain hands out to the threads that use it instead of the global
+// ones, so that its tables only need slots for its own threads. A thread
+// takes the lowest free one on its first operation in the domain and holds it
+// until it exits.
+pub(crate) struct DomainIds {
+    taken: Box<[AtomicBool]>,
+}
+
+// An id the thread holds in a domain, given back when the thread exits. The
+// weak reference keeps the allocation of the ids of a dropped domain alive,
+// so no other domain can get the same address while the thread still lists
+// it.
+struct DomainId {
+    ids: Weak<DomainIds>,
+    id: u16,
+}
+
+thread_local! {
+       static DOMAIN_IDS: RefCell<Vec<DomainId>> = const { RefCell::new(Vec::new()) };
+}
+
+impl DomainIds {
+    pub(crate) fn new(capacity: usize) -> Arc<Self> {
+        assert!(capacity > 0 && capacity <= MAX_REGISTERED);
+        Arc::new(Self {
+            taken: (0..capacity).map(|_| AtomicBool::new(false)).collect(),
+        })
+    }
+
+    // the calling thread's id, taking one on its first call. Fails with
+    // `CasNError::ThreadLimit` if all of them are held by live threads.
+    pub(crate) fn current(self: &Arc<Self>) -> Result<ThreadId, CasNError> {
+        DOMAIN_IDS.with(|held| {
+            let mut held = held.borrow_mut();
+            let ours = held
+                .iter()
+                .find(|domain_id| ptr::eq(domain_id.ids.as_ptr(), Arc::as_ptr(self)));
+            if let Some(domain_id) = ours {
+                return Ok(ThreadId(domain_id.id));
+            }
+            // the ids of dropped domains are of no use anymore
+            held.retain(|domain_id| domain_id.ids.strong_count() > 0);
+            // acquire pairs with the release in `drop`, same as for the
+            // global ids the new owner reuses the descriptors of the old one
+            let id = self
+                .taken
+                .iter()
+                .position(|taken| {
+                    taken
+                        .compare_exchange(
+                            false,
+                            true,
+                            Ordering::Acquire,
+                            Ordering::Relaxed,
+                        )
+                        .is_ok()
+                })
+                .ok_or(CasNError::ThreadLimit)? as u16;
+            held.push(DomainId {
+                ids: Arc::downgrade(self),
+                id,
+            });
+            Ok(ThreadId(id))
+        })
+    }
+}
+
+impl Drop for DomainId {
+    fn drop(&mut self) {
+        if let Some(ids) = self.ids.upgrade() {
+            ids.taken[self.id as usize].store(false, Ordering::Release);
+        }
+    }
 }
 
-impl<V> ThreadLocal<V>
+pub(crate) struct ThreadTable<V> {
+    map: Vec<Slot<V>>,
+    // only tables with `MAX_THREADS` slots grow, or with `per-cpu` all of
+    // them, each pointer is the first slot of a leaked boxed slice of
+    // `SEGMENT_LEN` slots
+    segments: Vec<AtomicPtr<Slot<V>>>,
+}
+
+impl<V> ThreadTable<V>
 where
     V: Send + 'static + Default,
 {
     pub fn new() -> Self {
+        Self::with_capacity(MAX_THREADS)
+    }
+
+    // only threads with an id below `capacity` can use the table, unless it's
+    // `MAX_THREADS`: such a table grows for larger ids. With `per-cpu` every
+    // table grows, past the leased slots are the fallback slots of the ids.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity > 0 && capacity <= MAX_THREADS);
+        let num_segments = if capacity == MAX_THREADS {
+            NUM_SEGMENTS
+        } else if cfg!(feature = "per-cpu") {
+            (MAX_THREAD_IDS - capacity).div_ceil(SEGMENT_LEN)
+        } else {
+            0
+        };
         Self {
-            map: (0..MAX_THREADS)
-                .map(|_| CachePadded::new(V::default()))
+            map: (0..capacity).map(|_| Slot::new(V::default())).collect(),
+            segments: (0..num_segments)
+                .map(|_| AtomicPtr::new(ptr::null_mut()))
                 .collect(),
         }
     }
 
+    // number of ids with a slot right now, the slots of a growing table are
+    // counted up to its first missing segment
+    #[cfg_attr(not(feature = "wait-free"), allow(dead_code))]
+    pub fn capacity(&self) -> usize {
+        let allocated = self
+            .segments
+            .iter()
+            .take_while(|segment| !segment.load(Ordering::Acquire).is_null())
+            .count();
+        self.map.len() + allocated * SEGMENT_LEN
+    }
+
+    #[cfg_attr(not(feature = "stats"), allow(dead_code))]
     pub fn get(&self) -> (ThreadId, &V) {
-        let id = THREAD_ID.with(|id| *id);
+        let id = ThreadId::current();
 
         // safety: safe as only one thread has access to V
-        (id, &*self.map.get(id.0 as usize).unwrap())
+        (id, self.slot(id.0 as usize))
+    }
+
+    // `get` for callers that hand out references into the slot that may
+    // outlive the calling thread's tokens, the thread keeps its id until it
+    // exits
+    pub fn get_pinned(&self) -> (ThreadId, &V) {
+        let id = ThreadId::current();
+        PINNED.with(|pinned| pinned.set(true));
+        (id, self.slot(id.0 as usize))
+    }
+
+    // the slot of the descriptors the calling thread uses: the one of its
+    // thread id, or with `per-cpu` the one it leased for its operation
+    #[cfg_attr(
+        all(feature = "heap-descriptors", not(feature = "wait-free")),
+        allow(dead_code)
+    )]
+    pub fn get_slot(&self) -> (ThreadId, &V) {
+        #[cfg(feature = "per-cpu")]
+        let id = crate::per_cpu::current();
+        #[cfg(not(feature = "per-cpu"))]
+        let id = ThreadId::current();
+        (id, self.slot(id.0 as usize))
     }
 
+    // `get_slot` for a caller that knows its thread id already, `owner` has
+    // to be the id of the calling thread
+    #[cfg_attr(
+        all(feature = "heap-descriptors", not(feature = "wait-free")),
+        allow(dead_code)
+    )]
+    pub fn get_slot_of(&self, owner: Option<ThreadId>) -> (ThreadId, &V) {
+        match owner {
+            #[cfg(not(feature = "per-cpu"))]
+            Some(id) => (id, self.slot(id.0 as usize)),
+            _ => self.get_slot(),
+        }
+    }
+
+    #[cfg_attr(
+        all(feature = "heap-descriptors", not(feature = "wait-free")),
+        allow(dead_code)
+    )]
     pub fn get_for_thread(&self, thread_id: ThreadId) -> &V
     where
         V: Sync,
     {
         // safety: safe as V is Sync
-        &*self.map.get(thread_id.0 as usize).unwrap()
+        self.slot(thread_id.0 as usize)
+    }
+
+    // the slots of all ids that have one, in id order
+    pub fn iter(&self) -> impl Iterator<Item = &V> {
+        let segments = self.segments.iter().flat_map(|segment| {
+            let first = segment.load(Ordering::Acquire);
+            let slots: &[Slot<V>] = if first.is_null() {
+                &[]
+            } else {
+                unsafe { slice::from_raw_parts(first, SEGMENT_LEN) }
+            };
+            slots.iter()
+        });
+        self.map.iter().chain(segments).map(|slot| &**slot)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        let segments = self.segments.iter_mut().flat_map(|segment| {
+            let first = *segment.get_mut();
+            let slots: &mut [Slot<V>] = if first.is_null() {
+                &mut []
+            } else {
+                unsafe { slice::from_raw_parts_mut(first, SEGMENT_LEN) }
+            };
+            slots.iter_mut()
+        });
+        self.map.iter_mut().chain(segments).map(|slot| &mut **slot)
+    }
+
+    fn slot(&self, index: usize) -> &V {
+        if let Some(slot) = self.map.get(index) {
+            return slot;
+        }
+        let segment = match self.segments.get((index - self.map.len()) / SEGMENT_LEN) {
+            Some(segment) => segment,
+            None => panic!(
+                "thread id {} is out of range, the table is sized for {} threads",
+                index,
+                self.map.len()
+            ),
+        };
+        let mut first = segment.load(Ordering::Acquire);
+        if first.is_null() {
+            let slots: Box<[Slot<V>]> =
+                (0..SEGMENT_LEN).map(|_| Slot::new(V::default())).collect();
+            let new = Box::into_raw(slots) as *mut Slot<V>;
+            // release publishes the default values to the threads that find
+            // the segment later
+            match segment.compare_exchange(
+                ptr::null_mut(),
+                new,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => first = new,
+                Err(current) => {
+                    unsafe {
+                        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                            new,
+                            SEGMENT_LEN,
+                        )))
+                    };
+                    first = current;
+                },
+            }
+        }
+        unsafe { &*first.add((index - self.map.len()) % SEGMENT_LEN) }
+    }
+}
+
+impl<V> Drop for ThreadTable<V> {
+    fn drop(&mut self) {
+        for segment in &self.segments {
+            let first = segment.load(Ordering::Relaxed);
+            if !first.is_null() {
+                unsafe {
+                    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                        first,
+                        SEGMENT_LEN,
+                    )))
+                };
+            }
+        }
+    }
+}
+
+// the raw segment pointers own their slots like `map` does
+unsafe impl<V: Send> Send for ThreadTable<V> {}
+unsafe impl<V: Send + Sync> Sync for ThreadTable<V> {}
+
+/// A value per thread, kept in the slot of the thread's `ThreadId`.
+///
+/// Slots belong to ids rather than threads: a value stays in its slot when
+/// its thread exits and the next thread that gets the id finds it there.
+/// Tables have 1024 slots up front and grow for larger ids. A thread that
+/// got a value keeps its id until it exits, dropping its `ThreadToken`s
+/// doesn't give the id back.
+pub struct ThreadLocal<T: Send> {
+    table: ThreadTable<OnceCell<T>>,
+}
+
+impl<T: Send + 'static> ThreadLocal<T> {
+    pub fn new() -> Self {
+        Self {
+            table: ThreadTable::new(),
+        }
+    }
+
+    /// The value of the calling thread, if it has one.
+    pub fn get(&self) -> Option<&T> {
+        self.table.get_pinned().1.get()
+    }
+
+    /// The value of the calling thread, initialized with `init` if it
+    /// doesn't have one yet.
+    pub fn get_or<F: FnOnce() -> T>(&self, init: F) -> &T {
+        self.table.get_pinned().1.get_or_init(init)
+    }
+
+    /// The values of all threads that have one, in thread id order.
+    pub fn iter(&self) -> impl Iterator<Item = &T>
+    where
+        T: Sync,
+    {
+        self.table.iter().filter_map(OnceCell::get)
+    }
+
+    /// Number of threads that have a value, counted without looking at the
+    /// values themselves, so it works for values that aren't `Sync`.
+    pub fn len(&self) -> usize {
+        self.table
+            .iter()
+            .filter(|slot| slot.get().is_some())
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Takes all values out, leaving every slot empty.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.table.iter_mut().filter_map(OnceCell::take)
+    }
+
+    pub fn clear(&mut self) {
+        self.drain().for_each(drop);
+    }
+}
+
+impl<T: Send + 'static> Default for ThreadLocal<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// `get` and `get_or` hand a value only to the thread holding the id of its
+// slot, which keeps the id as long as it lives, `iter` requires `Sync`
+unsafe impl<T: Send> Sync for ThreadLocal<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    #[test]
+    fn test_segments() {
+        let table: ThreadTable<AtomicUsize> = ThreadTable::new();
+        assert_eq!(table.capacity(), MAX_THREADS);
+        table
+            .get_for_thread(ThreadId::from_u16(MAX_THREADS as u16))
+            .store(1, Ordering::Relaxed);
+        table
+            .get_for_thread(ThreadId::from_u16((MAX_THREAD_IDS - 1) as u16))
+            .store(2, Ordering::Relaxed);
+        assert_eq!(table.capacity(), MAX_THREADS + SEGMENT_LEN);
+        assert_eq!(table.iter().count(), MAX_THREADS + 2 * SEGMENT_LEN);
+        let values: Vec<_> = table
+            .iter()
+            .map(|v| v.load(Ordering::Relaxed))
+            .filter(|&v| v != 0)
+            .collect();
+        assert_eq!(values, [1, 2]);
+    }
+
+    #[test]
+    fn test_try_register() {
+        let id = ThreadId::try_register().unwrap();
+        assert_eq!(ThreadId::try_register(), Ok(id));
+        let other = std::thread::spawn(|| ThreadId::try_register().unwrap())
+            .join()
+            .unwrap();
+        assert_ne!(other, id);
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_thread_token() {
+        std::thread::spawn(|| {
+            let token = register_current_thread();
+            assert_eq!(ThreadId::try_register(), Ok(token.id()));
+            let second = register_current_thread();
+            drop(token);
+            assert_eq!(ThreadId::registered(), Some(second.id()));
+            drop(second);
+            assert!(ThreadId::registered().is_none());
+            // registered again on demand
+            let id = ThreadId::current();
+            assert!(THREAD_IDS[id.as_u16() as usize].load(Ordering::Relaxed));
+        })
+        .join()
+        .unwrap();
+    }
+
+    #[test]
+    #[cfg(not(loom))]
+    fn test_pinned_id_outlives_tokens() {
+        let values = ThreadLocal::new();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let token = register_current_thread();
+                let value = values.get_or(|| std::cell::Cell::new(1));
+                let id = token.id();
+                drop(token);
+                // another thread must not get the slot `value` is in
+                assert_eq!(ThreadId::registered(), Some(id));
+                value.set(2);
+            });
+        });
+        assert_eq!(values.len(), 1);
+    }
+
+    #[test]
+    fn test_thread_local() {
+        let mut values = ThreadLocal::new();
+        assert_eq!(values.get(), None);
+        assert_eq!(*values.get_or(|| 1), 1);
+        assert_eq!(*values.get_or(|| 2), 1);
+        std::thread::scope(|s| {
+            s.spawn(|| values.get_or(|| 3));
+        });
+        let mut all: Vec<_> = values.iter().copied().collect();
+        all.sort_unstable();
+        assert_eq!(all, [1, 3]);
+        assert_eq!(values.len(), 2);
+
+        assert_eq!(values.drain().count(), 2);
+        assert_eq!(values.get(), None);
+        values.get_or(|| 4);
+        values.clear();
+        assert!(values.is_empty());
     }
 }
@@ -0,0 +1,84 @@
+use crate::atomic::Bits;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static LIMIT: AtomicUsize = AtomicUsize::new(1 << 20);
+
+/// Sets how often a single retry loop may go around before debug builds
+/// panic, reporting the descriptors and addresses involved. `0` turns the
+/// check off. Release builds never check.
+pub fn set_livelock_limit(limit: usize) {
+    LIMIT.store(limit, Ordering::Relaxed);
+}
+
+// Counts the iterations of a retry loop. `context` is only evaluated for the
+// panic message.
+#[cfg(debug_assertions)]
+pub(crate) struct Watchdog {
+    iterations: usize,
+    limit: usize,
+}
+
+#[cfg(debug_assertions)]
+impl Watchdog {
+    #[inline]
+    pub fn new() -> Self {
+        Self::with_limit(LIMIT.load(Ordering::Relaxed))
+    }
+
+    #[inline]
+    fn with_limit(limit: usize) -> Self {
+        Self {
+            iterations: 0,
+            limit,
+        }
+    }
+
+    #[inline]
+    pub fn tick<F: FnOnce() -> String>(&mut self, context: F) {
+        self.iterations += 1;
+        if self.iterations == self.limit {
+            panic!(
+                "possible livelock, retried {} times: {}",
+                self.iterations,
+                context()
+            );
+        }
+    }
+}
+
+#[cfg(not(debug_assertions))]
+pub(crate) struct Watchdog;
+
+#[cfg(not(debug_assertions))]
+impl Watchdog {
+    #[inline(always)]
+    pub fn new() -> Self {
+        Self
+    }
+
+    #[inline(always)]
+    pub fn tick<F: FnOnce() -> String>(&mut self, _context: F) {}
+}
+
+pub(crate) fn describe(descriptor_ptr: Bits) -> String {
+    format!(
+        "descriptor(mark: {}, tid: {}, seq: {})",
+        descriptor_ptr.mark(),
+        descriptor_ptr.tid().as_u16(),
+        descriptor_ptr.seq().as_usize()
+    )
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "retried 3 times: test loop")]
+    fn test_watchdog() {
+        let mut watchdog = Watchdog::with_limit(3);
+        for _ in 0..3 {
+            watchdog.tick(|| "test loop".to_string());
+        }
+    }
+}
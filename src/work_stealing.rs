@@ -0,0 +1,285 @@
+//! A Chase-Lev work-stealing deque, enabled by the experimental
+//! `work-stealing` feature.
+
+use crate::{atomic::Bits, cas2, cas_n, Atomic};
+use crossbeam_epoch::{pin, Guard};
+use std::{cell::Cell, marker::PhantomData, mem, ptr, sync::Arc};
+
+const MIN_CAPACITY: usize = 16;
+
+// a boxed value or whatever a slot held last, only read at indices in
+// `top..bottom`
+struct Buffer<T: 'static> {
+    slots: Box<[Atomic<*const T>]>,
+}
+
+impl<T: 'static> Buffer<T> {
+    fn alloc(capacity: usize) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            slots: (0..capacity).map(|_| Atomic::new(ptr::null())).collect(),
+        }))
+    }
+
+    fn slot(&self, index: usize) -> &Atomic<*const T> {
+        &self.slots[index % self.slots.len()]
+    }
+}
+
+// `top` and `bottom` count the values taken from the top and pushed to the
+// bottom, minus the ones popped back, so `top` never repeats.
+struct Inner<T: 'static> {
+    top: Atomic<usize>,
+    bottom: Atomic<usize>,
+    buffer: Atomic<*const Buffer<T>>,
+}
+
+/// Creates a deque, the `Worker` pushes and pops at the bottom, any number of
+/// `Stealer`s take values from the top.
+///
+/// Unlike the original algorithm, which relies on fences between the owner's
+/// store to `bottom` and its read of `top`, every step is a `cas2`: a steal
+/// bumps `top` and checks that the buffer it read the value from is still
+/// the current one, so a steal can't hand out a value from a buffer the owner
+/// replaced while growing it. A pop lowers `bottom` and checks that `top`
+/// didn't move, the last value is taken by bumping `top` like a steal would.
+///
+/// `T` has to be aligned to 4 bytes or more, the slots hold pointers to boxed
+/// values.
+pub fn work_stealing<T: Send + 'static>() -> (Worker<T>, Stealer<T>) {
+    let () = Worker::<T>::ALIGNED;
+    let inner = Arc::new(Inner {
+        top: Atomic::new(0),
+        bottom: Atomic::new(0),
+        buffer: Atomic::new(Buffer::alloc(MIN_CAPACITY) as *const _),
+    });
+    let worker = Worker {
+        inner: inner.clone(),
+        _not_sync: PhantomData,
+    };
+    (worker, Stealer { inner })
+}
+
+/// The owner's end of the deque, only one thread at a time can use it.
+pub struct Worker<T: 'static> {
+    inner: Arc<Inner<T>>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl<T: Send + 'static> Worker<T> {
+    const ALIGNED: () = assert!(
+        mem::align_of::<T>() > Bits::RESERVED_MASK,
+        "pointers to T don't leave the reserved bits free"
+    );
+
+    pub fn push(&self, value: T) {
+        let guard = pin();
+        let inner = &*self.inner;
+        let bottom = inner.bottom.load();
+        let top = inner.top.load();
+        let mut buffer = unsafe { &*inner.buffer.load() };
+        if bottom - top >= buffer.slots.len() {
+            buffer = unsafe { self.grow(buffer, top, bottom, &guard) };
+        }
+        // only the owner writes slots and `bottom`
+        let slot = buffer.slot(bottom);
+        let new = Box::into_raw(Box::new(value)) as *const T;
+        let pushed =
+            unsafe { cas2(slot, &inner.bottom, slot.load(), bottom, new, bottom + 1) };
+        assert!(pushed);
+    }
+
+    /// Takes the value pushed last.
+    pub fn pop(&self) -> Option<T> {
+        let _guard = pin();
+        let inner = &*self.inner;
+        loop {
+            let bottom = inner.bottom.load();
+            let top = inner.top.load();
+            if top == bottom {
+                return None;
+            }
+            let value = unsafe { &*inner.buffer.load() }.slot(bottom - 1).load();
+            let taken = if top + 1 < bottom {
+                unsafe { cas2(&inner.bottom, &inner.top, bottom, top, bottom - 1, top) }
+            } else {
+                // the last value, stealers may race for it
+                unsafe { cas2(&inner.top, &inner.bottom, top, bottom, top + 1, bottom) }
+            };
+            if taken {
+                return Some(unsafe { *Box::from_raw(value as *mut T) });
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer {
+            inner: self.inner.clone(),
+        }
+    }
+
+    // Replaces a full buffer with one twice as large, the old one is freed
+    // once no stealer can read from it anymore.
+    //
+    // safety: `old` is the current buffer, the guard keeps it alive
+    unsafe fn grow<'g>(
+        &self,
+        old: &Buffer<T>,
+        top: usize,
+        bottom: usize,
+        guard: &'g Guard,
+    ) -> &'g Buffer<T> {
+        let capacity = old.slots.len() * 2;
+        let new = Buffer::alloc(capacity);
+        // stealers only take values, so the ones from `top` on are still there
+        for index in top..bottom {
+            (*new).slots[index % capacity] = Atomic::new(old.slot(index).load());
+        }
+        let old = old as *const Buffer<T>;
+        assert!(cas_n(&[&self.inner.buffer], &[old], &[new as *const _]));
+        guard.defer_unchecked(move || drop(Box::from_raw(old as *mut Buffer<T>)));
+        &*new
+    }
+}
+
+/// A thief's end of the deque, can be cloned and shared between threads.
+pub struct Stealer<T: 'static> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T: Send + 'static> Stealer<T> {
+    /// Takes the value pushed first.
+    pub fn steal(&self) -> Option<T> {
+        let _guard = pin();
+        let inner = &*self.inner;
+        loop {
+            let top = inner.top.load();
+            let bottom = inner.bottom.load();
+            if top >= bottom {
+                return None;
+            }
+            let buffer = inner.buffer.load();
+            // the slot can only be reused once `top` moved past it
+            let value = unsafe { &*buffer }.slot(top).load();
+            if unsafe { cas2(&inner.top, &inner.buffer, top, buffer, top + 1, buffer) } {
+                return Some(unsafe { *Box::from_raw(value as *mut T) });
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: 'static> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T: 'static> Inner<T> {
+    // may be stale by the time it's returned
+    fn len(&self) -> usize {
+        loop {
+            let bottom = self.bottom.load();
+            let top = self.top.load();
+            if self.bottom.load() == bottom {
+                return bottom.saturating_sub(top);
+            }
+        }
+    }
+}
+
+impl<T: 'static> Drop for Inner<T> {
+    fn drop(&mut self) {
+        let buffer = unsafe { Box::from_raw(self.buffer.load() as *mut Buffer<T>) };
+        for index in self.top.load()..self.bottom.load() {
+            drop(unsafe { Box::from_raw(buffer.slot(index).load() as *mut T) });
+        }
+    }
+}
+
+unsafe impl<T: Send + 'static> Send for Worker<T> {}
+unsafe impl<T: Send + 'static> Send for Stealer<T> {}
+// a thief only keeps the value it read if its `cas2` moves `top` past the
+// slot, so a value goes to one thief or to the worker, never to both
+unsafe impl<T: Send + 'static> Sync for Stealer<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn test_work_stealing() {
+        let (worker, stealer) = work_stealing();
+        assert_eq!(worker.pop(), None);
+        assert_eq!(stealer.steal(), None);
+        for i in 0..40 {
+            worker.push(i);
+        }
+        assert_eq!(stealer.len(), 40);
+        assert_eq!(stealer.steal(), Some(0));
+        assert_eq!(worker.pop(), Some(39));
+        assert_eq!(stealer.steal(), Some(1));
+        let rest: Vec<_> = std::iter::from_fn(|| worker.pop()).collect();
+        assert_eq!(rest, (2..39).rev().collect::<Vec<_>>());
+        assert!(worker.is_empty());
+
+        let (strings, _) = work_stealing();
+        strings.push(String::from("freed with the deque"));
+    }
+
+    #[test]
+    fn test_concurrent_steals() {
+        let (worker, stealer) = work_stealing();
+        let sum = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for _ in 0..3 {
+                let (stealer, sum) = (stealer.clone(), &sum);
+                s.spawn(move || {
+                    let mut idle = 0;
+                    while idle < 1000 {
+                        match stealer.steal() {
+                            Some(value) => {
+                                sum.fetch_add(value, Ordering::Relaxed);
+                                idle = 0;
+                            },
+                            None => idle += 1,
+                        }
+                    }
+                });
+            }
+            for i in 1..=5000 {
+                worker.push(i);
+                if i % 3 == 0 {
+                    if let Some(value) = worker.pop() {
+                        sum.fetch_add(value, Ordering::Relaxed);
+                    }
+                }
+            }
+            while let Some(value) = worker.pop() {
+                sum.fetch_add(value, Ordering::Relaxed);
+            }
+        });
+        // every value was taken exactly once
+        assert_eq!(sum.into_inner(), 5000 * 5001 / 2);
+    }
+}
@@ -0,0 +1,38 @@
+#![cfg(feature = "derive")]
+
+use mw_cas::{Atomic, MwAtomicFields};
+use std::ptr;
+
+#[derive(MwAtomicFields)]
+struct Bounds {
+    low: Atomic<usize>,
+    high: Atomic<usize>,
+    name: Atomic<*const &'static str>,
+}
+
+#[test]
+fn test_derive() {
+    static NAME: &str = "bounds";
+    let bounds = Bounds {
+        low: Atomic::new(1),
+        high: Atomic::new(2),
+        name: Atomic::new(ptr::null()),
+    };
+    let before = bounds.snapshot();
+    assert_eq!(
+        before,
+        BoundsSnapshot {
+            low: 1,
+            high: 2,
+            name: ptr::null(),
+        }
+    );
+    let after = BoundsSnapshot {
+        low: 3,
+        high: 4,
+        name: &NAME,
+    };
+    assert!(unsafe { bounds.cas_all(before, after) });
+    assert!(!unsafe { bounds.cas_all(before, after) });
+    assert_eq!(bounds.snapshot(), after);
+}
@@ -0,0 +1,67 @@
+//! Helping paths reached by stopping an operation between its phases.
+#![cfg(feature = "test-internals")]
+
+use mw_cas::{
+    cas2,
+    test_hooks::{Breakpoint, Point},
+    Atomic,
+};
+use std::thread;
+
+// the words in the order their entries are installed in
+fn sorted_words() -> [Atomic<usize>; 2] {
+    [Atomic::new(0), Atomic::new(0)]
+}
+
+fn paused_cas2(point: Point, check: impl FnOnce(&Atomic<usize>, &Atomic<usize>)) {
+    let [first, second] = sorted_words();
+    assert!((&first as *const _) < (&second as *const _));
+    let breakpoint = Breakpoint::new(point);
+    let owner_succeeded = thread::scope(|s| {
+        let owner = s.spawn(|| {
+            let _armed = breakpoint.arm();
+            unsafe { cas2(&first, &second, 0, 0, 1, 1) }
+        });
+        breakpoint.wait_paused();
+        check(&first, &second);
+        breakpoint.resume();
+        owner.join().unwrap()
+    });
+    // a helper finished it, the owner still reports its success
+    assert!(owner_succeeded);
+    assert_eq!((first.load(), second.load()), (1, 1));
+}
+
+#[test]
+fn test_load_helps_partly_installed_cas2() {
+    // only the first word holds the descriptor, the load of the second one
+    // doesn't see the operation yet
+    paused_cas2(Point::Installed(0), |first, second| {
+        assert_eq!(second.load(), 0);
+        assert_eq!(first.load(), 1);
+        assert_eq!(second.load(), 1);
+    });
+}
+
+#[test]
+fn test_conflicting_cas2_helps_undecided_cas2() {
+    paused_cas2(Point::BeforeStatusCas, |first, second| {
+        assert!(!unsafe { cas2(first, second, 0, 0, 2, 2) });
+    });
+}
+
+#[test]
+fn test_load_helps_installed_rdcss() {
+    paused_cas2(Point::RdcssInstalled, |first, _| {
+        assert_eq!(first.load(), 1)
+    });
+}
+
+#[test]
+fn test_cleanup_by_helper() {
+    paused_cas2(Point::BeforeCleanup, |first, second| {
+        assert!(unsafe { cas2(first, second, 1, 1, 2, 2) });
+        assert_eq!((first.load(), second.load()), (2, 2));
+        assert!(unsafe { cas2(first, second, 2, 2, 1, 1) });
+    });
+}
@@ -0,0 +1,198 @@
+//! Records histories of concurrent `cas_n`s and loads and checks that each of
+//! them is linearizable: that some order of the operations, which keeps every
+//! operation after the ones that returned before it started, is what a
+//! sequential array of words would have answered.
+
+use mw_cas::{cas_n, Atomic};
+use rand::{rngs::SmallRng, seq::SliceRandom, Rng, SeedableRng};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Barrier,
+    },
+    thread,
+};
+
+const CELLS: usize = 4;
+const THREADS: usize = 3;
+// each round is up to 3 loads and a `cas_n`
+const ROUNDS_PER_THREAD: usize = 3;
+
+#[derive(Clone, Debug)]
+enum Op {
+    Load {
+        cell: usize,
+    },
+    CasN {
+        cells: Vec<usize>,
+        expected: Vec<usize>,
+        new: Vec<usize>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Ret {
+    Value(usize),
+    Swapped(bool),
+}
+
+// one operation of a history, `invoked` and `returned` are ticks of a clock
+// shared by all threads
+#[derive(Clone, Debug)]
+struct Event {
+    op: Op,
+    ret: Ret,
+    invoked: usize,
+    returned: usize,
+}
+
+// the sequential specification
+fn apply(cells: &mut [usize; CELLS], op: &Op) -> Ret {
+    match op {
+        Op::Load { cell } => Ret::Value(cells[*cell]),
+        Op::CasN {
+            cells: indices,
+            expected,
+            new,
+        } => {
+            let matches = indices.iter().zip(expected).all(|(&i, &e)| cells[i] == e);
+            if matches {
+                for (&i, &n) in indices.iter().zip(new) {
+                    cells[i] = n;
+                }
+            }
+            Ret::Swapped(matches)
+        },
+    }
+}
+
+// Searches for a linearization, Wing & Gong style: any pending operation
+// that was invoked before the earliest return among the pending ones can
+// take effect next. Visited states are remembered to keep it polynomial in
+// practice.
+fn is_linearizable(history: &[Event]) -> bool {
+    assert!(history.len() <= 64);
+    fn search(
+        history: &[Event],
+        done: u64,
+        cells: [usize; CELLS],
+        seen: &mut HashSet<(u64, [usize; CELLS])>,
+    ) -> bool {
+        if done.count_ones() as usize == history.len() {
+            return true;
+        }
+        if !seen.insert((done, cells)) {
+            return false;
+        }
+        let pending = || (0..history.len()).filter(move |&i| done & (1 << i) == 0);
+        let first_return = pending().map(|i| history[i].returned).min().unwrap();
+        pending()
+            .filter(|&i| history[i].invoked < first_return)
+            .any(|i| {
+                let mut next = cells;
+                apply(&mut next, &history[i].op) == history[i].ret
+                    && search(history, done | (1 << i), next, seen)
+            })
+    }
+    search(history, 0, [0; CELLS], &mut HashSet::new())
+}
+
+// Every thread loads a few cells and then tries to replace what it saw.
+fn record(seed: u64) -> Vec<Event> {
+    let cells: Vec<_> = (0..CELLS).map(|_| Atomic::new(0usize)).collect();
+    let clock = AtomicUsize::new(0);
+    // lines the threads up so their operations overlap
+    let start = Barrier::new(THREADS);
+    let histories: Vec<Vec<Event>> = thread::scope(|s| {
+        let threads: Vec<_> = (0..THREADS as u64)
+            .map(|t| {
+                let (cells, clock, start) = (&cells, &clock, &start);
+                s.spawn(move || {
+                    start.wait();
+                    let mut rng = SmallRng::seed_from_u64(seed * 31 + t);
+                    let mut events = Vec::new();
+                    let mut run = |op: Op| {
+                        let invoked = clock.fetch_add(1, Ordering::SeqCst);
+                        let ret = match &op {
+                            Op::Load { cell } => Ret::Value(cells[*cell].load()),
+                            Op::CasN {
+                                cells: indices,
+                                expected,
+                                new,
+                            } => {
+                                let addresses: Vec<_> =
+                                    indices.iter().map(|&i| &cells[i]).collect();
+                                Ret::Swapped(unsafe { cas_n(&addresses, expected, new) })
+                            },
+                        };
+                        let returned = clock.fetch_add(1, Ordering::SeqCst);
+                        events.push(Event {
+                            op,
+                            ret,
+                            invoked,
+                            returned,
+                        });
+                        ret
+                    };
+                    for _ in 0..ROUNDS_PER_THREAD {
+                        let mut indices: Vec<_> = (0..CELLS).collect();
+                        indices.shuffle(&mut rng);
+                        indices.truncate(rng.gen_range(1, 4));
+                        let expected = indices
+                            .iter()
+                            .map(|&cell| match run(Op::Load { cell }) {
+                                Ret::Value(value) => value,
+                                Ret::Swapped(_) => unreachable!(),
+                            })
+                            .collect();
+                        let new = indices.iter().map(|_| rng.gen_range(0, 3)).collect();
+                        run(Op::CasN {
+                            cells: indices,
+                            expected,
+                            new,
+                        });
+                    }
+                    events
+                })
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+    histories.into_iter().flatten().collect()
+}
+
+#[test]
+fn test_cas_n_is_linearizable() {
+    for seed in 0..300 {
+        let history = record(seed);
+        assert!(is_linearizable(&history), "{:#?}", history);
+    }
+}
+
+#[test]
+fn test_checker_rejects_lost_update() {
+    let event = |op, ret, invoked, returned| Event {
+        op,
+        ret,
+        invoked,
+        returned,
+    };
+    let swap = Op::CasN {
+        cells: vec![0, 1],
+        expected: vec![0, 0],
+        new: vec![1, 1],
+    };
+    // a load that starts after the swap returned still sees the old value
+    let history = [
+        event(swap.clone(), Ret::Swapped(true), 0, 1),
+        event(Op::Load { cell: 1 }, Ret::Value(0), 2, 3),
+    ];
+    assert!(!is_linearizable(&history));
+    // overlapping, the load may take effect first
+    let history = [
+        event(swap, Ret::Swapped(true), 0, 3),
+        event(Op::Load { cell: 1 }, Ret::Value(0), 1, 2),
+    ];
+    assert!(is_linearizable(&history));
+}
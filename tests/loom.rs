@@ -0,0 +1,109 @@
+//! Model checks interleavings of `cas2` and `load`, run with
+//! `RUSTFLAGS="--cfg loom" cargo test --release --test loom`.
+#![cfg(loom)]
+
+use loom::{sync::Arc, thread};
+use mw_cas::{cas2, Atomic};
+
+type Words = Arc<(Atomic<usize>, Atomic<usize>, Atomic<usize>)>;
+
+fn words() -> Words {
+    Arc::new((Atomic::new(0), Atomic::new(0), Atomic::new(0)))
+}
+
+#[test]
+fn test_cas2_load() {
+    loom::model(|| {
+        let words = words();
+        let writer = {
+            let words = words.clone();
+            thread::spawn(move || unsafe { cas2(&words.0, &words.1, 0, 0, 1, 1) })
+        };
+        // the second word can't lag behind the first one
+        let first = words.0.load();
+        let second = words.1.load();
+        assert!(first <= second);
+        assert!(writer.join().unwrap());
+        assert_eq!((words.0.load(), words.1.load()), (1, 1));
+    });
+}
+
+#[test]
+fn test_racing_cas2() {
+    loom::model(|| {
+        let words = words();
+        let threads: Vec<_> = (1..=2)
+            .map(|new| {
+                let words = words.clone();
+                thread::spawn(move || unsafe { cas2(&words.0, &words.1, 0, 0, new, new) })
+            })
+            .collect();
+        let succeeded: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        // exactly one wins, and both words hold its values
+        assert_eq!(succeeded.iter().filter(|&&s| s).count(), 1);
+        let winner = if succeeded[0] { 1 } else { 2 };
+        assert_eq!((words.0.load(), words.1.load()), (winner, winner));
+    });
+}
+
+#[test]
+fn test_load_helps_failing_cas2() {
+    loom::model(|| {
+        let words = Arc::new((Atomic::new(0), Atomic::new(5), Atomic::new(0)));
+        let writer = {
+            let words = words.clone();
+            thread::spawn(move || unsafe { cas2(&words.0, &words.1, 0, 0, 1, 1) })
+        };
+        // a helper that decides the failure puts the old value back
+        assert_eq!(words.0.load(), 0);
+        assert!(!writer.join().unwrap());
+        assert_eq!((words.0.load(), words.1.load()), (0, 5));
+    });
+}
+
+#[test]
+fn test_overlapping_cas2() {
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+    builder.check(|| {
+        let words = words();
+        let left = {
+            let words = words.clone();
+            thread::spawn(move || unsafe { cas2(&words.0, &words.1, 0, 0, 1, 1) })
+        };
+        let right = {
+            let words = words.clone();
+            thread::spawn(move || unsafe { cas2(&words.1, &words.2, 0, 0, 2, 2) })
+        };
+        let middle = words.1.load();
+        let (left, right) = (left.join().unwrap(), right.join().unwrap());
+        // they share the middle word, so only one of them can win
+        assert!(left != right);
+        let end = if left { (1, 1, 0) } else { (0, 2, 2) };
+        assert!(middle == 0 || middle == end.1);
+        assert_eq!((words.0.load(), words.1.load(), words.2.load()), end);
+    });
+}
+
+#[test]
+fn test_descriptor_reuse() {
+    // the writer reuses its descriptor for the second cas2 while the reader
+    // may still be helping the first one through a stale pointer
+    let mut builder = loom::model::Builder::new();
+    builder.preemption_bound = Some(3);
+    builder.check(|| {
+        let words = words();
+        let writer = {
+            let words = words.clone();
+            thread::spawn(move || unsafe {
+                assert!(cas2(&words.0, &words.1, 0, 0, 1, 1));
+                assert!(cas2(&words.0, &words.1, 1, 1, 2, 2));
+            })
+        };
+        let second = words.1.load();
+        let first = words.0.load();
+        assert!(second <= first && first <= 2);
+        writer.join().unwrap();
+        assert_eq!((words.0.load(), words.1.load()), (2, 2));
+    });
+}
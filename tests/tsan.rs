@@ -0,0 +1,76 @@
+//! Plain memory published through `cas2` and `cas_n`, the reports ThreadSanitizer
+//! would give for it are the false positives `--cfg tsan` is for. Runs without
+//! the sanitizer as well, under it with
+//! `RUSTFLAGS="-Zsanitizer=thread --cfg tsan" cargo +nightly test -Zbuild-std
+//! --target x86_64-unknown-linux-gnu --test tsan`.
+
+use mw_cas::{cas2, cas_n, Atomic};
+use std::{ptr, sync::Mutex, thread};
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 500;
+
+type Payload = [usize; 8];
+
+#[test]
+fn test_published_payloads() {
+    let first = Atomic::new(ptr::null_mut::<Payload>());
+    let second = Atomic::new(ptr::null_mut::<Payload>());
+    // addresses of the replaced payloads, freed at the end since readers may
+    // still hold any of them until then
+    let retired = Mutex::new(Vec::new());
+    thread::scope(|s| {
+        for t in 0..THREADS {
+            let (first, second, retired) = (&first, &second, &retired);
+            s.spawn(move || {
+                for round in 0..ROUNDS {
+                    let (old0, old1) = (first.load(), second.load());
+                    for old in [old0, old1] {
+                        if !old.is_null() {
+                            // written before it was published
+                            let payload = unsafe { *old };
+                            assert!(payload.iter().all(|&v| v == payload[0]));
+                        }
+                    }
+                    let value = t * ROUNDS + round;
+                    let new0 = Box::into_raw(Box::new([value; 8]));
+                    let new1 = Box::into_raw(Box::new([value; 8]));
+                    if unsafe { cas2(first, second, old0, old1, new0, new1) } {
+                        let old = [old0, old1];
+                        let old = old.iter().filter(|p| !p.is_null());
+                        retired.lock().unwrap().extend(old.map(|&p| p as usize));
+                    } else {
+                        unsafe { drop((Box::from_raw(new0), Box::from_raw(new1))) };
+                    }
+                }
+            });
+        }
+    });
+    let retired = retired.into_inner().unwrap();
+    let current = [first.load() as usize, second.load() as usize];
+    for payload in retired.into_iter().chain(current.iter().copied()) {
+        unsafe { drop(Box::from_raw(payload as *mut Payload)) };
+    }
+}
+
+#[test]
+fn test_contended_cas_n() {
+    let cells: Vec<_> = (0..4).map(|_| Atomic::new(0usize)).collect();
+    let addresses: Vec<_> = cells.iter().collect();
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| {
+                for _ in 0..ROUNDS {
+                    loop {
+                        let expected: Vec<_> = cells.iter().map(Atomic::load).collect();
+                        let new: Vec<_> = expected.iter().map(|v| v + 1).collect();
+                        if unsafe { cas_n(&addresses, &expected, &new) } {
+                            break;
+                        }
+                    }
+                }
+            });
+        }
+    });
+    assert!(cells.iter().all(|cell| cell.load() == THREADS * ROUNDS));
+}